@@ -0,0 +1,269 @@
+// run with `cargo bench --bench vm`, timed with std only.
+// add `--features nan-boxing` to compare with the 8-byte values
+use std::cell::Cell;
+use std::io::Write;
+use std::mem::size_of;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use tinyx::bytecode::register::{self, vm::RegisterVm, Module};
+use tinyx::bytecode::{compiler::compile, vm::Vm, Chunk, OpCode, Pos, PosRun, Value};
+use tinyx::lexer::Lexer;
+use tinyx::parser::parser::Parser;
+
+const RUNS: usize = 20;
+
+const FIB: &str = r#"
+fn fib(n) {
+    if (n < 2) { return n }
+    return fib(n - 1) + fib(n - 2)
+}
+let result = fib(25)
+"#;
+
+const LOOP: &str = r#"
+let sum = 0
+let i = 0
+while (i < 1000000) {
+    sum = sum + i * 2 - 1
+    i = i + 1
+}
+"#;
+
+// the same loop on locals, which the register vm reads in place
+const LOCAL_LOOP: &str = r#"
+{
+    let sum = 0
+    let i = 0
+    while (i < 1000000) {
+        sum = sum + i * 2 - 1
+        i = i + 1
+    }
+}
+"#;
+
+const METHODS: &str = r#"
+class Counter {
+    init() { this.count = 0 }
+    add(n) {
+        this.count = this.count + n
+        return this
+    }
+}
+let counter = Counter()
+let i = 0
+while (i < 200000) {
+    counter.add(1).add(2)
+    i = i + 1
+}
+"#;
+
+// method calls on instances with several fields, inherited methods included
+const INVOKE: &str = r#"
+class Shape {
+    area() { return this.width * this.height }
+    grow(n) {
+        this.width = this.width + n
+        this.height = this.height + n
+    }
+}
+class Box extends Shape {
+    init(width, height, depth) {
+        this.width = width
+        this.height = height
+        this.depth = depth
+    }
+    volume() { return this.area() * this.depth }
+}
+let box = Box(1, 2, 3)
+let total = 0
+let i = 0
+while (i < 100000) {
+    box.grow(0)
+    total = total + box.volume() + box.area()
+    i = i + 1
+}
+"#;
+
+const CLOSURES: &str = r#"
+fn make(n) {
+    let total = 0
+    fn add(x) {
+        total = total + x + n
+        return total
+    }
+    return add
+}
+let add = make(1)
+let i = 0
+while (i < 300000) {
+    add(i)
+    i = i + 1
+}
+"#;
+
+// a long loop body: the code no longer fits in the cpu caches as enums
+fn straight_line() -> String {
+    let body = "a = a + b * 2\nb = b - a / 4\n".repeat(1500);
+    format!(
+        "{{\nlet a = 1\nlet b = 2\nlet i = 0\nwhile (i < 200) {{\n{}i = i + 1\n}}\n}}",
+        body
+    )
+}
+
+fn compile_source(source: &str) -> Chunk {
+    let lexer = Lexer::new(source.as_bytes(), "bench.tx");
+    let ast = Parser::new(lexer).parse().expect("parse error");
+    compile(&ast).expect("compile error")
+}
+
+fn compile_registers(source: &str) -> Module {
+    let lexer = Lexer::new(source.as_bytes(), "bench.tx");
+    let ast = Parser::new(lexer).parse().expect("parse error");
+    register::compiler::compile(&ast).expect("compile error")
+}
+
+// counts the executed instructions of a trace: their lines start with the offset
+#[derive(Clone, Default)]
+struct Executed(Rc<Cell<u64>>, Rc<Cell<bool>>);
+
+impl Write for Executed {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut line_start = !self.1.get();
+        for &byte in buf {
+            if line_start && byte.is_ascii_digit() {
+                self.0.set(self.0.get() + 1);
+            }
+            line_start = byte == b'\n';
+        }
+        self.1.set(!line_start);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// (code bytes, position bytes, instructions), nested functions included
+fn footprint(chunk: &Chunk) -> (usize, usize, usize) {
+    let mut total = (
+        chunk.code.len(),
+        chunk.positions.len() * size_of::<PosRun>(),
+        chunk.instructions().count(),
+    );
+    for constant in chunk.constants.iter() {
+        if let Some(function) = constant.as_function() {
+            let (code, positions, count) = footprint(&function.chunk);
+            total = (total.0 + code, total.1 + positions, total.2 + count);
+        }
+    }
+    total
+}
+
+// fastest run of the vm on the compiled program, compilation excluded.
+// the minimum is the least sensitive to noise from other processes
+fn best(source: &str, inline_caches: bool) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let mut vm = Vm::new(compile_source(source)).with_inline_caches(inline_caches);
+            let start = Instant::now();
+            vm.run().expect("runtime error");
+            start.elapsed()
+        })
+        .min()
+        .unwrap_or(Duration::ZERO)
+}
+
+fn bench(name: &str, source: &str) {
+    let best = best(source, true);
+    // what the same instructions took as one enum and one position each
+    let (code, positions, count) = footprint(&compile_source(source));
+    println!(
+        "{:<10} {:>10.2?} (best of {})  code {} bytes, positions {} bytes, unencoded {} + {} bytes",
+        name,
+        best,
+        RUNS,
+        code,
+        positions,
+        count * size_of::<OpCode>(),
+        count * size_of::<Pos>()
+    );
+}
+
+// property accesses resolved by name on every run, for comparison
+fn bench_caches(name: &str, source: &str) {
+    let (cached, uncached) = (best(source, true), best(source, false));
+    println!(
+        "{:<10} {:>10.2?} with inline caches, {:.2?} without ({:.2}x)",
+        name,
+        cached,
+        uncached,
+        uncached.as_secs_f64() / cached.as_secs_f64()
+    );
+}
+
+fn best_registers(source: &str) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let mut vm = RegisterVm::new(compile_registers(source));
+            let start = Instant::now();
+            vm.run().expect("runtime error");
+            start.elapsed()
+        })
+        .min()
+        .unwrap_or(Duration::ZERO)
+}
+
+// the same program on the stack vm and on the register vm:
+// time, instructions in the code, and instructions executed
+fn bench_registers(name: &str, source: &str) {
+    let (stack, registers) = (best(source, true), best_registers(source));
+    let (stack_code, register_code) = (
+        footprint(&compile_source(source)).2,
+        compile_registers(source).instruction_count(),
+    );
+    let (executed, register_executed) = (Executed::default(), Executed::default());
+    Vm::new(compile_source(source))
+        .with_trace(executed.clone())
+        .run()
+        .expect("runtime error");
+    RegisterVm::new(compile_registers(source))
+        .with_trace(register_executed.clone())
+        .run()
+        .expect("runtime error");
+    println!(
+        "{:<10} {:>10.2?} on registers, {:.2?} on the stack ({:.2}x)  \
+         instructions {} / {}, executed {} / {}",
+        name,
+        registers,
+        stack,
+        stack.as_secs_f64() / registers.as_secs_f64(),
+        register_code,
+        stack_code,
+        register_executed.0.get(),
+        executed.0.get()
+    );
+}
+
+fn main() {
+    let values = match cfg!(feature = "nan-boxing") {
+        true => "nan-boxed",
+        false => "tagged enum",
+    };
+    println!("values: {}, {} bytes", values, size_of::<Value>());
+    bench("fib", FIB);
+    bench("loop", LOOP);
+    bench("methods", METHODS);
+    bench("closures", CLOSURES);
+    bench("large", &straight_line());
+    bench("invoke", INVOKE);
+
+    bench_caches("methods", METHODS);
+    bench_caches("invoke", INVOKE);
+
+    bench_registers("fib", FIB);
+    bench_registers("loop", LOOP);
+    bench_registers("locals", LOCAL_LOOP);
+    bench_registers("large", &straight_line());
+}
@@ -30,15 +30,33 @@ pub enum ClassType {
     SubClass,
 }
 
+/**
+ * receives the scope distance of every resolved local variable.
+ * the bytecode compiler only needs the checks, it uses `()`
+ */
+pub trait Locals {
+    fn resolve(&mut self, ident: &Identifier, depth: usize);
+}
+
+impl Locals for Interpreter {
+    fn resolve(&mut self, ident: &Identifier, depth: usize) {
+        Interpreter::resolve(self, ident, depth)
+    }
+}
+
+impl Locals for () {
+    fn resolve(&mut self, _ident: &Identifier, _depth: usize) {}
+}
+
 pub struct Resolver<'a> {
-    interpreter: &'a mut Interpreter,
+    interpreter: &'a mut dyn Locals,
     scopes: Vec<HashMap<String, IdentState>>,
     fn_type: FnType,
     class_type: ClassType,
 }
 
 impl<'a> Resolver<'a> {
-    pub fn new(interpreter: &'a mut Interpreter) -> Self {
+    pub fn new(interpreter: &'a mut dyn Locals) -> Self {
         Self {
             interpreter,
             scopes: vec![],
@@ -108,8 +126,10 @@ impl<'a> Resolver<'a> {
 
     fn resolve_local(&mut self, ident: &Identifier) {
         for (i, scope) in self.scopes.iter().rev().enumerate() {
+            // the innermost declaration shadows the outer ones
             if scope.contains_key(&ident.name) {
                 self.interpreter.resolve(ident, i);
+                return;
             }
         }
     }
@@ -209,6 +229,31 @@ impl<'a> StmtVisitor for Resolver<'a> {
         Ok(())
     }
 
+    fn visit_throw_stmt(&mut self, stmt: &ThrowStatement) -> Self::Item {
+        self.resolve_expr(&stmt.argument)
+    }
+
+    fn visit_try_stmt(&mut self, stmt: &TryStatement) -> Self::Item {
+        let TryStatement {
+            block,
+            handler,
+            finalizer,
+        } = stmt;
+        self.visit_block(block)?;
+        if let Some(CatchClause { param, body }) = handler {
+            // the exception lives in a scope of its own around the body
+            self.begin_scope();
+            self.declare(param)?;
+            self.define(param);
+            self.visit_block(body)?;
+            self.end_scope();
+        }
+        if let Some(block) = finalizer {
+            self.visit_block(block)?;
+        }
+        Ok(())
+    }
+
     fn visit_class_declare(&mut self, class: &ClassDeclaration) -> Self::Item {
         let prev = self.class_type.clone();
         self.class_type = ClassType::Class;
@@ -376,4 +421,11 @@ impl<'a> ExprVisitor for Resolver<'a> {
     fn visit_null(&mut self) -> Self::Item {
         Ok(())
     }
+
+    fn visit_array(&mut self, lit: &ArrayLiteral) -> Self::Item {
+        for ele in lit.elements.iter() {
+            self.resolve_expr(ele)?;
+        }
+        Ok(())
+    }
 }
@@ -45,6 +45,8 @@ impl Display for Statement {
             }
             Statement::While(s) => write!(f, "{}", s),
             Statement::ClassDeclaration(class) => write!(f, "{}", class),
+            Statement::Throw(throw) => write!(f, "Throw: {}", throw.argument),
+            Statement::Try(t) => write!(f, "{}", t),
         }
     }
 }
@@ -153,6 +155,7 @@ impl Display for Expr {
             Expr::Assign(a) => write!(f, "{}", a),
             Expr::Call(c) => write!(f, "{}", c),
             Expr::NullLiteral => write!(f, "null"),
+            Expr::ArrayLiteral(a) => write!(f, "{}", a),
             Expr::Logical(l) => write!(f, "{}", l),
             Expr::Get(m) => write!(f, "{}", m),
             Expr::Set(s) => write!(f, "{}", s),
@@ -272,6 +275,21 @@ impl Display for NumericLiteral {
     }
 }
 
+impl Display for ArrayLiteral {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Array: ")?;
+        write!(f, "[ ")?;
+        for (i, ele) in self.elements.iter().enumerate() {
+            if i == self.elements.len() - 1 {
+                write!(f, "{}", ele)?;
+            } else {
+                write!(f, "{}, ", ele)?;
+            }
+        }
+        write!(f, " ]")
+    }
+}
+
 impl Display for ThisExpr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -290,3 +308,23 @@ impl Display for SuperExpr {
         )
     }
 }
+
+impl Display for TryStatement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Try: ")?;
+        write!(f, "{{ ")?;
+        write!(f, "block: {}", Statement::Block(self.block.clone()))?;
+        if let Some(handler) = &self.handler {
+            write!(
+                f,
+                ", catch: {} {}",
+                handler.param,
+                Statement::Block(handler.body.clone())
+            )?;
+        }
+        if let Some(finalizer) = &self.finalizer {
+            write!(f, ", finally: {}", Statement::Block(finalizer.clone()))?;
+        }
+        write!(f, " }}")
+    }
+}
@@ -9,6 +9,7 @@ pub enum Expr {
     StringLiteral(StringLiteral),
     BooleanLiteral(bool),
     NullLiteral,
+    ArrayLiteral(ArrayLiteral),
     Binary(BinaryExpr),
     Logical(LogicalExpr),
     Unary(UnaryExpr),
@@ -45,6 +46,18 @@ impl NumericLiteral {
     }
 }
 
+#[derive(Debug, PartialEq, Clone)]
+pub struct ArrayLiteral {
+    pub elements: Vec<Expr>,
+    pub span: Span,
+}
+
+impl ArrayLiteral {
+    pub fn new(elements: Vec<Expr>, span: Span) -> Self {
+        Self { elements, span }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct BinaryExpr {
     pub left: Box<Expr>,
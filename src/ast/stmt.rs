@@ -1,4 +1,5 @@
 use super::expr::{Expr, Identifier};
+use crate::position::Span;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Statement {
@@ -12,6 +13,8 @@ pub enum Statement {
     VariableDeclaration(VariableDeclaration),
     FunctionDeclaration(FunctionDeclaration),
     ClassDeclaration(ClassDeclaration),
+    Throw(ThrowStatement),
+    Try(TryStatement),
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -97,3 +100,48 @@ impl ClassDeclaration {
         Self { id, super_id, body }
     }
 }
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ThrowStatement {
+    pub argument: Expr,
+    pub span: Span, // the `throw` keyword
+}
+
+impl ThrowStatement {
+    pub fn new(argument: Expr, span: Span) -> Self {
+        Self { argument, span }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct TryStatement {
+    pub block: Vec<Statement>,
+    pub handler: Option<CatchClause>,
+    pub finalizer: Option<Vec<Statement>>,
+}
+
+impl TryStatement {
+    pub fn new(
+        block: Vec<Statement>,
+        handler: Option<CatchClause>,
+        finalizer: Option<Vec<Statement>>,
+    ) -> Self {
+        Self {
+            block,
+            handler,
+            finalizer,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct CatchClause {
+    pub param: Identifier,
+    pub body: Vec<Statement>,
+}
+
+impl CatchClause {
+    pub fn new(param: Identifier, body: Vec<Statement>) -> Self {
+        Self { param, body }
+    }
+}
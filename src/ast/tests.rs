@@ -9,7 +9,7 @@ use crate::{
     position::{Loc, Pos, Span},
 };
 
-use super::Identifier;
+use super::{Identifier, Statement};
 
 #[test]
 fn test_parse_ast() {
@@ -19,7 +19,7 @@ fn test_parse_ast() {
     buf_reader.read_to_string(&mut contents).unwrap();
 
     println!("\n-------- AST START ----------\n");
-    let lexer = Lexer::new(&contents.as_bytes(), "ast_test.txt");
+    let lexer = Lexer::new(contents.as_bytes(), "ast_test.txt");
     let mut parser = Parser::new(lexer);
     let ast = parser.parse().unwrap();
     println!("{}", ast);
@@ -37,3 +37,29 @@ fn test_identifier_tostring() {
     );
     assert_eq!(ident.to_string(), String::from("abc@1:2"))
 }
+
+#[test]
+fn test_parse_try() {
+    let source = "try { throw 1 } catch (e) { print e } finally { print 2 }";
+    let ast = Parser::new(Lexer::new(source.as_bytes(), "test.txt"))
+        .parse()
+        .unwrap();
+    let Statement::Try(stmt) = &ast.body[0] else {
+        panic!("expect a try statement, found {}", ast.body[0]);
+    };
+    assert!(matches!(stmt.block[0], Statement::Throw(_)));
+    assert_eq!(stmt.handler.as_ref().unwrap().param.name, "e");
+    assert_eq!(stmt.finalizer.as_ref().unwrap().len(), 1);
+
+    let source = "try { throw 1 } finally {}";
+    let ast = Parser::new(Lexer::new(source.as_bytes(), "test.txt"))
+        .parse()
+        .unwrap();
+    assert!(matches!(&ast.body[0], Statement::Try(stmt) if stmt.handler.is_none()));
+
+    // a try needs a catch or a finally
+    let source = "try { throw 1 }\nprint 2";
+    assert!(Parser::new(Lexer::new(source.as_bytes(), "test.txt"))
+        .parse()
+        .is_err());
+}
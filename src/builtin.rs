@@ -0,0 +1,271 @@
+// builtins shared by the tree-walking interpreter and the bytecode vm.
+// a builtin is written once against `HostValue`, both runtimes call it
+// with their own values, without converting them
+use std::{
+    rc::Rc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{bytecode, value};
+
+/**
+ * the values of a runtime, as seen by a builtin: scalars only,
+ * objects of the runtime are opaque
+ */
+pub trait HostValue: Sized {
+    fn null() -> Self;
+    fn from_bool(b: bool) -> Self;
+    fn from_number(n: f64) -> Self;
+    fn from_str(s: &str) -> Self;
+    fn as_bool(&self) -> Option<bool>;
+    fn as_number(&self) -> Option<f64>;
+    fn as_str(&self) -> Option<&str>;
+    fn type_name(&self) -> &'static str;
+}
+
+/**
+ * function implemented in rust, callable from both runtimes.
+ * the arguments are checked against `arity` before `call`,
+ * an error is reported at the call site
+ */
+pub trait Builtin: 'static {
+    fn name(&self) -> &str;
+
+    // minimum and maximum number of arguments
+    fn arity(&self) -> (usize, usize);
+
+    fn call<V: HostValue>(&self, args: &[V]) -> Result<V, String>;
+}
+
+/**
+ * a `Builtin` as a trait object, instantiated for each runtime.
+ * an `Rc<dyn HostFunction>` can be defined in an interpreter and in a vm
+ */
+pub trait HostFunction {
+    fn name(&self) -> &str;
+
+    fn arity(&self) -> (usize, usize);
+
+    fn call_interpreter(&self, args: &[value::Value]) -> Result<value::Value, String>;
+
+    fn call_vm(&self, args: &[bytecode::Value]) -> Result<bytecode::Value, String>;
+}
+
+impl<B: Builtin> HostFunction for B {
+    fn name(&self) -> &str {
+        Builtin::name(self)
+    }
+
+    fn arity(&self) -> (usize, usize) {
+        Builtin::arity(self)
+    }
+
+    fn call_interpreter(&self, args: &[value::Value]) -> Result<value::Value, String> {
+        self.call(args)
+    }
+
+    fn call_vm(&self, args: &[bytecode::Value]) -> Result<bytecode::Value, String> {
+        self.call(args)
+    }
+}
+
+impl std::fmt::Debug for dyn HostFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn {}>", self.name())
+    }
+}
+
+/**
+ * message when `argc` arguments don't fit the arity of `function`
+ */
+pub fn check_arity(function: &dyn HostFunction, argc: usize) -> Result<(), String> {
+    let (min, max) = function.arity();
+    if (min..=max).contains(&argc) {
+        return Ok(());
+    }
+    let expected = if min == max {
+        min.to_string()
+    } else {
+        format!("{} to {}", min, max)
+    };
+    Err(format!(
+        "{} expected {} arguments but got {}",
+        function.name(),
+        expected,
+        argc
+    ))
+}
+
+/**
+ * builtins defined in every interpreter and every vm
+ */
+pub fn builtins() -> Vec<Rc<dyn HostFunction>> {
+    vec![Rc::new(Clock)]
+}
+
+pub fn expect_number<V: HostValue>(name: &str, value: &V) -> Result<f64, String> {
+    value
+        .as_number()
+        .ok_or_else(|| type_error(name, "number", value))
+}
+
+pub fn expect_string<'a, V: HostValue>(name: &str, value: &'a V) -> Result<&'a str, String> {
+    value
+        .as_str()
+        .ok_or_else(|| type_error(name, "string", value))
+}
+
+fn type_error<V: HostValue>(name: &str, expect: &str, value: &V) -> String {
+    format!("{}: expected {}, found {}", name, expect, value.type_name())
+}
+
+// seconds since unix epoch
+struct Clock;
+
+impl Builtin for Clock {
+    fn name(&self) -> &str {
+        "clock"
+    }
+
+    fn arity(&self) -> (usize, usize) {
+        (0, 0)
+    }
+
+    fn call<V: HostValue>(&self, _: &[V]) -> Result<V, String> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs_f64())
+            .unwrap_or(0.0);
+        Ok(V::from_number(now))
+    }
+}
+
+impl HostValue for value::Value {
+    fn null() -> Self {
+        value::Value::Null
+    }
+
+    fn from_bool(b: bool) -> Self {
+        value::Value::Boolean(b)
+    }
+
+    fn from_number(n: f64) -> Self {
+        value::Value::Number(n)
+    }
+
+    fn from_str(s: &str) -> Self {
+        value::Value::String(s.to_string())
+    }
+
+    fn as_bool(&self) -> Option<bool> {
+        match self {
+            value::Value::Boolean(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    fn as_number(&self) -> Option<f64> {
+        match self {
+            value::Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            value::Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn type_name(&self) -> &'static str {
+        value::Value::type_name(self)
+    }
+}
+
+impl HostValue for bytecode::Value {
+    fn null() -> Self {
+        bytecode::Value::NULL
+    }
+
+    fn from_bool(b: bool) -> Self {
+        bytecode::Value::boolean(b)
+    }
+
+    fn from_number(n: f64) -> Self {
+        bytecode::Value::number(n)
+    }
+
+    fn from_str(s: &str) -> Self {
+        bytecode::Value::string(s.into())
+    }
+
+    fn as_bool(&self) -> Option<bool> {
+        match self.unpack() {
+            bytecode::Unpacked::Boolean(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    fn as_number(&self) -> Option<f64> {
+        bytecode::Value::as_number(self)
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        self.as_string().map(|s| &**s)
+    }
+
+    fn type_name(&self) -> &'static str {
+        bytecode::Value::type_name(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Add;
+
+    impl Builtin for Add {
+        fn name(&self) -> &str {
+            "add"
+        }
+
+        fn arity(&self) -> (usize, usize) {
+            (2, 2)
+        }
+
+        fn call<V: HostValue>(&self, args: &[V]) -> Result<V, String> {
+            let x = expect_number("add", &args[0])?;
+            let y = expect_number("add", &args[1])?;
+            Ok(V::from_number(x + y))
+        }
+    }
+
+    #[test]
+    fn both_runtimes() {
+        let add: Rc<dyn HostFunction> = Rc::new(Add);
+        let tree = add.call_interpreter(&[value::Value::Number(1.0), value::Value::Number(2.0)]);
+        assert_eq!(tree, Ok(value::Value::Number(3.0)));
+        let vm = add.call_vm(&[1.into(), 2.into()]);
+        assert_eq!(vm, Ok(bytecode::Value::number(3.0)));
+
+        assert_eq!(
+            add.call_vm(&[1.into(), "2".into()]),
+            Err("add: expected number, found string".to_string())
+        );
+    }
+
+    #[test]
+    fn arity() {
+        assert_eq!(check_arity(&Add, 2), Ok(()));
+        assert_eq!(
+            check_arity(&Add, 1),
+            Err("add expected 2 arguments but got 1".to_string())
+        );
+        assert_eq!(
+            check_arity(&Clock, 1),
+            Err("clock expected 0 arguments but got 1".to_string())
+        );
+    }
+}
@@ -0,0 +1,813 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::error::AssembleError;
+
+use super::{
+    object::{Function, UpvalueDesc},
+    opcode::{
+        CacheIndex, ConstantIndex, JumpOffset, MAX_CACHES, MAX_CONSTANTS, MAX_JUMP,
+        MAX_SHORT_CONSTANTS, MAX_SLOTS,
+    },
+    Chunk, Handler, OpCode,
+    OpCode::*,
+    Value,
+};
+
+type Result<T> = std::result::Result<T, AssembleError>;
+
+/**
+ * parse a textual listing into a chunk, the inverse of `debug::write_chunk`.
+ *
+ *  == name ==                      the script, can be omitted
+ *  == name (arity 1) ==            a function, listed in the order of the function
+ *                                  constants, nested functions after their parent
+ *  loop:                           a label, jumps of the same chunk can target it
+ *  0002  <1:5> OP_GET_GLOBAL    1 '"a"'
+ *  OP_GET_GLOBAL a                 offset, position and constant index are optional
+ *  OP_CONSTANT 1.2
+ *  OP_JUMP_IF_FALSE 0004 -> 0012   or a label: OP_LOOP loop
+ *  OP_CLOSURE 3 '<fn f>'           followed by its upvalues: | local 1
+ *  OP_INVOKE (1 args) add
+ *  try 0002..0010 -> 0014 (depth 1)   an exception handler, its offsets can be labels
+ *
+ * instructions without position are located at their line in the listing,
+ * lines starting with `;` are comments
+ */
+pub fn assemble(source: &str) -> Result<Chunk> {
+    let mut sections: Vec<Section> = vec![];
+    for (i, text) in source.lines().enumerate() {
+        let line = i + 1;
+        let text = text.trim();
+        if text.is_empty() || text.starts_with(';') {
+            continue;
+        }
+        if let Some(header) = text.strip_prefix("==").and_then(|t| t.strip_suffix("==")) {
+            if let Some(section) = sections.last_mut() {
+                section.finish()?;
+            }
+            sections.push(Section::header(header.trim(), line)?);
+            continue;
+        }
+
+        if sections.is_empty() {
+            sections.push(Section::new("script".to_string(), None, line));
+        }
+        let section = sections.last_mut().unwrap();
+        if let Some(upvalue) = text.strip_prefix('|') {
+            section.upvalue(upvalue.trim(), line)?;
+            continue;
+        }
+        section.flush_closure(line)?;
+        if let Some(handler) = text.strip_prefix("try ") {
+            section.handler(Cursor::new(handler, line))?;
+            continue;
+        }
+        match text.strip_suffix(':') {
+            Some(label) if is_ident(label) => section.label(label, line)?,
+            _ => section.instruction(Cursor::new(text, line))?,
+        }
+    }
+    if let Some(section) = sections.last_mut() {
+        section.finish()?;
+    }
+
+    // function constants take the following chunks, depth first
+    let mut sections = sections.into_iter();
+    let chunk = match sections.next() {
+        Some(script) => build(&mut sections, script)?,
+        None => Chunk::new(),
+    };
+    if let Some(extra) = sections.next() {
+        return Err(AssembleError::new(
+            format!("chunk {} is not a function constant", extra.name),
+            extra.line,
+        ));
+    }
+    Ok(chunk)
+}
+
+fn build(sections: &mut std::vec::IntoIter<Section>, section: Section) -> Result<Chunk> {
+    let Section {
+        mut chunk,
+        constants,
+        mut upvalues,
+        ..
+    } = section;
+    for (idx, constant) in constants.into_iter().enumerate() {
+        let value = match constant {
+            // a hole in the constant pool, never referenced
+            None => Value::NULL,
+            Some((Constant::Value(value), _)) => value,
+            Some((Constant::Function(name), line)) => {
+                let nested = match sections.next() {
+                    Some(nested) if nested.name == name && nested.arity.is_some() => nested,
+                    Some(nested) => {
+                        return Err(AssembleError::new(
+                            format!("expected the chunk of <fn {}>, found {}", name, nested.name),
+                            nested.line,
+                        ))
+                    }
+                    None => {
+                        return Err(AssembleError::new(
+                            format!("missing the chunk of <fn {}>", name),
+                            line,
+                        ))
+                    }
+                };
+                let arity = nested.arity.unwrap_or(0);
+                Value::function(Rc::new(Function {
+                    name: Some(name),
+                    arity,
+                    upvalues: upvalues.remove(&idx).unwrap_or_default(),
+                    chunk: build(sections, nested)?,
+                }))
+            }
+        };
+        chunk.constants.push(value);
+    }
+    Ok(chunk)
+}
+
+#[derive(PartialEq)]
+enum Constant {
+    Value(Value),
+    // the function defined by a later chunk
+    Function(String),
+}
+
+// how an instruction reads its operands
+enum Operand {
+    None(OpCode),
+    Constant(fn(ConstantIndex) -> OpCode),
+    Name(fn(ConstantIndex) -> OpCode),
+    // a name and a fresh inline cache
+    Property(fn(ConstantIndex, CacheIndex) -> OpCode),
+    Closure,
+    Byte(fn(usize) -> OpCode),
+    Jump(fn(JumpOffset) -> OpCode),
+    Loop,
+    Invoke,
+}
+
+fn operand(name: &str) -> Option<Operand> {
+    Some(match name {
+        "OP_RETURN" => Operand::None(OpReturn),
+        "OP_CONSTANT" => Operand::Constant(OpConstant),
+        "OP_CONSTANT_LONG" => Operand::Constant(OpConstantLong),
+        "OP_NULL" => Operand::None(OpNull),
+        "OP_TRUE" => Operand::None(OpTrue),
+        "OP_FALSE" => Operand::None(OpFalse),
+        "OP_NEGATE" => Operand::None(OpNegate),
+        "OP_NOT" => Operand::None(OpNot),
+        "OP_ADD" => Operand::None(OpAdd),
+        "OP_SUBTRACT" => Operand::None(OpSubtract),
+        "OP_MULTIPLY" => Operand::None(OpMultiply),
+        "OP_DIVIDE" => Operand::None(OpDivide),
+        "OP_EQUAL" => Operand::None(OpEqual),
+        "OP_NOT_EQUAL" => Operand::None(OpNotEqual),
+        "OP_GREATER" => Operand::None(OpGreater),
+        "OP_GREATER_EQUAL" => Operand::None(OpGreaterEqual),
+        "OP_LESS" => Operand::None(OpLess),
+        "OP_LESS_EQUAL" => Operand::None(OpLessEqual),
+        "OP_PRINT" => Operand::None(OpPrint),
+        "OP_POP" => Operand::None(OpPop),
+        "OP_DEFINE_GLOBAL" => Operand::Name(OpDefineGlobal),
+        "OP_GET_GLOBAL" => Operand::Name(OpGetGlobal),
+        "OP_SET_GLOBAL" => Operand::Name(OpSetGlobal),
+        "OP_GET_LOCAL" => Operand::Byte(OpGetLocal),
+        "OP_SET_LOCAL" => Operand::Byte(OpSetLocal),
+        "OP_JUMP" => Operand::Jump(OpJump),
+        "OP_JUMP_IF_FALSE" => Operand::Jump(OpJumpIfFalse),
+        "OP_LOOP" => Operand::Loop,
+        "OP_CALL" => Operand::Byte(OpCall),
+        "OP_CLOSURE" => Operand::Closure,
+        "OP_GET_UPVALUE" => Operand::Byte(OpGetUpvalue),
+        "OP_SET_UPVALUE" => Operand::Byte(OpSetUpvalue),
+        "OP_CLOSE_UPVALUE" => Operand::None(OpCloseUpvalue),
+        "OP_CLASS" => Operand::Name(OpClass),
+        "OP_INHERIT" => Operand::None(OpInherit),
+        "OP_METHOD" => Operand::Name(OpMethod),
+        "OP_GET_PROPERTY" => Operand::Property(OpGetProperty),
+        "OP_SET_PROPERTY" => Operand::Property(OpSetProperty),
+        "OP_GET_SUPER" => Operand::Name(OpGetSuper),
+        "OP_INVOKE" => Operand::Invoke,
+        "OP_THROW" => Operand::None(OpThrow),
+        "OP_RETHROW" => Operand::Byte(OpRethrow),
+        _ => return None,
+    })
+}
+
+// where a jump goes
+enum Target {
+    Offset(usize),
+    Label(String),
+}
+
+// one `== name ==` chunk of the listing
+struct Section {
+    name: String,
+    // None for the script
+    arity: Option<usize>,
+    line: usize,
+    chunk: Chunk,
+    // the constant pool, with the line defining each constant
+    constants: Vec<Option<(Constant, usize)>>,
+    upvalues: HashMap<ConstantIndex, Vec<UpvalueDesc>>,
+    // the closure whose upvalue lines are being read
+    closure: Option<(ConstantIndex, Vec<UpvalueDesc>)>,
+    labels: HashMap<String, usize>,
+    // jumps to labels, patched at the end of the chunk
+    pending: Vec<(usize, String, usize)>,
+    // start, end and target of the exception handlers, with their depth and line
+    handlers: Vec<([Target; 3], usize, usize)>,
+}
+
+impl Section {
+    fn new(name: String, arity: Option<usize>, line: usize) -> Self {
+        Self {
+            name,
+            arity,
+            line,
+            chunk: Chunk::new(),
+            constants: vec![],
+            upvalues: HashMap::new(),
+            closure: None,
+            labels: HashMap::new(),
+            pending: vec![],
+            handlers: vec![],
+        }
+    }
+
+    // `name` or `name (arity 1)`
+    fn header(header: &str, line: usize) -> Result<Self> {
+        let Some(function) = header.strip_suffix(')') else {
+            return Ok(Self::new(header.to_string(), None, line));
+        };
+        let arity = function
+            .rsplit_once(" (arity ")
+            .and_then(|(name, arity)| Some((name, arity.parse().ok()?)));
+        match arity {
+            Some((name, arity)) => Ok(Self::new(name.to_string(), Some(arity), line)),
+            None => Err(AssembleError::new(
+                format!("invalid chunk header '{}'", header),
+                line,
+            )),
+        }
+    }
+
+    fn label(&mut self, label: &str, line: usize) -> Result<()> {
+        let offset = self.chunk.code.len();
+        if self.labels.insert(label.to_string(), offset).is_some() {
+            return Err(AssembleError::new(
+                format!("label {} is already defined", label),
+                line,
+            ));
+        }
+        Ok(())
+    }
+
+    // `local 1` or `upvalue 0`, after an OP_CLOSURE
+    fn upvalue(&mut self, text: &str, line: usize) -> Result<()> {
+        let error = |msg: &str| Err(AssembleError::new(msg.to_string(), line));
+        let Some((_, upvalues)) = &mut self.closure else {
+            return error("upvalue outside of a closure");
+        };
+        let (is_local, index) = match text.split_once(' ') {
+            Some(("local", index)) => (true, index),
+            Some(("upvalue", index)) => (false, index),
+            _ => return error("expected 'local <index>' or 'upvalue <index>'"),
+        };
+        match index.trim().parse() {
+            Ok(index) if index < MAX_SLOTS => upvalues.push(UpvalueDesc { is_local, index }),
+            _ => return error("invalid upvalue index"),
+        }
+        Ok(())
+    }
+
+    // the upvalues of the closure are complete
+    fn flush_closure(&mut self, line: usize) -> Result<()> {
+        let Some((idx, upvalues)) = self.closure.take() else {
+            return Ok(());
+        };
+        match self.upvalues.get(&idx) {
+            Some(existing) if *existing != upvalues => Err(AssembleError::new(
+                format!("conflicting upvalues for constant {}", idx),
+                line,
+            )),
+            Some(_) => Ok(()),
+            None => {
+                self.upvalues.insert(idx, upvalues);
+                Ok(())
+            }
+        }
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.flush_closure(self.line)?;
+        for (at, label, line) in std::mem::take(&mut self.pending) {
+            let Some(&target) = self.labels.get(&label) else {
+                return Err(AssembleError::new(
+                    format!("undefined label {}", label),
+                    line,
+                ));
+            };
+            let (code, _) = self.chunk.read(at);
+            let code = jump(code, at, target, line)?;
+            self.chunk.patch(at, code);
+        }
+        for (targets, depth, line) in std::mem::take(&mut self.handlers) {
+            let mut offsets = [0; 3];
+            for (offset, target) in offsets.iter_mut().zip(targets) {
+                *offset = match target {
+                    Target::Offset(offset) => offset,
+                    Target::Label(label) => match self.labels.get(&label) {
+                        Some(&offset) => offset,
+                        None => {
+                            return Err(AssembleError::new(
+                                format!("undefined label {}", label),
+                                line,
+                            ))
+                        }
+                    },
+                };
+            }
+            let [start, end, target] = offsets;
+            self.chunk.handlers.push(Handler {
+                start,
+                end,
+                target,
+                depth,
+            });
+        }
+        Ok(())
+    }
+
+    // `0002..0010 -> 0014 (depth 1)`, resolved at the end of the chunk
+    fn handler(&mut self, mut cursor: Cursor) -> Result<()> {
+        let range = cursor.word();
+        let Some((start, end)) = range.split_once("..") else {
+            return cursor.error(format!("expected a range, found '{}'", range));
+        };
+        if cursor.word() != "->" {
+            return cursor.error("expected '->' after the range".to_string());
+        }
+        let target = cursor.word();
+        let depth = match (cursor.word(), cursor.word().strip_suffix(')')) {
+            ("(depth", Some(depth)) => depth.parse().ok(),
+            _ => None,
+        };
+        let Some(depth) = depth.filter(|depth| *depth < MAX_SLOTS) else {
+            return cursor.error("expected '(depth <slots>)'".to_string());
+        };
+        cursor.end()?;
+        let targets = [start, end, target].map(|text| match text.parse() {
+            Ok(offset) => Target::Offset(offset),
+            Err(_) => Target::Label(text.to_string()),
+        });
+        self.handlers.push((targets, depth, cursor.line));
+        Ok(())
+    }
+
+    // [offset] [<ln:col>] OP_NAME [operands]
+    fn instruction(&mut self, mut cursor: Cursor) -> Result<()> {
+        let line = cursor.line;
+        let offset = self.chunk.code.len();
+        let mut word = cursor.word();
+        if !word.is_empty() && word.bytes().all(|b| b.is_ascii_digit()) {
+            if word.parse() != Ok(offset) {
+                return cursor.error(format!(
+                    "offset {} doesn't match the code ({:04})",
+                    word, offset
+                ));
+            }
+            word = cursor.word();
+        }
+        let mut pos = (line, 1);
+        if let Some(annotation) = word.strip_prefix('<').and_then(|w| w.strip_suffix('>')) {
+            pos = match annotation
+                .split_once(':')
+                .map(|(l, c)| (l.parse(), c.parse()))
+            {
+                Some((Ok(ln), Ok(col))) => (ln, col),
+                _ => return cursor.error(format!("invalid position {}", word)),
+            };
+            word = cursor.word();
+        }
+
+        let Some(operand) = operand(word) else {
+            return cursor.error(format!("unknown instruction '{}'", word));
+        };
+        let code = match operand {
+            Operand::None(code) => code,
+            Operand::Constant(code) => {
+                let (idx, text) = cursor.constant()?;
+                let value = parse_value(text).ok_or_else(|| {
+                    AssembleError::new(format!("invalid constant {}", text), line)
+                })?;
+                let idx = self.constant(idx, Constant::Value(value), line)?;
+                if code(0) == OpConstant(0) && idx >= MAX_SHORT_CONSTANTS {
+                    return cursor.error(format!("constant {} needs OP_CONSTANT_LONG", idx));
+                }
+                code(idx)
+            }
+            Operand::Name(code) => {
+                let idx = self.name(&mut cursor)?;
+                code(idx)
+            }
+            Operand::Property(code) => {
+                let idx = self.name(&mut cursor)?;
+                code(idx, self.cache(&cursor)?)
+            }
+            Operand::Closure => {
+                let (idx, text) = cursor.constant()?;
+                let name = match text.strip_prefix("<fn ").and_then(|t| t.strip_suffix('>')) {
+                    Some(name) => name,
+                    None if is_ident(text) => text,
+                    None => return cursor.error(format!("expected a function, found {}", text)),
+                };
+                let idx = self.constant(idx, Constant::Function(name.to_string()), line)?;
+                self.closure = Some((idx, vec![]));
+                OpClosure(idx)
+            }
+            Operand::Byte(code) => code(cursor.byte()?),
+            Operand::Invoke => {
+                let argc = cursor.args()?;
+                let idx = self.name(&mut cursor)?;
+                OpInvoke(idx, argc, self.cache(&cursor)?)
+            }
+            Operand::Jump(_) | Operand::Loop => {
+                let placeholder = match operand {
+                    Operand::Jump(code) => code(0),
+                    _ => OpLoop(0),
+                };
+                match cursor.target()? {
+                    Target::Offset(target) => jump(placeholder, offset, target, line)?,
+                    Target::Label(label) => {
+                        self.pending.push((offset, label, line));
+                        placeholder
+                    }
+                }
+            }
+        };
+        cursor.end()?;
+        self.chunk.write(code, pos);
+        Ok(())
+    }
+
+    // property instructions get their inline caches in order
+    fn cache(&mut self, cursor: &Cursor) -> Result<CacheIndex> {
+        if self.chunk.caches.len() >= MAX_CACHES {
+            return cursor.error("too many inline caches".to_string());
+        }
+        Ok(self.chunk.add_cache())
+    }
+
+    // a name operand is a string constant
+    fn name(&mut self, cursor: &mut Cursor) -> Result<ConstantIndex> {
+        let line = cursor.line;
+        let (idx, text) = cursor.constant()?;
+        let name = match text.strip_prefix('"').and_then(|t| t.strip_suffix('"')) {
+            Some(name) => name,
+            None if is_ident(text) => text,
+            None => return cursor.error(format!("expected a name, found {}", text)),
+        };
+        self.constant(idx, Constant::Value(Value::string(name.into())), line)
+    }
+
+    // put the constant at `idx`, or at the end of the pool
+    fn constant(
+        &mut self,
+        idx: Option<ConstantIndex>,
+        constant: Constant,
+        line: usize,
+    ) -> Result<ConstantIndex> {
+        let idx = idx.unwrap_or(self.constants.len());
+        if idx >= MAX_CONSTANTS {
+            return Err(AssembleError::new(
+                format!("constant index {} out of range", idx),
+                line,
+            ));
+        }
+        if self.constants.len() <= idx {
+            self.constants.resize_with(idx + 1, || None);
+        }
+        match &self.constants[idx] {
+            Some((existing, _)) if *existing != constant => Err(AssembleError::new(
+                format!("constant {} is already defined as another value", idx),
+                line,
+            )),
+            Some(_) => Ok(idx),
+            None => {
+                self.constants[idx] = Some((constant, line));
+                Ok(idx)
+            }
+        }
+    }
+}
+
+// the jump at `at` landing on `target`, forward jumps and loops only go one way
+fn jump(code: OpCode, at: usize, target: usize, line: usize) -> Result<OpCode> {
+    let next = at + code.size();
+    let offset = match code {
+        OpLoop(_) => next.checked_sub(target),
+        _ => target.checked_sub(next),
+    };
+    match (code, offset) {
+        (OpJump(_), Some(offset)) if offset <= MAX_JUMP => Ok(OpJump(offset)),
+        (OpJumpIfFalse(_), Some(offset)) if offset <= MAX_JUMP => Ok(OpJumpIfFalse(offset)),
+        (OpLoop(_), Some(offset)) if offset <= MAX_JUMP => Ok(OpLoop(offset)),
+        _ => Err(AssembleError::new(
+            format!("{} can't jump from {:04} to {:04}", code, at, target),
+            line,
+        )),
+    }
+}
+
+// the text of a constant: a number, "string", true, false, null
+fn parse_value(text: &str) -> Option<Value> {
+    if let Some(s) = text.strip_prefix('"').and_then(|t| t.strip_suffix('"')) {
+        return Some(Value::string(s.into()));
+    }
+    Some(match text {
+        "true" => Value::boolean(true),
+        "false" => Value::boolean(false),
+        "null" => Value::NULL,
+        _ => Value::number(text.parse().ok()?),
+    })
+}
+
+fn is_ident(text: &str) -> bool {
+    let mut chars = text.chars();
+    matches!(chars.next(), Some(c) if c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+// the operands of one instruction line
+struct Cursor<'a> {
+    text: &'a str,
+    line: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(text: &'a str, line: usize) -> Self {
+        Self { text, line }
+    }
+
+    fn error<T>(&self, msg: String) -> Result<T> {
+        Err(AssembleError::new(msg, self.line))
+    }
+
+    fn word(&mut self) -> &'a str {
+        let text = self.text.trim_start();
+        let end = text.find(char::is_whitespace).unwrap_or(text.len());
+        self.text = &text[end..];
+        &text[..end]
+    }
+
+    fn end(&self) -> Result<()> {
+        match self.text.trim() {
+            "" => Ok(()),
+            rest => self.error(format!("unexpected '{}'", rest)),
+        }
+    }
+
+    // `3 '"text"'` as printed by the disassembler, or the bare text
+    fn constant(&mut self) -> Result<(Option<ConstantIndex>, &'a str)> {
+        let rest = self.text.trim();
+        self.text = "";
+        let (Some(open), Some(close)) = (rest.find('\''), rest.rfind('\'')) else {
+            if rest.is_empty() {
+                return self.error("missing operand".to_string());
+            }
+            return Ok((None, rest));
+        };
+        if open == close {
+            return self.error("unterminated constant".to_string());
+        }
+        match rest[..open].trim().parse() {
+            Ok(idx) => {
+                self.text = &rest[close + 1..];
+                Ok((Some(idx), &rest[open + 1..close]))
+            }
+            Err(_) => self.error(format!("invalid constant index '{}'", &rest[..open])),
+        }
+    }
+
+    fn byte(&mut self) -> Result<usize> {
+        let word = self.word();
+        match word.parse() {
+            Ok(n) if n < MAX_SLOTS => Ok(n),
+            _ => self.error(format!(
+                "expected an operand below {}, found '{}'",
+                MAX_SLOTS, word
+            )),
+        }
+    }
+
+    // `(2 args)`
+    fn args(&mut self) -> Result<usize> {
+        let argc = self.word().strip_prefix('(');
+        if self.word() != "args)" {
+            return self.error("expected '(<count> args)'".to_string());
+        }
+        match argc.map(str::parse) {
+            Some(Ok(n)) if n < MAX_SLOTS => Ok(n),
+            _ => self.error("invalid argument count".to_string()),
+        }
+    }
+
+    // `0004 -> 0012` as printed by the disassembler, or a label
+    fn target(&mut self) -> Result<Target> {
+        let rest = self.text.trim();
+        self.text = "";
+        if let Some((_, target)) = rest.split_once("->") {
+            return match target.trim().parse() {
+                Ok(target) => Ok(Target::Offset(target)),
+                Err(_) => self.error(format!("invalid jump target '{}'", target.trim())),
+            };
+        }
+        if is_ident(rest) {
+            return Ok(Target::Label(rest.to_string()));
+        }
+        self.error(format!("expected a label, found '{}'", rest))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        bytecode::{compiler::compile, debug::write_chunk, vm::Vm},
+        lexer::Lexer,
+        parser::parser::Parser,
+    };
+
+    use super::*;
+
+    fn listing(chunk: &Chunk) -> String {
+        let mut out = vec![];
+        write_chunk(&mut out, chunk, "script").unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn run(chunk: Chunk) -> Vm {
+        let mut vm = Vm::load(chunk).unwrap();
+        vm.run().unwrap();
+        vm
+    }
+
+    #[test]
+    fn round_trip() {
+        let source = r#"
+            class A {
+                init(n) { this.n = n }
+                get() {
+                    fn inner() { return this.n }
+                    return inner
+                }
+            }
+            class B extends A {
+                get() { return super.get()() * 2 }
+            }
+            fn counter() {
+                let i = 0
+                fn next() {
+                    i = i + 1
+                    return i
+                }
+                return next
+            }
+            let next = counter()
+            let total = 0
+            while (total < 10 && true) total = total + next()
+            let result = B(1.5).get() + total
+            let s = "it's 'quoted'"
+            fn safe(n) {
+                try {
+                    if (n > 1) throw n
+                } catch (e) {
+                    return -e
+                } finally {
+                    total = total + 1
+                }
+                return n
+            }
+            let negated = safe(2)
+        "#;
+        let lexer = Lexer::new(source.as_bytes(), "test.tx");
+        let chunk = compile(&Parser::new(lexer).parse().unwrap()).unwrap();
+        let text = listing(&chunk);
+
+        let assembled = assemble(&text).unwrap();
+        assert_eq!(listing(&assembled), text);
+        assert_eq!(assembled.code, chunk.code);
+        assert_eq!(assembled.positions, chunk.positions);
+        assert!(text.contains("try 0"));
+
+        let vm = run(assembled);
+        assert_eq!(vm.global("result"), Some(&Value::number(13.0)));
+        assert_eq!(vm.global("s"), Some(&Value::string("it's 'quoted'".into())));
+        assert_eq!(vm.global("negated"), Some(&Value::number(-2.0)));
+    }
+
+    #[test]
+    fn hand_written() {
+        let source = r#"
+            ; sum = 1 + 2 + ... + 10
+            OP_CONSTANT 0
+            OP_DEFINE_GLOBAL sum
+            OP_CONSTANT 1
+            loop:
+            OP_GET_LOCAL 0
+            OP_CONSTANT 10
+            OP_LESS_EQUAL
+            OP_JUMP_IF_FALSE end
+            OP_POP
+            OP_GET_GLOBAL sum
+            OP_GET_LOCAL 0
+            OP_ADD
+            OP_SET_GLOBAL "sum"
+            OP_POP
+            <20:3> OP_GET_LOCAL 0
+            OP_CONSTANT 1
+            OP_ADD
+            OP_SET_LOCAL 0
+            OP_POP
+            OP_LOOP loop
+            end:
+            OP_POP
+            OP_CLOSURE twice
+            | local 0
+            OP_CONSTANT 4
+            OP_CALL 1
+            OP_DEFINE_GLOBAL eight
+
+            == twice (arity 1) ==
+            OP_GET_LOCAL 1
+            OP_CONSTANT 2
+            OP_MULTIPLY
+            OP_RETURN
+        "#;
+        let chunk = assemble(source).unwrap();
+        // unannotated instructions are located at their line
+        assert_eq!(chunk.position(0), (3, 1));
+        let vm = run(chunk);
+        assert_eq!(vm.global("sum"), Some(&Value::number(55.0)));
+        assert_eq!(vm.global("eight"), Some(&Value::number(8.0)));
+    }
+
+    fn error(source: &str) -> String {
+        assemble(source).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            error("OP_PUSH 1"),
+            "AssembleError: unknown instruction 'OP_PUSH', at line 1"
+        );
+        assert_eq!(
+            error("OP_TRUE\nOP_JUMP nowhere"),
+            "AssembleError: undefined label nowhere, at line 2"
+        );
+        assert_eq!(
+            error("start:\nOP_JUMP start"),
+            "AssembleError: OP_JUMP can't jump from 0000 to 0000, at line 2"
+        );
+        assert_eq!(
+            error("OP_CONSTANT 300 '1'"),
+            "AssembleError: constant 300 needs OP_CONSTANT_LONG, at line 1"
+        );
+        assert_eq!(
+            error("OP_CONSTANT 0 '1'\nOP_CONSTANT 0 '2'"),
+            "AssembleError: constant 0 is already defined as another value, at line 2"
+        );
+        assert_eq!(
+            error("0001  <1:1> OP_NULL"),
+            "AssembleError: offset 0001 doesn't match the code (0000), at line 1"
+        );
+        assert_eq!(
+            error("OP_GET_LOCAL 256"),
+            "AssembleError: expected an operand below 256, found '256', at line 1"
+        );
+        assert_eq!(
+            error("OP_ADD 1"),
+            "AssembleError: unexpected '1', at line 1"
+        );
+        assert_eq!(
+            error("OP_CLOSURE f"),
+            "AssembleError: missing the chunk of <fn f>, at line 1"
+        );
+        assert_eq!(
+            error("OP_NULL\n== f (arity 0) =="),
+            "AssembleError: chunk f is not a function constant, at line 2"
+        );
+        assert_eq!(
+            error("try start..end -> catch (depth 0)"),
+            "AssembleError: undefined label start, at line 1"
+        );
+        assert_eq!(
+            error("try 0000..0001 -> 0001"),
+            "AssembleError: expected '(depth <slots>)', at line 1"
+        );
+    }
+}
@@ -1,12 +1,47 @@
-use super::{opcode::ConstantIndex, OpCode, Value};
+use std::cell::Cell;
+
+use super::{
+    opcode::{CacheIndex, ConstantIndex},
+    shape::InlineCache,
+    OpCode, Value,
+};
 
 pub type Pos = (usize, usize); // (ln, col)
 
-#[derive(Debug)]
+/**
+ * a run of bytecode sharing the same source position,
+ * from `start` up to the start of the next run
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PosRun {
+    pub start: u32,
+    pub ln: u32,
+    pub col: u32,
+}
+
+/**
+ * exception handler of the code from `start` up to `end`: a value thrown there
+ * cuts the frame's stack to `depth` slots, is pushed, and the code goes on at `target`
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Handler {
+    pub start: usize,
+    pub end: usize,
+    pub target: usize,
+    pub depth: usize,
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct Chunk {
-    pub codes: Vec<OpCode>,
+    // encoded instructions, see `OpCode::write`
+    pub code: Vec<u8>,
     pub constants: Vec<Value>,
-    pub positions: Vec<Pos>,
+    // run-length encoded positions of the code bytes
+    pub positions: Vec<PosRun>,
+    // one per property instruction, filled by the vm as the code runs
+    pub caches: Vec<Cell<InlineCache>>,
+    // innermost first, the first one covering an instruction handles its throws
+    pub handlers: Vec<Handler>,
 }
 
 impl Chunk {
@@ -14,9 +49,68 @@ impl Chunk {
         Self::default()
     }
 
-    pub fn write(&mut self, code: OpCode, pos: Pos) {
-        self.codes.push(code);
-        self.positions.push(pos);
+    /**
+     * append an instruction, returns its offset
+     */
+    pub fn write(&mut self, code: OpCode, pos: Pos) -> usize {
+        let offset = self.code.len();
+        code.write(&mut self.code);
+        let (ln, col) = (pos.0 as u32, pos.1 as u32);
+        match self.positions.last() {
+            Some(run) if run.ln == ln && run.col == col => (),
+            _ => self.positions.push(PosRun {
+                start: offset as u32,
+                ln,
+                col,
+            }),
+        }
+        offset
+    }
+
+    /**
+     * re-encode the instruction at `offset` in place, e.g. to patch a jump.
+     * the new instruction must have the same size
+     */
+    pub fn patch(&mut self, offset: usize, code: OpCode) {
+        let mut bytes = Vec::with_capacity(code.size());
+        code.write(&mut bytes);
+        let (old, _) = self.read(offset);
+        assert_eq!(old.size(), bytes.len(), "patch {} with {}", old, code);
+        self.code[offset..offset + bytes.len()].copy_from_slice(&bytes);
+    }
+
+    /**
+     * decode the instruction at `offset`, with the offset of the next one
+     */
+    #[inline(always)]
+    pub fn read(&self, offset: usize) -> (OpCode, usize) {
+        OpCode::read(&self.code, offset).expect("invalid bytecode")
+    }
+
+    /**
+     * decoded instructions with their offsets
+     */
+    pub fn instructions(&self) -> impl Iterator<Item = (usize, OpCode)> + '_ {
+        let mut offset = 0;
+        std::iter::from_fn(move || {
+            let (code, next) = OpCode::read(&self.code, offset)?;
+            let at = offset;
+            offset = next;
+            Some((at, code))
+        })
+    }
+
+    /**
+     * source position of the code byte at `offset`
+     */
+    pub fn position(&self, offset: usize) -> Pos {
+        let i = self
+            .positions
+            .partition_point(|run| run.start as usize <= offset);
+        match i.checked_sub(1).map(|i| self.positions[i]) {
+            Some(run) => (run.ln as usize, run.col as usize),
+            None => (0, 0),
+        }
     }
 
     pub fn add_constant(&mut self, value: Value) -> ConstantIndex {
@@ -27,15 +121,22 @@ impl Chunk {
     pub fn read_constant(&self, idx: ConstantIndex) -> Value {
         self.constants[idx].clone()
     }
-}
 
-impl Default for Chunk {
-    fn default() -> Self {
-        Self {
-            codes: Default::default(),
-            constants: Default::default(),
-            positions: Default::default(),
-        }
+    /**
+     * the handler of a value thrown by the instruction at `offset`
+     */
+    pub fn handler(&self, offset: usize) -> Option<&Handler> {
+        self.handlers
+            .iter()
+            .find(|handler| handler.start <= offset && offset < handler.end)
+    }
+
+    /**
+     * a new empty inline cache for a property instruction
+     */
+    pub fn add_cache(&mut self) -> CacheIndex {
+        self.caches.push(Cell::default());
+        self.caches.len() - 1
     }
 }
 
@@ -55,4 +156,58 @@ mod tests {
 
         println!("{:?}", chunk);
     }
+
+    #[test]
+    fn encoding() {
+        use OpCode::*;
+        let codes = [
+            OpConstant(255),
+            OpConstantLong(70000),
+            OpGetLocal(3),
+            OpJump(513),
+            OpInvoke(65536, 255, 65535),
+            OpAdd,
+            OpGetGlobal(0),
+        ];
+        let mut chunk = Chunk::new();
+        for code in codes {
+            chunk.write(code, (1, 1));
+        }
+        assert_eq!(chunk.code.len(), 2 + 4 + 2 + 3 + 7 + 1 + 4);
+        let decoded: Vec<_> = chunk.instructions().map(|(_, code)| code).collect();
+        assert_eq!(decoded, codes);
+
+        chunk.patch(8, OpLoop(7));
+        assert_eq!(chunk.read(8), (OpLoop(7), 11));
+        // truncated operand
+        assert_eq!(OpCode::read(&chunk.code[..3], 2), None);
+    }
+
+    #[test]
+    fn positions() {
+        use OpCode::*;
+        let mut chunk = Chunk::new();
+        chunk.write(OpConstant(0), (1, 1));
+        chunk.write(OpConstant(1), (1, 1));
+        chunk.write(OpAdd, (1, 3));
+        chunk.write(OpGetGlobal(2), (2, 1));
+        chunk.write(OpPop, (2, 1));
+        assert_eq!(chunk.positions.len(), 3);
+        let positions: Vec<_> = (0..chunk.code.len()).map(|i| chunk.position(i)).collect();
+        assert_eq!(
+            positions,
+            vec![
+                (1, 1),
+                (1, 1),
+                (1, 1),
+                (1, 1),
+                (1, 3),
+                (2, 1),
+                (2, 1),
+                (2, 1),
+                (2, 1),
+                (2, 1)
+            ]
+        );
+    }
 }
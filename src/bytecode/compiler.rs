@@ -0,0 +1,1339 @@
+use crate::{
+    analizer::resolver::Resolver,
+    ast::*,
+    error::CompileError,
+    interpreter::visitor::{ExprVisitor, StmtVisitor},
+    position::{Loc, Pos, Span},
+    token::Operator,
+};
+
+use std::rc::Rc;
+
+use super::{
+    object::{Function, UpvalueDesc},
+    opcode::{
+        CacheIndex, JumpOffset, MAX_CACHES, MAX_CONSTANTS, MAX_JUMP, MAX_SHORT_CONSTANTS, MAX_SLOTS,
+    },
+    Chunk, Handler, OpCode,
+    OpCode::*,
+    Value,
+};
+
+pub type CompileResult<T> = std::result::Result<T, CompileError>;
+
+/**
+ * lower the ast into a chunk of bytecode
+ */
+pub fn compile(program: &Program) -> CompileResult<Chunk> {
+    // same scoping rules as the tree-walker: redeclaration, self-initialization, ...
+    Resolver::new(&mut ())
+        .resolve(program)
+        .map_err(CompileError::Resolve)?;
+
+    let mut compiler = Compiler::new(program.file.as_deref().unwrap_or("<script>"));
+    for stmt in program.body.iter() {
+        compiler.compile_stmt(stmt)?;
+    }
+    Ok(compiler.states.pop().unwrap().function.chunk)
+}
+
+// local variable living in a stack slot
+struct Local {
+    name: String,
+    depth: usize,
+    // captured by a closure, closed instead of popped at the end of its scope
+    is_captured: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+const CONSTRUCTOR_INITIALIZER: &str = "init";
+
+// the function being compiled, nested declarations push a new state
+struct FunctionState {
+    function: Function,
+    kind: FunctionKind,
+    // index in `locals` is the stack slot, relative to the frame
+    locals: Vec<Local>,
+    scope_depth: usize,
+    // try statements around the code being compiled, innermost last
+    trys: Vec<TryState>,
+}
+
+// code covered by the handler of a try statement
+struct TryState {
+    // where the current range started, a `return` leaves the range
+    // while it runs the finally blocks
+    start: usize,
+    ranges: Vec<(usize, usize)>,
+    finalizer: Option<Vec<Statement>>,
+}
+
+impl TryState {
+    fn close(&mut self, end: usize) {
+        if self.start < end {
+            self.ranges.push((self.start, end));
+        }
+    }
+}
+
+impl FunctionState {
+    fn new(name: Option<String>, kind: FunctionKind) -> Self {
+        let mut locals = vec![];
+        // slot 0 holds the callee, or the receiver of methods
+        let slot0 = match kind {
+            FunctionKind::Script => None,
+            FunctionKind::Function => Some(""),
+            FunctionKind::Method | FunctionKind::Initializer => Some("this"),
+        };
+        if let Some(name) = slot0 {
+            locals.push(Local {
+                name: name.to_string(),
+                depth: 0,
+                is_captured: false,
+            });
+        }
+        Self {
+            function: Function {
+                name,
+                ..Default::default()
+            },
+            kind,
+            locals,
+            scope_depth: 0,
+            trys: vec![],
+        }
+    }
+
+    fn resolve_local(&self, name: &str) -> Option<usize> {
+        self.locals.iter().rposition(|local| local.name == name)
+    }
+
+    // None when the function captures too many variables
+    fn add_upvalue(&mut self, is_local: bool, index: usize) -> Option<usize> {
+        let desc = UpvalueDesc { is_local, index };
+        let upvalues = &mut self.function.upvalues;
+        match upvalues.iter().position(|u| *u == desc) {
+            Some(idx) => Some(idx),
+            None if upvalues.len() >= MAX_SLOTS => None,
+            None => {
+                upvalues.push(desc);
+                Some(upvalues.len() - 1)
+            }
+        }
+    }
+}
+
+enum Variable {
+    Local(usize),
+    Upvalue(usize),
+    Global,
+}
+
+pub struct Compiler {
+    states: Vec<FunctionState>,
+    filename: String,
+    // position of the node being compiled,
+    // used by nodes without span (eg: boolean literal)
+    pos: Pos,
+}
+
+impl Compiler {
+    pub fn new(filename: &str) -> Self {
+        Self {
+            states: vec![FunctionState::new(None, FunctionKind::Script)],
+            filename: filename.to_string(),
+            pos: Pos::new(1, 1),
+        }
+    }
+
+    fn compile_stmt(&mut self, stmt: &Statement) -> CompileResult<()> {
+        self.walk_stmt(stmt)
+    }
+
+    fn compile_expr(&mut self, expr: &Expr) -> CompileResult<()> {
+        self.walk_expr(expr)
+    }
+
+    fn state(&mut self) -> &mut FunctionState {
+        self.states.last_mut().unwrap()
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.state().function.chunk
+    }
+
+    fn emit(&mut self, code: OpCode) {
+        let pos = (self.pos.ln, self.pos.col);
+        self.chunk().write(code, pos);
+    }
+
+    // the first constants fit in a one byte operand
+    fn emit_constant(&mut self, value: Value) -> CompileResult<()> {
+        let idx = self.make_constant(value)?;
+        if idx < MAX_SHORT_CONSTANTS {
+            self.emit(OpConstant(idx));
+        } else {
+            self.emit(OpConstantLong(idx));
+        }
+        Ok(())
+    }
+
+    fn make_constant(&mut self, value: Value) -> CompileResult<usize> {
+        if self.chunk().constants.len() >= MAX_CONSTANTS {
+            return self.error("too many constants in one chunk");
+        }
+        Ok(self.chunk().add_constant(value))
+    }
+
+    fn make_cache(&mut self) -> CompileResult<CacheIndex> {
+        if self.chunk().caches.len() >= MAX_CACHES {
+            return self.error("too many property accesses in one function");
+        }
+        Ok(self.chunk().add_cache())
+    }
+
+    // emit a forward jump with a placeholder offset, returns its offset for `patch_jump`
+    fn emit_jump(&mut self, code: fn(JumpOffset) -> OpCode) -> usize {
+        let pos = (self.pos.ln, self.pos.col);
+        self.chunk().write(code(0), pos)
+    }
+
+    // make the jump at `at` land on the next emitted instruction
+    fn patch_jump(&mut self, at: usize) -> CompileResult<()> {
+        let chunk = self.chunk();
+        let (jump, next) = chunk.read(at);
+        let offset = chunk.code.len() - next;
+        if offset > MAX_JUMP {
+            return self.error("too much code to jump over");
+        }
+        let jump = match jump {
+            OpJump(_) => OpJump(offset),
+            OpJumpIfFalse(_) => OpJumpIfFalse(offset),
+            code => unreachable!("not a jump: {}", code),
+        };
+        self.chunk().patch(at, jump);
+        Ok(())
+    }
+
+    fn emit_loop(&mut self, loop_start: usize) -> CompileResult<()> {
+        let offset = self.chunk().code.len() + OpLoop(0).size() - loop_start;
+        if offset > MAX_JUMP {
+            return self.error("loop body too large");
+        }
+        self.emit(OpLoop(offset));
+        Ok(())
+    }
+
+    // initializers always return the instance
+    fn emit_return(&mut self) -> CompileResult<()> {
+        if self.state().kind == FunctionKind::Initializer {
+            self.emit(OpGetLocal(0));
+        } else {
+            self.emit(OpNull);
+        }
+        self.emit_return_value()
+    }
+
+    // return the value on top of the stack, after the finally blocks
+    // of the try statements around
+    fn emit_return_value(&mut self) -> CompileResult<()> {
+        if self.state().trys.is_empty() {
+            self.emit(OpReturn);
+            return Ok(());
+        }
+        // the value waits in a hidden local, under the locals of the finally blocks
+        let depth = self.state().locals.len();
+        self.begin_scope();
+        self.declare_variable("")?;
+        for i in (0..self.state().trys.len()).rev() {
+            let here = self.chunk().code.len();
+            self.state().trys[i].close(here);
+            // what the finally block throws goes to the handlers around its try
+            let inner = self.state().trys.split_off(i);
+            let result = match &inner[0].finalizer {
+                Some(finalizer) => self.visit_block(finalizer),
+                None => Ok(()),
+            };
+            self.state().trys.extend(inner);
+            result?;
+        }
+        self.emit(OpReturn);
+
+        let here = self.chunk().code.len();
+        let state = self.state();
+        for try_state in state.trys.iter_mut() {
+            try_state.start = here;
+        }
+        state.scope_depth -= 1;
+        state.locals.truncate(depth);
+        Ok(())
+    }
+
+    // compile `body` under a handler, returns the ranges it covers
+    fn protect<F>(
+        &mut self,
+        finalizer: &Option<Vec<Statement>>,
+        body: F,
+    ) -> CompileResult<Vec<(usize, usize)>>
+    where
+        F: FnOnce(&mut Self) -> CompileResult<()>,
+    {
+        let start = self.chunk().code.len();
+        self.state().trys.push(TryState {
+            start,
+            ranges: vec![],
+            finalizer: finalizer.clone(),
+        });
+        body(self)?;
+        let mut try_state = self.state().trys.pop().unwrap();
+        try_state.close(self.chunk().code.len());
+        Ok(try_state.ranges)
+    }
+
+    // the ranges go to the next emitted instruction,
+    // with the stack cut to the locals of the try statement
+    fn add_handlers(&mut self, ranges: &[(usize, usize)], depth: usize) {
+        let target = self.chunk().code.len();
+        for &(start, end) in ranges {
+            self.chunk().handlers.push(Handler {
+                start,
+                end,
+                target,
+                depth,
+            });
+        }
+    }
+
+    fn finally(&mut self, finalizer: &Option<Vec<Statement>>) -> CompileResult<()> {
+        match finalizer {
+            Some(block) => self.visit_block(block),
+            None => Ok(()),
+        }
+    }
+
+    fn identifier_constant(&mut self, name: &str) -> CompileResult<usize> {
+        self.make_constant(Value::string(name.into()))
+    }
+
+    fn begin_scope(&mut self) {
+        self.state().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        let state = self.state();
+        state.scope_depth -= 1;
+        let depth = state.scope_depth;
+        while let Some(local) = self.state().locals.pop() {
+            if local.depth <= depth {
+                self.state().locals.push(local);
+                break;
+            }
+            self.emit(if local.is_captured {
+                OpCloseUpvalue
+            } else {
+                OpPop
+            });
+        }
+    }
+
+    // a local in the current scope, or a global at the top level
+    fn declare_variable(&mut self, name: &str) -> CompileResult<()> {
+        let state = self.state();
+        if state.scope_depth > 0 {
+            if state.locals.len() >= MAX_SLOTS {
+                return self.error("too many local variables in function");
+            }
+            let depth = state.scope_depth;
+            state.locals.push(Local {
+                name: name.to_string(),
+                depth,
+                is_captured: false,
+            });
+        }
+        Ok(())
+    }
+
+    // the value on top of the stack becomes the variable
+    fn define_variable(&mut self, name: &str) -> CompileResult<()> {
+        if self.state().scope_depth == 0 {
+            let idx = self.identifier_constant(name)?;
+            self.emit(OpDefineGlobal(idx));
+        }
+        Ok(())
+    }
+
+    fn resolve_variable(&mut self, name: &str) -> CompileResult<Variable> {
+        let level = self.states.len() - 1;
+        if let Some(slot) = self.states[level].resolve_local(name) {
+            return Ok(Variable::Local(slot));
+        }
+        match self.resolve_upvalue(level, name)? {
+            Some(idx) => Ok(Variable::Upvalue(idx)),
+            None => Ok(Variable::Global),
+        }
+    }
+
+    // look for `name` in the enclosing functions of `states[level]`
+    fn resolve_upvalue(&mut self, level: usize, name: &str) -> CompileResult<Option<usize>> {
+        if level == 0 {
+            return Ok(None);
+        }
+        let upvalue = match self.states[level - 1].resolve_local(name) {
+            Some(slot) => {
+                self.states[level - 1].locals[slot].is_captured = true;
+                self.states[level].add_upvalue(true, slot)
+            }
+            None => match self.resolve_upvalue(level - 1, name)? {
+                Some(idx) => self.states[level].add_upvalue(false, idx),
+                None => return Ok(None),
+            },
+        };
+        match upvalue {
+            Some(idx) => Ok(Some(idx)),
+            None => self.error("too many closure variables in function"),
+        }
+    }
+
+    fn get_variable(&mut self, name: &str) -> CompileResult<()> {
+        let code = match self.resolve_variable(name)? {
+            Variable::Local(slot) => OpGetLocal(slot),
+            Variable::Upvalue(idx) => OpGetUpvalue(idx),
+            Variable::Global => OpGetGlobal(self.identifier_constant(name)?),
+        };
+        self.emit(code);
+        Ok(())
+    }
+
+    fn set_variable(&mut self, name: &str) -> CompileResult<()> {
+        let code = match self.resolve_variable(name)? {
+            Variable::Local(slot) => OpSetLocal(slot),
+            Variable::Upvalue(idx) => OpSetUpvalue(idx),
+            Variable::Global => OpSetGlobal(self.identifier_constant(name)?),
+        };
+        self.emit(code);
+        Ok(())
+    }
+
+    // compile the function into its own chunk, then emit the closure creation
+    fn function(&mut self, decl: &FunctionDeclaration, kind: FunctionKind) -> CompileResult<()> {
+        let FunctionDeclaration { id, params, body } = decl;
+        self.states
+            .push(FunctionState::new(Some(id.name.clone()), kind));
+        self.begin_scope();
+        for param in params.iter() {
+            self.declare_variable(&param.name)?;
+        }
+        self.state().function.arity = params.len();
+
+        for stmt in body.iter() {
+            self.compile_stmt(stmt)?;
+        }
+        self.emit_return()?;
+
+        let function = self.states.pop().unwrap().function;
+        self.set_pos(&id.span);
+        let idx = self.make_constant(Value::function(Rc::new(function)))?;
+        self.emit(OpClosure(idx));
+        Ok(())
+    }
+
+    // following instructions are emitted at the start of `span`
+    fn set_pos(&mut self, span: &Span) {
+        self.pos = span.loc.start;
+    }
+
+    fn span(&self) -> Span {
+        Span::new(self.filename.clone(), Loc::new(self.pos, self.pos))
+    }
+
+    fn error<T>(&self, msg: &str) -> CompileResult<T> {
+        Err(CompileError::Error(msg.to_string(), self.span()))
+    }
+
+    fn unsupported<T>(&self, what: &str) -> CompileResult<T> {
+        Err(CompileError::Unsupported(what.to_string(), self.span()))
+    }
+}
+
+impl StmtVisitor for Compiler {
+    type Item = CompileResult<()>;
+
+    fn visit_expr_stmt(&mut self, expr: &Expr) -> Self::Item {
+        self.compile_expr(expr)?;
+        self.emit(OpPop);
+        Ok(())
+    }
+
+    fn visit_block(&mut self, block: &[Statement]) -> Self::Item {
+        self.begin_scope();
+        for stmt in block {
+            self.compile_stmt(stmt)?;
+        }
+        self.end_scope();
+        Ok(())
+    }
+
+    fn visit_empty(&mut self) -> Self::Item {
+        Ok(())
+    }
+
+    fn visit_variable_declare(&mut self, decl: &VariableDeclaration) -> Self::Item {
+        let VariableDeclaration { id, init } = decl;
+        match init {
+            Some(expr) => self.compile_expr(expr)?,
+            None => {
+                self.set_pos(&id.span);
+                self.emit(OpNull);
+            }
+        }
+
+        // a local's slot is the initializer value left on the stack
+        self.set_pos(&id.span);
+        self.declare_variable(&id.name)?;
+        self.define_variable(&id.name)?;
+        Ok(())
+    }
+
+    fn visit_function_declare(&mut self, decl: &FunctionDeclaration) -> Self::Item {
+        // declared before the body, so the function can call itself
+        self.declare_variable(&decl.id.name)?;
+        self.function(decl, FunctionKind::Function)?;
+        self.define_variable(&decl.id.name)?;
+        Ok(())
+    }
+
+    fn visit_class_declare(&mut self, class: &ClassDeclaration) -> Self::Item {
+        let ClassDeclaration { id, super_id, body } = class;
+        self.set_pos(&id.span);
+        let name = self.identifier_constant(&id.name)?;
+        self.declare_variable(&id.name)?;
+        self.emit(OpClass(name));
+        self.define_variable(&id.name)?;
+
+        if let Some(super_id) = super_id {
+            // the superclass lives in a local named `super`, captured by the methods
+            self.set_pos(&super_id.span);
+            self.get_variable(&super_id.name)?;
+            self.begin_scope();
+            self.declare_variable("super")?;
+            self.get_variable(&id.name)?;
+            self.emit(OpInherit);
+        }
+
+        // the class stays on the stack while its methods are defined
+        self.get_variable(&id.name)?;
+        for method in body.iter() {
+            let kind = if method.id.name == CONSTRUCTOR_INITIALIZER {
+                FunctionKind::Initializer
+            } else {
+                FunctionKind::Method
+            };
+            self.function(method, kind)?;
+            let name = self.identifier_constant(&method.id.name)?;
+            self.emit(OpMethod(name));
+        }
+        self.emit(OpPop);
+
+        if super_id.is_some() {
+            self.end_scope();
+        }
+        Ok(())
+    }
+
+    fn visit_if_stmt(&mut self, stmt: &IfStatement) -> Self::Item {
+        let IfStatement {
+            test,
+            consequent,
+            alternate,
+        } = stmt;
+        self.compile_expr(test)?;
+
+        let then_jump = self.emit_jump(OpJumpIfFalse);
+        self.emit(OpPop);
+        self.compile_stmt(consequent)?;
+        let else_jump = self.emit_jump(OpJump);
+
+        self.patch_jump(then_jump)?;
+        self.emit(OpPop);
+        if let Some(alternate) = alternate {
+            self.compile_stmt(alternate)?;
+        }
+        self.patch_jump(else_jump)?;
+        Ok(())
+    }
+
+    fn visit_return_stmt(&mut self, stmt: &ReturnStatement) -> Self::Item {
+        match &stmt.argument {
+            Some(_) if self.state().kind == FunctionKind::Initializer => Err(CompileError::Error(
+                "Can't return a value from an initializer".to_string(),
+                self.span(),
+            )),
+            Some(expr) => {
+                self.compile_expr(expr)?;
+                self.emit_return_value()
+            }
+            None => self.emit_return(),
+        }
+    }
+
+    fn visit_print_stmt(&mut self, expr: &Expr) -> Self::Item {
+        self.compile_expr(expr)?;
+        self.emit(OpPrint);
+        Ok(())
+    }
+
+    fn visit_throw_stmt(&mut self, stmt: &ThrowStatement) -> Self::Item {
+        self.compile_expr(&stmt.argument)?;
+        self.set_pos(&stmt.span);
+        self.emit(OpThrow);
+        Ok(())
+    }
+
+    fn visit_try_stmt(&mut self, stmt: &TryStatement) -> Self::Item {
+        let TryStatement {
+            block,
+            handler,
+            finalizer,
+        } = stmt;
+        let depth = self.state().locals.len();
+        let mut ranges = self.protect(finalizer, |this| this.visit_block(block))?;
+        self.finally(finalizer)?;
+        let mut exits = vec![self.emit_jump(OpJump)];
+
+        if let Some(CatchClause { param, body }) = handler {
+            // the vm pushes the thrown value, it becomes the parameter
+            self.add_handlers(&ranges, depth);
+            self.begin_scope();
+            self.set_pos(&param.span);
+            self.declare_variable(&param.name)?;
+            ranges = match finalizer {
+                Some(_) => self.protect(finalizer, |this| this.visit_block(body))?,
+                None => {
+                    self.visit_block(body)?;
+                    vec![]
+                }
+            };
+            self.end_scope();
+            if finalizer.is_some() {
+                self.finally(finalizer)?;
+                exits.push(self.emit_jump(OpJump));
+            }
+        }
+
+        if finalizer.is_some() {
+            // what the finally block runs after: the value stays in a hidden
+            // local, and is thrown again at the end of the block
+            self.add_handlers(&ranges, depth);
+            self.begin_scope();
+            self.declare_variable("")?;
+            self.finally(finalizer)?;
+            self.emit(OpRethrow(depth));
+            let state = self.state();
+            state.scope_depth -= 1;
+            state.locals.truncate(depth);
+        }
+
+        for exit in exits {
+            self.patch_jump(exit)?;
+        }
+        Ok(())
+    }
+
+    fn visit_while_stmt(&mut self, stmt: &WhileStmt) -> Self::Item {
+        let WhileStmt { test, body } = stmt;
+        let loop_start = self.chunk().code.len();
+        self.compile_expr(test)?;
+
+        let exit_jump = self.emit_jump(OpJumpIfFalse);
+        self.emit(OpPop);
+        self.compile_stmt(body)?;
+        self.emit_loop(loop_start)?;
+
+        self.patch_jump(exit_jump)?;
+        self.emit(OpPop);
+        Ok(())
+    }
+}
+
+impl ExprVisitor for Compiler {
+    type Item = CompileResult<()>;
+
+    fn visit_binary(&mut self, binary: &BinaryExpr) -> Self::Item {
+        let BinaryExpr { left, op, right } = binary;
+        self.compile_expr(left)?;
+        self.compile_expr(right)?;
+
+        self.set_pos(&op.span());
+        match op.value {
+            Operator::Add => self.emit(OpAdd),
+            Operator::Min => self.emit(OpSubtract),
+            Operator::Mul => self.emit(OpMultiply),
+            Operator::Div => self.emit(OpDivide),
+            Operator::Equal => self.emit(OpEqual),
+            Operator::NotEqual => self.emit(OpNotEqual),
+            Operator::GreaterThan => self.emit(OpGreater),
+            Operator::GreaterThanEqual => self.emit(OpGreaterEqual),
+            Operator::LessThan => self.emit(OpLess),
+            Operator::LessThanEqual => self.emit(OpLessEqual),
+            _ => return self.unsupported(&format!("operator '{}'", op.value)),
+        }
+        Ok(())
+    }
+
+    fn visit_unary(&mut self, unary: &UnaryExpr) -> Self::Item {
+        let UnaryExpr { op, argument } = unary;
+        self.compile_expr(argument)?;
+
+        self.set_pos(&op.span());
+        match op.value {
+            Operator::Min => self.emit(OpNegate),
+            Operator::Not => self.emit(OpNot),
+            Operator::Add => (),
+            _ => return self.unsupported(&format!("operator '{}'", op.value)),
+        }
+        Ok(())
+    }
+
+    fn visit_assign(&mut self, assign: &AssignExpr) -> Self::Item {
+        let AssignExpr { op: _, left, right } = assign;
+        self.compile_expr(right)?;
+
+        self.set_pos(&left.span);
+        self.set_variable(&left.name)?;
+        Ok(())
+    }
+
+    fn visit_ident(&mut self, ident: &Identifier) -> Self::Item {
+        self.set_pos(&ident.span);
+        self.get_variable(&ident.name)?;
+        Ok(())
+    }
+
+    fn visit_call(&mut self, call: &CallExpr) -> Self::Item {
+        let CallExpr {
+            callee,
+            arguments,
+            span,
+        } = call;
+        // `a.method()` is invoked directly, without a bound method
+        let method = match callee.as_ref() {
+            Expr::Get(GetExpr { object, property }) => {
+                self.compile_expr(object)?;
+                Some(property)
+            }
+            _ => {
+                self.compile_expr(callee)?;
+                None
+            }
+        };
+        for arg in arguments.iter() {
+            self.compile_expr(arg)?;
+        }
+
+        self.set_pos(span);
+        match method {
+            Some(property) => {
+                let name = self.identifier_constant(&property.name)?;
+                let cache = self.make_cache()?;
+                self.emit(OpInvoke(name, arguments.len(), cache));
+            }
+            None => self.emit(OpCall(arguments.len())),
+        }
+        Ok(())
+    }
+
+    fn visit_logical(&mut self, expr: &LogicalExpr) -> Self::Item {
+        let LogicalExpr { left, op, right } = expr;
+        self.compile_expr(left)?;
+
+        self.set_pos(&op.span());
+        match op.value {
+            Operator::And => {
+                let end_jump = self.emit_jump(OpJumpIfFalse);
+                self.emit(OpPop);
+                self.compile_expr(right)?;
+                self.patch_jump(end_jump)?;
+            }
+            Operator::Or => {
+                let else_jump = self.emit_jump(OpJumpIfFalse);
+                let end_jump = self.emit_jump(OpJump);
+                self.patch_jump(else_jump)?;
+                self.emit(OpPop);
+                self.compile_expr(right)?;
+                self.patch_jump(end_jump)?;
+            }
+            _ => return self.unsupported(&format!("operator '{}'", op.value)),
+        }
+        Ok(())
+    }
+
+    fn visit_get(&mut self, expr: &GetExpr) -> Self::Item {
+        let GetExpr { object, property } = expr;
+        self.compile_expr(object)?;
+        self.set_pos(&property.span);
+        let name = self.identifier_constant(&property.name)?;
+        let cache = self.make_cache()?;
+        self.emit(OpGetProperty(name, cache));
+        Ok(())
+    }
+
+    fn visit_set(&mut self, expr: &SetExpr) -> Self::Item {
+        let SetExpr {
+            object,
+            property,
+            value,
+        } = expr;
+        self.compile_expr(object)?;
+        self.compile_expr(value)?;
+        self.set_pos(&property.span);
+        let name = self.identifier_constant(&property.name)?;
+        let cache = self.make_cache()?;
+        self.emit(OpSetProperty(name, cache));
+        Ok(())
+    }
+
+    fn visit_this(&mut self, this: &ThisExpr) -> Self::Item {
+        self.set_pos(&this.span);
+        self.get_variable("this")?;
+        Ok(())
+    }
+
+    fn visit_super(&mut self, expr: &SuperExpr) -> Self::Item {
+        self.set_pos(&expr.span);
+        self.get_variable("this")?;
+        self.get_variable("super")?;
+        let name = self.identifier_constant(&expr.method.name)?;
+        self.emit(OpGetSuper(name));
+        Ok(())
+    }
+
+    fn visit_numeric(&mut self, lit: &NumericLiteral) -> Self::Item {
+        self.set_pos(&lit.span);
+        self.emit_constant(Value::number(lit.value))
+    }
+
+    fn visit_string(&mut self, lit: &StringLiteral) -> Self::Item {
+        self.set_pos(&lit.span);
+        self.emit_constant(Value::string(lit.value.as_str().into()))
+    }
+
+    fn visit_boolean(&mut self, lit: bool) -> Self::Item {
+        self.emit(if lit { OpTrue } else { OpFalse });
+        Ok(())
+    }
+
+    fn visit_null(&mut self) -> Self::Item {
+        self.emit(OpNull);
+        Ok(())
+    }
+
+    fn visit_array(&mut self, lit: &ArrayLiteral) -> Self::Item {
+        self.set_pos(&lit.span);
+        self.unsupported("array")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        bytecode::{
+            debug::disassemble_chunk,
+            verify::verify,
+            vm::{interpret, Vm},
+        },
+        lexer::Lexer,
+        parser::parser::Parser,
+    };
+
+    use super::*;
+
+    // the compiler only produces verifiable code
+    fn compile_source(source: &str) -> CompileResult<Chunk> {
+        let lexer = Lexer::new(source.as_bytes(), "test.tx");
+        let ast = Parser::new(lexer).parse().unwrap();
+        let chunk = compile(&ast)?;
+        if let Err(e) = verify(&chunk) {
+            panic!("{}", e);
+        }
+        Ok(chunk)
+    }
+
+    fn codes(chunk: &Chunk) -> Vec<OpCode> {
+        chunk.instructions().map(|(_, code)| code).collect()
+    }
+
+    // position of each instruction
+    fn positions(chunk: &Chunk) -> Vec<(usize, usize)> {
+        chunk
+            .instructions()
+            .map(|(at, _)| chunk.position(at))
+            .collect()
+    }
+
+    #[test]
+    fn arithmetic() {
+        let chunk = compile_source("1 + 2 * -3").unwrap();
+        assert_eq!(
+            codes(&chunk),
+            vec![
+                OpConstant(0),
+                OpConstant(1),
+                OpConstant(2),
+                OpNegate,
+                OpMultiply,
+                OpAdd,
+                OpPop
+            ]
+        );
+        // the operators are located at their tokens
+        assert_eq!(positions(&chunk)[4], (1, 7));
+        assert_eq!(positions(&chunk)[5], (1, 3));
+
+        disassemble_chunk(&chunk, "arithmetic");
+        interpret(chunk).unwrap();
+    }
+
+    #[test]
+    fn print_stmt() {
+        let chunk = compile_source("print (1 + 2) / 4\n{ print 3 }").unwrap();
+        assert_eq!(codes(&chunk)[5], OpPrint);
+        assert_eq!(codes(&chunk)[7], OpPrint);
+        assert_eq!(positions(&chunk)[7], (2, 9));
+        interpret(chunk).unwrap();
+    }
+
+    #[test]
+    fn comparison() {
+        let chunk = compile_source("print !(1 >= 2) == true").unwrap();
+        assert_eq!(
+            codes(&chunk),
+            vec![
+                OpConstant(0),
+                OpConstant(1),
+                OpGreaterEqual,
+                OpNot,
+                OpTrue,
+                OpEqual,
+                OpPrint
+            ]
+        );
+        interpret(chunk).unwrap();
+    }
+
+    #[test]
+    fn type_mismatch() {
+        let chunk = compile_source("print \"a\" + \"b\"\nprint 1 < \"b\"").unwrap();
+        let err = interpret(chunk).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "RuntimeError: invalid operands for '<': number and string, at: 2:9"
+        );
+    }
+
+    #[test]
+    fn variables() {
+        let chunk = compile_source("let a = 1\n{ let b = a\n b = 2 }").unwrap();
+        assert_eq!(
+            codes(&chunk),
+            vec![
+                OpConstant(0),
+                OpDefineGlobal(1),
+                OpGetGlobal(2),
+                OpConstant(3),
+                OpSetLocal(0),
+                OpPop,
+                OpPop
+            ]
+        );
+
+        let err = compile_source("{ let a = 1\n let a = 2 }").unwrap_err();
+        assert!(matches!(err, CompileError::Resolve(_)));
+    }
+
+    #[test]
+    fn block_scope() {
+        let source = r#"
+            let a = 1
+            let b = 0
+            {
+                let a = 2
+                {
+                    let a = 3
+                    b = b * 10 + a
+                }
+                b = b * 10 + a
+                a = 4
+            }
+            b = b * 10 + a
+            let c
+        "#;
+        let mut vm = Vm::new(compile_source(source).unwrap());
+        vm.run().unwrap();
+        assert_eq!(vm.global("b"), Some(&Value::number(321.0)));
+        assert_eq!(vm.global("c"), Some(&Value::NULL));
+
+        let mut vm = Vm::new(compile_source("print x").unwrap());
+        let err = vm.run().unwrap_err();
+        assert_eq!(err.to_string(), "RuntimeError: x is not defined, at: 1:7");
+    }
+
+    #[test]
+    fn jumps() {
+        let chunk = compile_source("if (true) print 1; else print 2").unwrap();
+        assert_eq!(
+            codes(&chunk),
+            vec![
+                OpTrue,
+                OpJumpIfFalse(7),
+                OpPop,
+                OpConstant(0),
+                OpPrint,
+                OpJump(4),
+                OpPop,
+                OpConstant(1),
+                OpPrint
+            ]
+        );
+
+        let chunk = compile_source("while (false) {}").unwrap();
+        assert_eq!(
+            codes(&chunk),
+            vec![OpFalse, OpJumpIfFalse(4), OpPop, OpLoop(8), OpPop]
+        );
+    }
+
+    #[test]
+    fn exceptions() {
+        let chunk = compile_source("try { throw 1 } catch (e) { print e }").unwrap();
+        assert_eq!(
+            codes(&chunk),
+            vec![
+                OpConstant(0),
+                OpThrow,
+                OpJump(4),
+                // the thrown value is the local `e`
+                OpGetLocal(0),
+                OpPrint,
+                OpPop
+            ]
+        );
+        assert_eq!(
+            chunk.handlers,
+            vec![Handler {
+                start: 0,
+                end: 3,
+                target: 6,
+                depth: 0
+            }]
+        );
+
+        // the return runs the finally block outside of the handler
+        let chunk = compile_source("fn f() { try { return 1 } finally { print 2 } }").unwrap();
+        let f = chunk.constants[0].as_function().unwrap();
+        assert_eq!(
+            codes(&f.chunk),
+            vec![
+                OpConstant(0),
+                OpConstant(1),
+                OpPrint,
+                OpReturn,
+                OpConstant(2),
+                OpPrint,
+                OpJump(5),
+                // thrown: run the finally block, then throw again
+                OpConstant(3),
+                OpPrint,
+                OpRethrow(1),
+                OpNull,
+                OpReturn
+            ]
+        );
+        assert_eq!(
+            f.chunk.handlers,
+            vec![Handler {
+                start: 0,
+                end: 2,
+                target: 12,
+                depth: 1
+            }]
+        );
+    }
+
+    #[test]
+    fn control_flow() {
+        let source = r#"
+            let i = 0
+            let sum = 0
+            while (i < 10) {
+                if (i == 3 || i == 5) sum = sum + 100;
+                else if (i > 6 && i <= 8) sum = sum + 10;
+                else sum = sum + 1
+                i = i + 1
+            }
+            // the right side is never evaluated
+            let a = false && undefined
+            let b = 1 || undefined
+            let c = null || "c"
+        "#;
+        let mut vm = Vm::new(compile_source(source).unwrap());
+        vm.run().unwrap();
+        assert_eq!(vm.global("sum"), Some(&Value::number(226.0)));
+        assert_eq!(vm.global("a"), Some(&Value::boolean(false)));
+        assert_eq!(vm.global("b"), Some(&Value::number(1.0)));
+        assert_eq!(vm.global("c"), Some(&"c".into()));
+    }
+
+    // collect on every allocation, a missing gc root shows up as a dangling object
+    fn run(source: &str) -> Vm {
+        let mut vm = Vm::new(compile_source(source).unwrap()).with_stress_gc(true);
+        vm.run().unwrap();
+        vm
+    }
+
+    #[test]
+    fn functions() {
+        let source = r#"
+            fn fib(n) {
+                if (n < 2) return n
+                return fib(n - 1) + fib(n - 2)
+            }
+            fn noop() {}
+            let a = fib(15)
+            let b = noop()
+        "#;
+        let vm = run(source);
+        assert_eq!(vm.global("a"), Some(&Value::number(610.0)));
+        assert_eq!(vm.global("b"), Some(&Value::NULL));
+        assert_same_globals(source, &["a", "b", "fib"]);
+    }
+
+    #[test]
+    fn closures() {
+        let source = r#"
+            fn makeCounter() {
+                let count = 0
+                fn inc() {
+                    count = count + 1
+                    return count
+                }
+                return inc
+            }
+            let c1 = makeCounter()
+            let c2 = makeCounter()
+            c1()
+            c1()
+            let a = c1()
+            let b = c2()
+
+            // closures share the captured variable, even after it leaves the stack
+            let get
+            let set
+            {
+                let x = "a"
+                fn g() { return x }
+                fn s(v) { x = v }
+                get = g
+                set = s
+            }
+            set("b")
+            let x = get()
+        "#;
+        let vm = run(source);
+        assert_eq!(vm.global("a"), Some(&Value::number(3.0)));
+        assert_eq!(vm.global("b"), Some(&Value::number(1.0)));
+        assert_eq!(vm.global("x"), Some(&"b".into()));
+    }
+
+    // the globals display the same on both backends
+    fn assert_same_globals(source: &str, names: &[&str]) {
+        let vm = run(source);
+
+        let ast = Parser::new(Lexer::new(source.as_bytes(), "test.tx"))
+            .parse()
+            .unwrap();
+        let mut interpreter = crate::interpreter::Interpreter::new();
+        Resolver::new(&mut interpreter).resolve(&ast).unwrap();
+        interpreter.eval(ast).unwrap();
+
+        for name in names {
+            let ast = Parser::new(Lexer::new(name.as_bytes(), "test.tx"))
+                .parse()
+                .unwrap();
+            let expected = interpreter.eval(ast).unwrap().unwrap();
+            let found = vm.global(name).unwrap();
+            assert_eq!(vm.format(found), expected.to_string(), "global {}", name);
+        }
+    }
+
+    #[test]
+    fn classes() {
+        let source = r#"
+            class Point {
+                init(x, y) {
+                    this.x = x
+                    this.y = y
+                }
+                sum() {
+                    return this.x + this.y
+                }
+                adder() {
+                    fn add(n) {
+                        return this.x + n
+                    }
+                    return add
+                }
+            }
+            let p = Point(1, 2)
+            let sum = p.sum()
+            let add = p.adder()
+            let added = add(10)
+            p.x = 5
+            let bound = p.sum
+            let rebound = bound()
+            fn fn_field(a) { return a * 2 }
+            p.f = fn_field
+            let field = p.f(21)
+            let name = Point
+            let method = p.sum
+        "#;
+        assert_same_globals(
+            source,
+            &["p", "sum", "added", "rebound", "field", "name", "method"],
+        );
+    }
+
+    #[test]
+    fn inheritance() {
+        let source = r#"
+            class A {
+                init(name) {
+                    this.name = name
+                }
+                hello() {
+                    return "A " + this.name
+                }
+                who() {
+                    return "A"
+                }
+            }
+            class B extends A {
+                hello() {
+                    return "B " + super.hello()
+                }
+                who() {
+                    let parent = super.who
+                    return parent() + "B"
+                }
+            }
+            class C extends B {}
+            let c = C("c")
+            let hello = c.hello()
+            let who = c.who()
+        "#;
+        assert_same_globals(source, &["c", "hello", "who"]);
+
+        let mut vm = Vm::new(compile_source("let a = 1\nclass B extends a {}").unwrap());
+        let err = vm.run().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "RuntimeError: superclass must be a class, at: 2:17"
+        );
+
+        let mut vm = Vm::new(compile_source("class A {}\nA().missing()").unwrap());
+        let err = vm.run().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "RuntimeError: undefined property missing, at: 2:1"
+        );
+    }
+
+    #[test]
+    fn call_errors() {
+        let mut vm = Vm::new(compile_source("fn f(a) {}\nf(1, 2)").unwrap());
+        let err = vm.run().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "RuntimeError: f expected 1 arguments but got 2, at: 2:1"
+        );
+
+        let mut vm = Vm::new(compile_source("let a = 1\na()").unwrap());
+        let err = vm.run().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "RuntimeError: number is not callable, at: 2:1"
+        );
+
+        let mut vm = Vm::new(compile_source("fn f() { return f() }\nf()").unwrap());
+        let err = vm.run().unwrap_err();
+        assert!(err.to_string().contains("stack overflow"));
+        assert!(err.to_string().contains("... 1003 more frames"));
+    }
+
+    #[test]
+    fn backtrace() {
+        let source = r#"
+fn inner(x) {
+    return x + "!"
+}
+fn outer(x) {
+    return inner(x)
+}
+class A {
+    run() { return outer(1) }
+}
+A().run()
+"#;
+        let mut vm = Vm::new(compile_source(source).unwrap());
+        let err = vm.run().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "RuntimeError: invalid operands for '+': number and string, at: 3:14
+stack backtrace (most recent call last):
+    at run (11:1)
+    at outer (9:20)
+    at inner (6:12)"
+        );
+    }
+
+    #[test]
+    fn unsupported() {
+        let err = compile_source("1 +\n[1]").unwrap_err();
+        assert!(
+            matches!(err, CompileError::Unsupported(_, span) if span.loc.start == Pos::new(2, 1))
+        );
+    }
+
+    #[test]
+    fn operand_limits() {
+        // constants past the first 256 use the 3 bytes operand
+        let terms: Vec<_> = (0..300).map(|i| i.to_string()).collect();
+        let source = format!("let sum = {}", terms.join(" + "));
+        let chunk = compile_source(&source).unwrap();
+        assert!(codes(&chunk).contains(&OpConstant(255)));
+        assert!(codes(&chunk).contains(&OpConstantLong(256)));
+        let mut vm = Vm::new(chunk);
+        vm.run().unwrap();
+        assert_eq!(vm.global("sum"), Some(&Value::number(44850.0)));
+
+        let locals: Vec<_> = (0..257).map(|i| format!("let a{} = {}", i, i)).collect();
+        let source = format!("{{\n{}\n}}", locals.join("\n"));
+        let err = compile_source(&source).unwrap_err();
+        assert!(err.to_string().contains("too many local variables"));
+
+        let body = "i = i + 1\n".repeat(7000);
+        let source = format!("let i = 0\nif (i < 1) {{\n{}}}", body);
+        let err = compile_source(&source).unwrap_err();
+        assert!(err.to_string().contains("too much code to jump over"));
+
+        let source = format!("let i = 0\nwhile (i < 1) {{\n{}}}", body);
+        let err = compile_source(&source).unwrap_err();
+        assert!(err.to_string().contains("loop body too large"));
+    }
+}
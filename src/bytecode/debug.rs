@@ -1,42 +1,116 @@
-use super::{Chunk, OpCode};
+use std::io::{self, Write};
+
+use super::{Chunk, OpCode, Value};
 
 pub fn disassemble_chunk(chunk: &Chunk, name: &str) {
-    println!("\n== {} ==", name);
+    write_chunk(&mut io::stdout(), chunk, name).expect("failed to write to stdout");
+}
+
+/**
+ * print the instruction at `offset`, returns the offset of the next one
+ */
+pub fn disassemble_instruction(chunk: &Chunk, offset: usize) -> usize {
+    write_instruction(&mut io::stdout(), chunk, offset).expect("failed to write to stdout")
+}
+
+pub fn write_chunk(out: &mut dyn Write, chunk: &Chunk, name: &str) -> io::Result<()> {
+    writeln!(out, "\n== {} ==", name)?;
+    write_code(out, chunk)
+}
 
-    for i in 0..chunk.codes.len() {
-        disassemble_instruction(chunk, i)
+fn write_code(out: &mut dyn Write, chunk: &Chunk) -> io::Result<()> {
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        offset = write_instruction(out, chunk, offset)?;
+    }
+    for handler in chunk.handlers.iter() {
+        writeln!(
+            out,
+            "try {:04}..{:04} -> {:04} (depth {})",
+            handler.start, handler.end, handler.target, handler.depth
+        )?;
     }
-    println!();
+    writeln!(out)?;
+
+    // nested functions
+    for constant in chunk.constants.iter() {
+        if let Some(function) = constant.as_function() {
+            writeln!(
+                out,
+                "\n== {} (arity {}) ==",
+                function.name(),
+                function.arity
+            )?;
+            write_code(out, &function.chunk)?;
+        }
+    }
+    Ok(())
 }
 
-pub fn disassemble_instruction(chunk: &Chunk, offset: usize) {
-    print!("{:04} ", offset);
+pub fn write_instruction(out: &mut dyn Write, chunk: &Chunk, offset: usize) -> io::Result<usize> {
+    write!(out, "{:04} ", offset)?;
 
-    let pos = &chunk.positions[offset];
-    print!(" <{}:{}> ", pos.0, pos.1);
+    let pos = chunk.position(offset);
+    write!(out, " <{}:{}> ", pos.0, pos.1)?;
 
     use OpCode::*;
-    let code = chunk.codes.get(offset).expect("chunk codes is empty");
+    let (code, next) = chunk.read(offset);
     match code {
-        OpReturn => simple_instruction(OpReturn),
-        OpConstant(idx) => constant_instruction(OpConstant(*idx), chunk),
-        OpNegate => simple_instruction(OpNegate),
-        OpAdd => simple_instruction(OpAdd),
-        OpSubtract => simple_instruction(OpSubtract),
-        OpMultiply => simple_instruction(OpMultiply),
-        OpDivide => simple_instruction(OpDivide),
-        // _ => println!("Unknown opcode {}", *code),
+        OpConstant(_) | OpConstantLong(_) | OpDefineGlobal(_) | OpGetGlobal(_) | OpSetGlobal(_)
+        | OpClass(_) | OpMethod(_) | OpGetProperty(..) | OpSetProperty(..) | OpGetSuper(_) => {
+            constant_instruction(out, code, chunk)?
+        }
+        OpInvoke(idx, argc, _) => invoke_instruction(out, code, idx, argc, chunk)?,
+        OpGetLocal(slot) | OpSetLocal(slot) | OpRethrow(slot) => slot_instruction(out, code, slot)?,
+        OpJump(jump) | OpJumpIfFalse(jump) => jump_instruction(out, code, offset, next + jump)?,
+        OpLoop(jump) => jump_instruction(out, code, offset, next - jump)?,
+        OpClosure(_) => closure_instruction(out, code, chunk)?,
+        OpCall(argc) => slot_instruction(out, code, argc)?,
+        OpGetUpvalue(idx) | OpSetUpvalue(idx) => slot_instruction(out, code, idx)?,
+        _ => simple_instruction(out, code)?,
     }
+    Ok(next)
+}
+
+fn closure_instruction(out: &mut dyn Write, code: OpCode, chunk: &Chunk) -> io::Result<()> {
+    constant_instruction(out, code, chunk)?;
+    let constant = chunk.constants.get(code.get_const_index().unwrap());
+    if let Some(function) = constant.and_then(Value::as_function) {
+        for upvalue in function.upvalues.iter() {
+            let kind = if upvalue.is_local { "local" } else { "upvalue" };
+            writeln!(out, "{:>22} {} {}", "|", kind, upvalue.index)?;
+        }
+    }
+    Ok(())
+}
+
+fn invoke_instruction(
+    out: &mut dyn Write,
+    code: OpCode,
+    idx: usize,
+    argc: usize,
+    chunk: &Chunk,
+) -> io::Result<()> {
+    let constant = chunk.constants.get(idx).unwrap();
+    writeln!(out, "{:<16} ({} args) {:4} '{}'", code, argc, idx, constant)
+}
+
+fn simple_instruction(out: &mut dyn Write, code: OpCode) -> io::Result<()> {
+    writeln!(out, "{}", code)
+}
+
+fn jump_instruction(out: &mut dyn Write, code: OpCode, from: usize, to: usize) -> io::Result<()> {
+    writeln!(out, "{:<16} {:04} -> {:04}", code, from, to)
 }
 
-fn simple_instruction(code: OpCode) {
-    println!("{}", code);
+fn slot_instruction(out: &mut dyn Write, code: OpCode, slot: usize) -> io::Result<()> {
+    writeln!(out, "{:<16} {:4}", code, slot)
 }
 
-fn constant_instruction(code: OpCode, chunk: &Chunk) {
+fn constant_instruction(out: &mut dyn Write, code: OpCode, chunk: &Chunk) -> io::Result<()> {
     let idx = code.get_const_index().unwrap();
     let constant = chunk.constants.get(idx).unwrap();
-    println!("{:<16} {:4} '{}'", code, idx, constant);
+    writeln!(out, "{:<16} {:4} '{}'", code, idx, constant)
 }
 
 #[cfg(test)]
@@ -0,0 +1,198 @@
+use super::{
+    object::{Class, Closure, Instance, Obj, ObjRef, Upvalue},
+    Value,
+};
+
+// first collection once this many bytes are allocated
+const GC_INITIAL_THRESHOLD: usize = 1024 * 1024;
+// the next collection happens when the live heap has grown this much
+const GC_HEAP_GROW_FACTOR: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct HeapStats {
+    pub bytes_allocated: usize,
+    pub objects: usize,
+    pub collections: usize,
+}
+
+/**
+ * objects owned by the vm: closures, upvalues, classes, instances, bound methods.
+ * they can reference each other in cycles, so they are reclaimed by a mark-and-sweep
+ * collector instead of reference counting. strings and functions are immutable
+ * and can't form cycles, they stay in `Rc`
+ */
+#[derive(Debug)]
+pub struct Heap {
+    objects: Vec<Option<Obj>>,
+    marks: Vec<bool>,
+    // freed slots, reused by the next allocations
+    free: Vec<usize>,
+    // marked objects whose references are not traced yet
+    gray: Vec<ObjRef>,
+    bytes_allocated: usize,
+    next_gc: usize,
+    collections: usize,
+    // collect before every allocation, to shake out missing roots
+    pub stress: bool,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self {
+            objects: vec![],
+            marks: vec![],
+            free: vec![],
+            gray: vec![],
+            bytes_allocated: 0,
+            next_gc: GC_INITIAL_THRESHOLD,
+            collections: 0,
+            stress: false,
+        }
+    }
+}
+
+impl Heap {
+    pub fn alloc(&mut self, obj: Obj) -> ObjRef {
+        self.bytes_allocated += obj.size();
+        match self.free.pop() {
+            Some(idx) => {
+                self.objects[idx] = Some(obj);
+                ObjRef(idx)
+            }
+            None => {
+                self.objects.push(Some(obj));
+                self.marks.push(false);
+                ObjRef(self.objects.len() - 1)
+            }
+        }
+    }
+
+    pub fn should_collect(&self) -> bool {
+        self.stress || self.bytes_allocated > self.next_gc
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        HeapStats {
+            bytes_allocated: self.bytes_allocated,
+            objects: self.objects.len() - self.free.len(),
+            collections: self.collections,
+        }
+    }
+
+    pub fn mark_value(&mut self, value: &Value) {
+        if let Some(r) = value.as_object() {
+            self.mark_object(r);
+        }
+    }
+
+    pub fn mark_object(&mut self, r: ObjRef) {
+        if !self.marks[r.0] {
+            self.marks[r.0] = true;
+            self.gray.push(r);
+        }
+    }
+
+    /**
+     * mark everything reachable from the marked roots, then free the rest
+     */
+    pub fn collect(&mut self) {
+        let mut refs = vec![];
+        while let Some(r) = self.gray.pop() {
+            self.get(r).references(&mut refs);
+            for r in refs.drain(..) {
+                self.mark_object(r);
+            }
+        }
+
+        let mut live = 0;
+        for (idx, slot) in self.objects.iter_mut().enumerate() {
+            if let Some(obj) = slot {
+                if self.marks[idx] {
+                    self.marks[idx] = false;
+                    live += obj.size();
+                } else {
+                    *slot = None;
+                    self.free.push(idx);
+                }
+            }
+        }
+
+        self.bytes_allocated = live;
+        self.next_gc = (live * GC_HEAP_GROW_FACTOR).max(GC_INITIAL_THRESHOLD);
+        self.collections += 1;
+    }
+
+    pub fn get(&self, r: ObjRef) -> &Obj {
+        self.objects[r.0]
+            .as_ref()
+            .expect("dangling object reference")
+    }
+
+    pub fn get_mut(&mut self, r: ObjRef) -> &mut Obj {
+        self.objects[r.0]
+            .as_mut()
+            .expect("dangling object reference")
+    }
+
+    /**
+     * display an object, like the tree-walker displays its values
+     */
+    pub fn format(&self, r: ObjRef) -> String {
+        match self.get(r) {
+            Obj::Closure(closure) => format!("<fn {}>", closure.function.name()),
+            Obj::BoundMethod(bound) => self.format(bound.method),
+            Obj::Upvalue(_) => "<upvalue>".to_string(),
+            Obj::Class(class) => format!("<class {}>", class.name),
+            Obj::Instance(instance) => format!("<instance of {}>", self.class(instance.class).name),
+        }
+    }
+
+    pub fn closure(&self, r: ObjRef) -> &Closure {
+        match self.get(r) {
+            Obj::Closure(closure) => closure,
+            obj => unreachable!("expect closure, found {}", obj.type_name()),
+        }
+    }
+
+    pub fn upvalue_mut(&mut self, r: ObjRef) -> &mut Upvalue {
+        match self.get_mut(r) {
+            Obj::Upvalue(upvalue) => upvalue,
+            obj => unreachable!("expect upvalue, found {}", obj.type_name()),
+        }
+    }
+
+    pub fn upvalue(&self, r: ObjRef) -> &Upvalue {
+        match self.get(r) {
+            Obj::Upvalue(upvalue) => upvalue,
+            obj => unreachable!("expect upvalue, found {}", obj.type_name()),
+        }
+    }
+
+    pub fn class(&self, r: ObjRef) -> &Class {
+        match self.get(r) {
+            Obj::Class(class) => class,
+            obj => unreachable!("expect class, found {}", obj.type_name()),
+        }
+    }
+
+    pub fn class_mut(&mut self, r: ObjRef) -> &mut Class {
+        match self.get_mut(r) {
+            Obj::Class(class) => class,
+            obj => unreachable!("expect class, found {}", obj.type_name()),
+        }
+    }
+
+    pub fn instance(&self, r: ObjRef) -> &Instance {
+        match self.get(r) {
+            Obj::Instance(instance) => instance,
+            obj => unreachable!("expect instance, found {}", obj.type_name()),
+        }
+    }
+
+    pub fn instance_mut(&mut self, r: ObjRef) -> &mut Instance {
+        match self.get_mut(r) {
+            Obj::Instance(instance) => instance,
+            obj => unreachable!("expect instance, found {}", obj.type_name()),
+        }
+    }
+}
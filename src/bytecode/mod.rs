@@ -1,9 +1,18 @@
+pub mod assembler;
 mod chunk;
+pub mod compiler;
 pub mod debug;
+pub mod heap;
+pub mod object;
 mod opcode;
+pub mod optimize;
+pub mod register;
+pub mod serialize;
+pub mod shape;
 mod value;
+pub mod verify;
 pub mod vm;
 
-pub use chunk::{Chunk, Pos};
+pub use chunk::{Chunk, Handler, Pos, PosRun};
 pub use opcode::OpCode;
-pub use value::Value;
+pub use value::{Unpacked, Value};
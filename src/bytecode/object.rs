@@ -0,0 +1,128 @@
+use std::{collections::HashMap, rc::Rc};
+
+use super::{shape::ShapeId, Chunk, Value};
+
+/**
+ * compiled function: immutable, shared by all closures created from it.
+ * lives in the constant pool of the enclosing chunk
+ */
+#[derive(Debug, Default)]
+pub struct Function {
+    pub name: Option<String>,
+    pub arity: usize,
+    // how to capture each upvalue when a closure is created
+    pub upvalues: Vec<UpvalueDesc>,
+    pub chunk: Chunk,
+}
+
+impl Function {
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or("script")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UpvalueDesc {
+    // true: a local of the enclosing function, false: an upvalue of the enclosing function
+    pub is_local: bool,
+    pub index: usize,
+}
+
+/**
+ * handle of an object living in the vm heap
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObjRef(pub(super) usize);
+
+#[derive(Debug)]
+pub enum Obj {
+    Closure(Closure),
+    Upvalue(Upvalue),
+    Class(Class),
+    Instance(Instance),
+    BoundMethod(BoundMethod),
+}
+
+impl Obj {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Obj::Closure(_) | Obj::BoundMethod(_) => "function",
+            Obj::Upvalue(_) => "upvalue",
+            Obj::Class(_) => "class",
+            Obj::Instance(_) => "instance",
+        }
+    }
+
+    // objects directly referenced by this one
+    pub fn references(&self, refs: &mut Vec<ObjRef>) {
+        fn value(value: &Value, refs: &mut Vec<ObjRef>) {
+            if let Some(r) = value.as_object() {
+                refs.push(r);
+            }
+        }
+        match self {
+            Obj::Closure(closure) => refs.extend(closure.upvalues.iter()),
+            Obj::Upvalue(Upvalue::Open(_)) => (),
+            Obj::Upvalue(Upvalue::Closed(closed)) => value(closed, refs),
+            Obj::Class(class) => refs.extend(class.methods.values()),
+            Obj::Instance(instance) => {
+                refs.push(instance.class);
+                instance.fields.iter().for_each(|v| value(v, refs));
+            }
+            Obj::BoundMethod(bound) => {
+                value(&bound.receiver, refs);
+                refs.push(bound.method);
+            }
+        }
+    }
+
+    // approximate memory used by the object, for the collection threshold
+    pub fn size(&self) -> usize {
+        use std::mem::size_of;
+        let extra = match self {
+            Obj::Closure(closure) => closure.upvalues.capacity() * size_of::<ObjRef>(),
+            Obj::Upvalue(_) | Obj::BoundMethod(_) => 0,
+            Obj::Class(class) => class.methods.capacity() * size_of::<(Rc<str>, ObjRef)>(),
+            Obj::Instance(instance) => instance.fields.capacity() * size_of::<Value>(),
+        };
+        size_of::<Obj>() + extra
+    }
+}
+
+#[derive(Debug)]
+pub struct Closure {
+    pub function: Rc<Function>,
+    pub upvalues: Vec<ObjRef>,
+}
+
+#[derive(Debug)]
+pub enum Upvalue {
+    // the variable is still on the stack, at this absolute slot
+    Open(usize),
+    // the variable left the stack, the upvalue owns it now
+    Closed(Value),
+}
+
+#[derive(Debug)]
+pub struct Class {
+    pub name: Rc<str>,
+    // method name -> closure, inherited methods are copied in
+    pub methods: HashMap<Rc<str>, ObjRef>,
+    // changes with the methods, unique among all classes of all vms
+    pub version: u64,
+}
+
+#[derive(Debug)]
+pub struct Instance {
+    pub class: ObjRef,
+    // the slot of each field
+    pub shape: ShapeId,
+    pub fields: Vec<Value>,
+}
+
+// method read from an instance, remembers its receiver
+#[derive(Debug)]
+pub struct BoundMethod {
+    pub receiver: Value,
+    pub method: ObjRef,
+}
@@ -1,22 +1,305 @@
 pub type ConstantIndex = usize;
+pub type SlotIndex = usize;
+// number of bytes to skip, counted from the next instruction
+pub type JumpOffset = usize;
+// inline cache of a property instruction, see `Chunk::caches`
+pub type CacheIndex = usize;
 
-#[derive(Debug)]
+// operand limits of the byte encoding
+pub const MAX_CONSTANTS: usize = 1 << 24;
+pub const MAX_SHORT_CONSTANTS: usize = 1 << 8;
+pub const MAX_SLOTS: usize = 1 << 8;
+pub const MAX_JUMP: usize = u16::MAX as usize;
+pub const MAX_CACHES: usize = 1 << 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum OpCode {
     OpReturn,
     OpConstant(ConstantIndex),
+    OpNull,
+    OpTrue,
+    OpFalse,
     OpNegate,
+    OpNot,
     OpAdd,
     OpSubtract,
     OpMultiply,
     OpDivide,
+    OpEqual,
+    OpNotEqual,
+    OpGreater,
+    OpGreaterEqual,
+    OpLess,
+    OpLessEqual,
+    OpPrint,
+    OpPop,
+    OpDefineGlobal(ConstantIndex),
+    OpGetGlobal(ConstantIndex),
+    OpSetGlobal(ConstantIndex),
+    OpGetLocal(SlotIndex),
+    OpSetLocal(SlotIndex),
+    OpJump(JumpOffset),
+    OpJumpIfFalse(JumpOffset),
+    OpLoop(JumpOffset),
+    OpCall(usize),
+    OpClosure(ConstantIndex),
+    OpGetUpvalue(usize),
+    OpSetUpvalue(usize),
+    OpCloseUpvalue,
+    OpClass(ConstantIndex),
+    OpInherit,
+    OpMethod(ConstantIndex),
+    // property name, inline cache
+    OpGetProperty(ConstantIndex, CacheIndex),
+    OpSetProperty(ConstantIndex, CacheIndex),
+    OpGetSuper(ConstantIndex),
+    // method name, argument count, inline cache
+    OpInvoke(ConstantIndex, usize, CacheIndex),
+    // constant index above the one byte operand of OP_CONSTANT
+    OpConstantLong(ConstantIndex),
+    // unwind to the handler of the thrown value, see `Chunk::handlers`
+    OpThrow,
+    // throw the exception held in the slot again, at the end of a finally block
+    OpRethrow(SlotIndex),
 }
 
 impl OpCode {
+    /**
+     * stable byte tag of the instruction, the first byte of its encoding.
+     * tags are part of the bytecode file format: only append new instructions
+     */
+    pub fn tag(&self) -> u8 {
+        use OpCode::*;
+        match self {
+            OpReturn => 0,
+            OpConstant(_) => 1,
+            OpNull => 2,
+            OpTrue => 3,
+            OpFalse => 4,
+            OpNegate => 5,
+            OpNot => 6,
+            OpAdd => 7,
+            OpSubtract => 8,
+            OpMultiply => 9,
+            OpDivide => 10,
+            OpEqual => 11,
+            OpNotEqual => 12,
+            OpGreater => 13,
+            OpGreaterEqual => 14,
+            OpLess => 15,
+            OpLessEqual => 16,
+            OpPrint => 17,
+            OpPop => 18,
+            OpDefineGlobal(_) => 19,
+            OpGetGlobal(_) => 20,
+            OpSetGlobal(_) => 21,
+            OpGetLocal(_) => 22,
+            OpSetLocal(_) => 23,
+            OpJump(_) => 24,
+            OpJumpIfFalse(_) => 25,
+            OpLoop(_) => 26,
+            OpCall(_) => 27,
+            OpClosure(_) => 28,
+            OpGetUpvalue(_) => 29,
+            OpSetUpvalue(_) => 30,
+            OpCloseUpvalue => 31,
+            OpClass(_) => 32,
+            OpInherit => 33,
+            OpMethod(_) => 34,
+            OpGetProperty(..) => 35,
+            OpSetProperty(..) => 36,
+            OpGetSuper(_) => 37,
+            OpInvoke(..) => 38,
+            OpConstantLong(_) => 39,
+            OpThrow => 40,
+            OpRethrow(_) => 41,
+        }
+    }
+
+    /**
+     * encoded size in bytes: the tag and its little-endian operands
+     */
+    pub fn size(&self) -> usize {
+        use OpCode::*;
+        match self {
+            OpConstant(_) | OpGetLocal(_) | OpSetLocal(_) | OpCall(_) | OpGetUpvalue(_)
+            | OpSetUpvalue(_) | OpRethrow(_) => 2,
+            OpJump(_) | OpJumpIfFalse(_) | OpLoop(_) => 3,
+            OpDefineGlobal(_) | OpGetGlobal(_) | OpSetGlobal(_) | OpClosure(_) | OpClass(_)
+            | OpMethod(_) | OpGetSuper(_) | OpConstantLong(_) => 4,
+            OpGetProperty(..) | OpSetProperty(..) => 6,
+            OpInvoke(..) => 7,
+            _ => 1,
+        }
+    }
+
+    /**
+     * append the encoding to `code`.
+     * the compiler checks the operand limits, an operand too large is a bug
+     */
+    pub fn write(&self, code: &mut Vec<u8>) {
+        use OpCode::*;
+        code.push(self.tag());
+        match *self {
+            OpConstant(a) => write_operand(code, a, 1),
+            OpDefineGlobal(a) => write_operand(code, a, 3),
+            OpGetGlobal(a) => write_operand(code, a, 3),
+            OpSetGlobal(a) => write_operand(code, a, 3),
+            OpGetLocal(a) => write_operand(code, a, 1),
+            OpSetLocal(a) => write_operand(code, a, 1),
+            OpJump(a) => write_operand(code, a, 2),
+            OpJumpIfFalse(a) => write_operand(code, a, 2),
+            OpLoop(a) => write_operand(code, a, 2),
+            OpCall(a) => write_operand(code, a, 1),
+            OpClosure(a) => write_operand(code, a, 3),
+            OpGetUpvalue(a) => write_operand(code, a, 1),
+            OpSetUpvalue(a) => write_operand(code, a, 1),
+            OpClass(a) => write_operand(code, a, 3),
+            OpMethod(a) => write_operand(code, a, 3),
+            OpGetProperty(a, c) | OpSetProperty(a, c) => {
+                write_operand(code, a, 3);
+                write_operand(code, c, 2);
+            }
+            OpGetSuper(a) => write_operand(code, a, 3),
+            OpInvoke(a, b, c) => {
+                write_operand(code, a, 3);
+                write_operand(code, b, 1);
+                write_operand(code, c, 2);
+            }
+            OpConstantLong(a) => write_operand(code, a, 3),
+            OpRethrow(a) => write_operand(code, a, 1),
+            _ => (),
+        }
+    }
+
+    /**
+     * decode the instruction at `offset`, with the offset of the next one.
+     * None for an unknown tag or a truncated instruction
+     */
+    #[inline(always)]
+    pub fn read(code: &[u8], offset: usize) -> Option<(OpCode, usize)> {
+        use OpCode::*;
+        let at = offset + 1;
+        Some(match *code.get(offset)? {
+            0 => (OpReturn, at),
+            1 => (OpConstant(read_operand(code, at, 1)?), at + 1),
+            2 => (OpNull, at),
+            3 => (OpTrue, at),
+            4 => (OpFalse, at),
+            5 => (OpNegate, at),
+            6 => (OpNot, at),
+            7 => (OpAdd, at),
+            8 => (OpSubtract, at),
+            9 => (OpMultiply, at),
+            10 => (OpDivide, at),
+            11 => (OpEqual, at),
+            12 => (OpNotEqual, at),
+            13 => (OpGreater, at),
+            14 => (OpGreaterEqual, at),
+            15 => (OpLess, at),
+            16 => (OpLessEqual, at),
+            17 => (OpPrint, at),
+            18 => (OpPop, at),
+            19 => (OpDefineGlobal(read_operand(code, at, 3)?), at + 3),
+            20 => (OpGetGlobal(read_operand(code, at, 3)?), at + 3),
+            21 => (OpSetGlobal(read_operand(code, at, 3)?), at + 3),
+            22 => (OpGetLocal(read_operand(code, at, 1)?), at + 1),
+            23 => (OpSetLocal(read_operand(code, at, 1)?), at + 1),
+            24 => (OpJump(read_operand(code, at, 2)?), at + 2),
+            25 => (OpJumpIfFalse(read_operand(code, at, 2)?), at + 2),
+            26 => (OpLoop(read_operand(code, at, 2)?), at + 2),
+            27 => (OpCall(read_operand(code, at, 1)?), at + 1),
+            28 => (OpClosure(read_operand(code, at, 3)?), at + 3),
+            29 => (OpGetUpvalue(read_operand(code, at, 1)?), at + 1),
+            30 => (OpSetUpvalue(read_operand(code, at, 1)?), at + 1),
+            31 => (OpCloseUpvalue, at),
+            32 => (OpClass(read_operand(code, at, 3)?), at + 3),
+            33 => (OpInherit, at),
+            34 => (OpMethod(read_operand(code, at, 3)?), at + 3),
+            35 => {
+                let a = read_operand(code, at, 3)?;
+                let c = read_operand(code, at + 3, 2)?;
+                (OpGetProperty(a, c), at + 5)
+            }
+            36 => {
+                let a = read_operand(code, at, 3)?;
+                let c = read_operand(code, at + 3, 2)?;
+                (OpSetProperty(a, c), at + 5)
+            }
+            37 => (OpGetSuper(read_operand(code, at, 3)?), at + 3),
+            38 => {
+                let a = read_operand(code, at, 3)?;
+                let b = read_operand(code, at + 3, 1)?;
+                let c = read_operand(code, at + 4, 2)?;
+                (OpInvoke(a, b, c), at + 6)
+            }
+            39 => (OpConstantLong(read_operand(code, at, 3)?), at + 3),
+            40 => (OpThrow, at),
+            41 => (OpRethrow(read_operand(code, at, 1)?), at + 1),
+            _ => return None,
+        })
+    }
+
     pub fn get_const_index(&self) -> Option<ConstantIndex> {
-        if let Self::OpConstant(idx) = self {
-            Some(*idx)
-        } else {
-            None
+        match self {
+            Self::OpConstant(idx)
+            | Self::OpConstantLong(idx)
+            | Self::OpDefineGlobal(idx)
+            | Self::OpGetGlobal(idx)
+            | Self::OpSetGlobal(idx)
+            | Self::OpClosure(idx)
+            | Self::OpClass(idx)
+            | Self::OpMethod(idx)
+            | Self::OpGetProperty(idx, _)
+            | Self::OpSetProperty(idx, _)
+            | Self::OpGetSuper(idx)
+            | Self::OpInvoke(idx, ..) => Some(*idx),
+            _ => None,
+        }
+    }
+
+    pub fn get_cache_index(&self) -> Option<CacheIndex> {
+        match self {
+            Self::OpGetProperty(_, cache)
+            | Self::OpSetProperty(_, cache)
+            | Self::OpInvoke(_, _, cache) => Some(*cache),
+            _ => None,
+        }
+    }
+
+    /**
+     * the same instruction with another inline cache
+     */
+    pub fn with_cache_index(self, cache: CacheIndex) -> Self {
+        match self {
+            Self::OpGetProperty(idx, _) => Self::OpGetProperty(idx, cache),
+            Self::OpSetProperty(idx, _) => Self::OpSetProperty(idx, cache),
+            Self::OpInvoke(idx, argc, _) => Self::OpInvoke(idx, argc, cache),
+            code => code,
+        }
+    }
+
+    /**
+     * the same instruction with another constant index,
+     * constant loads switch between the short and long form as needed
+     */
+    pub fn with_const_index(self, idx: ConstantIndex) -> Self {
+        match self {
+            Self::OpConstant(_) | Self::OpConstantLong(_) if idx < MAX_SHORT_CONSTANTS => {
+                Self::OpConstant(idx)
+            }
+            Self::OpConstant(_) | Self::OpConstantLong(_) => Self::OpConstantLong(idx),
+            Self::OpDefineGlobal(_) => Self::OpDefineGlobal(idx),
+            Self::OpGetGlobal(_) => Self::OpGetGlobal(idx),
+            Self::OpSetGlobal(_) => Self::OpSetGlobal(idx),
+            Self::OpClosure(_) => Self::OpClosure(idx),
+            Self::OpClass(_) => Self::OpClass(idx),
+            Self::OpMethod(_) => Self::OpMethod(idx),
+            Self::OpGetProperty(_, cache) => Self::OpGetProperty(idx, cache),
+            Self::OpSetProperty(_, cache) => Self::OpSetProperty(idx, cache),
+            Self::OpGetSuper(_) => Self::OpGetSuper(idx),
+            Self::OpInvoke(_, argc, cache) => Self::OpInvoke(idx, argc, cache),
+            code => code,
         }
     }
 }
@@ -27,11 +310,68 @@ impl std::fmt::Display for OpCode {
         match self {
             OpReturn => write!(f, "OP_RETURN"),
             OpConstant(_) => write!(f, "OP_CONSTANT"),
+            OpNull => write!(f, "OP_NULL"),
+            OpTrue => write!(f, "OP_TRUE"),
+            OpFalse => write!(f, "OP_FALSE"),
             OpNegate => write!(f, "OP_NEGATE"),
+            OpNot => write!(f, "OP_NOT"),
             OpAdd => write!(f, "OP_ADD"),
             OpSubtract => write!(f, "OP_SUBTRACT"),
             OpMultiply => write!(f, "OP_MULTIPLY"),
             OpDivide => write!(f, "OP_DIVIDE"),
+            OpEqual => write!(f, "OP_EQUAL"),
+            OpNotEqual => write!(f, "OP_NOT_EQUAL"),
+            OpGreater => write!(f, "OP_GREATER"),
+            OpGreaterEqual => write!(f, "OP_GREATER_EQUAL"),
+            OpLess => write!(f, "OP_LESS"),
+            OpLessEqual => write!(f, "OP_LESS_EQUAL"),
+            OpPrint => write!(f, "OP_PRINT"),
+            OpPop => write!(f, "OP_POP"),
+            OpDefineGlobal(_) => write!(f, "OP_DEFINE_GLOBAL"),
+            OpGetGlobal(_) => write!(f, "OP_GET_GLOBAL"),
+            OpSetGlobal(_) => write!(f, "OP_SET_GLOBAL"),
+            OpGetLocal(_) => write!(f, "OP_GET_LOCAL"),
+            OpSetLocal(_) => write!(f, "OP_SET_LOCAL"),
+            OpJump(_) => write!(f, "OP_JUMP"),
+            OpJumpIfFalse(_) => write!(f, "OP_JUMP_IF_FALSE"),
+            OpLoop(_) => write!(f, "OP_LOOP"),
+            OpCall(_) => write!(f, "OP_CALL"),
+            OpClosure(_) => write!(f, "OP_CLOSURE"),
+            OpGetUpvalue(_) => write!(f, "OP_GET_UPVALUE"),
+            OpSetUpvalue(_) => write!(f, "OP_SET_UPVALUE"),
+            OpCloseUpvalue => write!(f, "OP_CLOSE_UPVALUE"),
+            OpClass(_) => write!(f, "OP_CLASS"),
+            OpInherit => write!(f, "OP_INHERIT"),
+            OpMethod(_) => write!(f, "OP_METHOD"),
+            OpGetProperty(..) => write!(f, "OP_GET_PROPERTY"),
+            OpSetProperty(..) => write!(f, "OP_SET_PROPERTY"),
+            OpGetSuper(_) => write!(f, "OP_GET_SUPER"),
+            OpInvoke(..) => write!(f, "OP_INVOKE"),
+            OpConstantLong(_) => write!(f, "OP_CONSTANT_LONG"),
+            OpThrow => write!(f, "OP_THROW"),
+            OpRethrow(_) => write!(f, "OP_RETHROW"),
         }
     }
 }
+
+// little-endian operand of 1 to 3 bytes
+fn write_operand(code: &mut Vec<u8>, value: usize, width: usize) {
+    assert!(
+        value < 1 << (8 * width),
+        "operand {} overflows {} bytes",
+        value,
+        width
+    );
+    code.extend_from_slice(&value.to_le_bytes()[..width]);
+}
+
+#[inline(always)]
+fn read_operand(code: &[u8], at: usize, width: usize) -> Option<usize> {
+    let bytes = code.get(at..at + width)?;
+    Some(
+        bytes
+            .iter()
+            .rev()
+            .fold(0, |acc, b| (acc << 8) | *b as usize),
+    )
+}
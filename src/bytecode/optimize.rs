@@ -0,0 +1,592 @@
+use std::{collections::HashMap, rc::Rc};
+
+use super::{
+    object::Function,
+    opcode::{ConstantIndex, MAX_JUMP},
+    Chunk, Handler, OpCode,
+    OpCode::*,
+    Pos, Unpacked, Value,
+};
+
+/**
+ * optimized copy of a verified chunk and of the functions in its constants:
+ * constant arithmetic is folded, values pushed only to be popped are dropped,
+ * jumps landing on jumps go straight to the final target, and the constants
+ * are deduplicated. the remaining instructions keep their source positions,
+ * and the exception handlers cover the same instructions
+ */
+pub fn optimize(chunk: &Chunk) -> Chunk {
+    let mut constants: Vec<Value> = chunk
+        .constants
+        .iter()
+        .map(|constant| match constant.as_function() {
+            Some(function) => Value::function(Rc::new(Function {
+                name: function.name.clone(),
+                arity: function.arity,
+                upvalues: function.upvalues.clone(),
+                chunk: optimize(&function.chunk),
+            })),
+            None => constant.clone(),
+        })
+        .collect();
+
+    let (insts, mut handlers) = decode(chunk);
+    let mut insts = collapse_jumps(insts);
+    // dropping code can turn a jump into a jump to the next instruction
+    loop {
+        let len = insts.len();
+        insts = peephole(insts, &mut handlers, &mut constants);
+        if insts.len() == len {
+            break;
+        }
+    }
+    // a constant load grew to the long form and pushed a jump out of range:
+    // keep the code as it was
+    encode(&insts, &handlers, &constants).unwrap_or_else(|| chunk.clone())
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Inst {
+    code: OpCode,
+    pos: Pos,
+    // index of the instruction a jump goes to, the end of the code is `len`
+    target: Option<usize>,
+}
+
+// the instructions, and the handlers with instruction indices instead of offsets
+fn decode(chunk: &Chunk) -> (Vec<Inst>, Vec<Handler>) {
+    let offsets: Vec<usize> = chunk.instructions().map(|(at, _)| at).collect();
+    // the end of the code isn't an instruction
+    let index = |offset: usize| offsets.binary_search(&offset).unwrap_or_else(|end| end);
+    let handlers = chunk
+        .handlers
+        .iter()
+        .map(|handler| Handler {
+            start: index(handler.start),
+            end: index(handler.end),
+            target: index(handler.target),
+            depth: handler.depth,
+        })
+        .collect();
+    let insts = chunk
+        .instructions()
+        .map(|(at, code)| {
+            let next = at + code.size();
+            let target = match code {
+                OpJump(offset) | OpJumpIfFalse(offset) => Some(index(next + offset)),
+                OpLoop(offset) => Some(index(next - offset)),
+                _ => None,
+            };
+            Inst {
+                code,
+                pos: chunk.position(at),
+                target,
+            }
+        })
+        .collect();
+    (insts, handlers)
+}
+
+// a jump landing on another jump goes where that one goes. a false condition
+// stays on the stack, so after OP_JUMP_IF_FALSE the next one jumps too
+fn collapse_jumps(mut insts: Vec<Inst>) -> Vec<Inst> {
+    for i in 0..insts.len() {
+        let Some(mut target) = insts[i].target else {
+            continue;
+        };
+        let conditional = matches!(insts[i].code, OpJumpIfFalse(_));
+        // jumps in a cycle never reach a final target
+        for _ in 0..insts.len() {
+            match insts.get(target) {
+                Some(Inst {
+                    code: OpJump(_) | OpLoop(_),
+                    target: Some(next),
+                    ..
+                }) => target = *next,
+                Some(Inst {
+                    code: OpJumpIfFalse(_),
+                    target: Some(next),
+                    ..
+                }) if conditional => target = *next,
+                _ => break,
+            }
+        }
+        // OP_JUMP_IF_FALSE only goes forward
+        if !conditional || target > i {
+            insts[i].target = Some(target);
+        }
+    }
+    insts
+}
+
+// rewrite the code in one pass, looking back at what is already emitted.
+// instructions are only merged or dropped when no jump lands between them,
+// and no handler range starts or ends between them
+fn peephole(
+    insts: Vec<Inst>,
+    handlers: &mut Vec<Handler>,
+    constants: &mut Vec<Value>,
+) -> Vec<Inst> {
+    let mut labels = vec![false; insts.len() + 1];
+    for target in insts.iter().filter_map(|inst| inst.target) {
+        labels[target] = true;
+    }
+    for handler in handlers.iter() {
+        labels[handler.start] = true;
+        labels[handler.end] = true;
+        labels[handler.target] = true;
+    }
+    // (instruction, is a jump target)
+    let mut out: Vec<(Inst, bool)> = Vec::with_capacity(insts.len());
+    // new index of each instruction, a dropped one maps to what follows it
+    let mut map = Vec::with_capacity(insts.len() + 1);
+    // a dropped jump target, its jumps go to the next instruction
+    let mut pending = false;
+
+    for (i, inst) in insts.into_iter().enumerate() {
+        map.push(out.len());
+        let labelled = labels[i] || std::mem::take(&mut pending);
+        let n = out.len();
+
+        if inst.target == Some(i + 1) {
+            // a jump to the next instruction does nothing
+            pending = labelled;
+        } else if inst.code == OpPop && !labelled && n > 0 && is_pure_push(out[n - 1].0.code) {
+            pending = out.pop().unwrap().1;
+        } else if let Some((value, count)) = fold(inst.code, &out, constants).filter(|_| !labelled)
+        {
+            // the folded value replaces the literals, and takes their jumps
+            let (first, label) = out[n - count];
+            out.truncate(n - count);
+            out.push((load(value, first.pos, constants), label));
+        } else {
+            out.push((inst, labelled));
+        }
+    }
+    map.push(out.len());
+
+    for handler in handlers.iter_mut() {
+        handler.start = map[handler.start];
+        handler.end = map[handler.end];
+        handler.target = map[handler.target];
+    }
+    // a range of dropped code
+    handlers.retain(|handler| handler.start < handler.end);
+
+    out.into_iter()
+        .map(|(mut inst, _)| {
+            inst.target = inst.target.map(|target| map[target]);
+            inst
+        })
+        .collect()
+}
+
+// pushes a value and does nothing else
+fn is_pure_push(code: OpCode) -> bool {
+    matches!(
+        code,
+        OpConstant(_)
+            | OpConstantLong(_)
+            | OpNull
+            | OpTrue
+            | OpFalse
+            | OpGetLocal(_)
+            | OpGetUpvalue(_)
+    )
+}
+
+// value pushed by a literal instruction
+fn literal(code: OpCode, constants: &[Value]) -> Option<Value> {
+    match code {
+        OpConstant(idx) | OpConstantLong(idx) => match constants[idx].unpack() {
+            Unpacked::Number(_) | Unpacked::String(_) => Some(constants[idx].clone()),
+            _ => None,
+        },
+        OpNull => Some(Value::NULL),
+        OpTrue => Some(Value::boolean(true)),
+        OpFalse => Some(Value::boolean(false)),
+        _ => None,
+    }
+}
+
+// an operation on the literals on top of the emitted code, with the number
+// of literals it replaces. nothing may jump between them
+fn fold(code: OpCode, out: &[(Inst, bool)], constants: &[Value]) -> Option<(Value, usize)> {
+    let operand = |inst: &Inst| literal(inst.code, constants);
+    match (code, out) {
+        (OpNegate | OpNot, [.., (x, _)]) => Some((fold_unary(code, &operand(x)?)?, 1)),
+        (_, [.., (x, _), (y, false)]) => Some((fold_binary(code, &operand(x)?, &operand(y)?)?, 2)),
+        _ => None,
+    }
+}
+
+// same results as the vm, operations that fail at runtime aren't folded
+fn fold_unary(code: OpCode, x: &Value) -> Option<Value> {
+    match (code, x.as_number()) {
+        (OpNegate, Some(n)) => Some(Value::number(-n)),
+        (OpNot, _) => Some(Value::boolean(!x.is_truthy())),
+        _ => None,
+    }
+}
+
+fn fold_binary(code: OpCode, x: &Value, y: &Value) -> Option<Value> {
+    Some(match (code, x.unpack(), y.unpack()) {
+        (OpEqual, _, _) => Value::boolean(x == y),
+        (OpNotEqual, _, _) => Value::boolean(x != y),
+        (OpAdd, Unpacked::String(x), Unpacked::String(y)) => {
+            Value::string(format!("{}{}", x, y).into())
+        }
+        (code, Unpacked::Number(x), Unpacked::Number(y)) => match code {
+            OpAdd => Value::number(x + y),
+            OpSubtract => Value::number(x - y),
+            OpMultiply => Value::number(x * y),
+            OpDivide => Value::number(x / y),
+            OpGreater => Value::boolean(x > y),
+            OpGreaterEqual => Value::boolean(x >= y),
+            OpLess => Value::boolean(x < y),
+            OpLessEqual => Value::boolean(x <= y),
+            _ => return None,
+        },
+        _ => return None,
+    })
+}
+
+// instruction pushing a folded value
+fn load(value: Value, pos: Pos, constants: &mut Vec<Value>) -> Inst {
+    let code = match value.unpack() {
+        Unpacked::Null => OpNull,
+        Unpacked::Boolean(true) => OpTrue,
+        Unpacked::Boolean(false) => OpFalse,
+        _ => {
+            constants.push(value);
+            OpConstant(constants.len() - 1)
+        }
+    };
+    Inst {
+        code,
+        pos,
+        target: None,
+    }
+}
+
+// constants are the same if they are the same value, functions are never merged.
+// numbers compare by bits to keep 0 and -0 apart
+#[derive(PartialEq, Eq, Hash)]
+enum ConstantKey {
+    Number(u64),
+    String(Rc<str>),
+    Other(ConstantIndex),
+}
+
+fn encode(insts: &[Inst], handlers: &[Handler], constants: &[Value]) -> Option<Chunk> {
+    let mut chunk = Chunk::new();
+    // the constants still used, in order of first use
+    let mut indices: HashMap<ConstantKey, ConstantIndex> = HashMap::new();
+    let codes: Vec<OpCode> = insts
+        .iter()
+        .map(|inst| {
+            // the remaining property instructions get fresh caches, in order
+            let code = match inst.code.get_cache_index() {
+                Some(_) => inst.code.with_cache_index(chunk.add_cache()),
+                None => inst.code,
+            };
+            let Some(idx) = code.get_const_index() else {
+                return code;
+            };
+            let key = match constants[idx].unpack() {
+                Unpacked::Number(n) => ConstantKey::Number(n.to_bits()),
+                Unpacked::String(s) => ConstantKey::String(s.clone()),
+                _ => ConstantKey::Other(idx),
+            };
+            let new = *indices
+                .entry(key)
+                .or_insert_with(|| chunk.add_constant(constants[idx].clone()));
+            code.with_const_index(new)
+        })
+        .collect();
+
+    // jumps have the same size whatever their operand
+    let mut offsets = Vec::with_capacity(codes.len() + 1);
+    let mut offset = 0;
+    for code in codes.iter() {
+        offsets.push(offset);
+        offset += code.size();
+    }
+    offsets.push(offset);
+
+    for (i, (inst, code)) in insts.iter().zip(codes).enumerate() {
+        let code = match inst.target {
+            Some(target) => {
+                let (next, to) = (offsets[i + 1], offsets[target]);
+                match code {
+                    OpJumpIfFalse(_) => OpJumpIfFalse(to.checked_sub(next)?),
+                    _ if to >= next => OpJump(to - next),
+                    _ => OpLoop(next - to),
+                }
+            }
+            None => code,
+        };
+        if let OpJump(offset) | OpJumpIfFalse(offset) | OpLoop(offset) = code {
+            if offset > MAX_JUMP {
+                return None;
+            }
+        }
+        chunk.write(code, inst.pos);
+    }
+    chunk.handlers = handlers
+        .iter()
+        .map(|handler| Handler {
+            start: offsets[handler.start],
+            end: offsets[handler.end],
+            target: offsets[handler.target],
+            depth: handler.depth,
+        })
+        .collect();
+    Some(chunk)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        bytecode::{assembler::assemble, compiler::compile, verify::verify, vm::Vm},
+        lexer::Lexer,
+        parser::parser::Parser,
+    };
+
+    use super::*;
+
+    fn compile_source(source: &str) -> Chunk {
+        let lexer = Lexer::new(source.as_bytes(), "test.tx");
+        let ast = Parser::new(lexer).parse().unwrap();
+        compile(&ast).unwrap()
+    }
+
+    fn codes(chunk: &Chunk) -> Vec<OpCode> {
+        chunk.instructions().map(|(_, code)| code).collect()
+    }
+
+    #[test]
+    fn constant_folding() {
+        let chunk = optimize(&compile_source(
+            "let x = 1 + 2 * -3\nlet y = !(\"a\" + \"b\" == \"ab\")",
+        ));
+        assert_eq!(
+            codes(&chunk),
+            vec![OpConstant(0), OpDefineGlobal(1), OpFalse, OpDefineGlobal(2)]
+        );
+        assert_eq!(chunk.constants, vec![(-5).into(), "x".into(), "y".into()]);
+        // the folded value is located at the start of the expression
+        assert_eq!(chunk.position(0), (1, 9));
+
+        // left for the vm to fail on
+        let chunk = optimize(&compile_source("let x = \"a\" - 2 * 3"));
+        assert_eq!(
+            codes(&chunk),
+            vec![OpConstant(0), OpConstant(1), OpSubtract, OpDefineGlobal(2)]
+        );
+        assert_eq!(chunk.constants[1], 6.into());
+    }
+
+    #[test]
+    fn push_pop() {
+        let chunk = optimize(&compile_source("1 + 2\nnull\n{ let a = g\na }"));
+        assert_eq!(codes(&chunk), vec![OpGetGlobal(0), OpPop]);
+    }
+
+    #[test]
+    fn jump_chains() {
+        let chunk = assemble(
+            r#"
+            OP_FALSE
+            OP_JUMP_IF_FALSE one
+            OP_POP
+            OP_TRUE
+            one:
+            OP_JUMP_IF_FALSE two
+            OP_POP
+            OP_TRUE
+            two:
+            OP_JUMP end
+            OP_NULL
+            OP_POP
+            end:
+            OP_POP
+            "#,
+        )
+        .unwrap();
+        let optimized = optimize(&chunk);
+        verify(&optimized).unwrap();
+        assert_eq!(
+            codes(&optimized),
+            vec![
+                OpFalse,
+                OpJumpIfFalse(7),
+                OpPop,
+                OpTrue,
+                OpJumpIfFalse(2),
+                OpPop,
+                OpTrue,
+                OpPop
+            ]
+        );
+
+        // a jump to a loop goes back itself
+        let chunk = assemble(
+            r#"
+            start:
+            OP_FALSE
+            OP_JUMP_IF_FALSE exit
+            OP_POP
+            OP_JUMP back
+            OP_CONSTANT 1
+            OP_POP
+            back:
+            OP_LOOP start
+            exit:
+            OP_POP
+            "#,
+        )
+        .unwrap();
+        let optimized = optimize(&chunk);
+        verify(&optimized).unwrap();
+        assert_eq!(
+            codes(&optimized),
+            vec![
+                OpFalse,
+                OpJumpIfFalse(7),
+                OpPop,
+                OpLoop(8),
+                OpLoop(11),
+                OpPop
+            ]
+        );
+        assert!(optimized.constants.is_empty());
+    }
+
+    #[test]
+    fn constants() {
+        let chunk = compile_source(
+            "let a = 2\nlet b = 2\nlet c = a + b + 0 * -1 + 0\nlet d = \"a\" + \"a\"",
+        );
+        let optimized = optimize(&chunk);
+        assert!(optimized.constants.len() < chunk.constants.len());
+        assert_eq!(
+            optimized.constants,
+            vec![
+                2.into(),
+                "a".into(),
+                "b".into(),
+                (-0.0).into(),
+                0.into(),
+                "c".into(),
+                "aa".into(),
+                "d".into()
+            ]
+        );
+    }
+
+    // code bytes, nested functions included
+    fn code_size(chunk: &Chunk) -> usize {
+        let nested = chunk
+            .constants
+            .iter()
+            .map(|constant| match constant.as_function() {
+                Some(function) => code_size(&function.chunk),
+                None => 0,
+            });
+        chunk.code.len() + nested.sum::<usize>()
+    }
+
+    // (result, runtime error) of a program
+    fn run(chunk: Chunk) -> (Option<String>, Option<String>) {
+        verify(&chunk).unwrap();
+        let mut vm = Vm::new(chunk).with_stress_gc(true);
+        let error = vm.run().err().map(|e| e.to_string());
+        (vm.global("result").map(|value| vm.format(value)), error)
+    }
+
+    #[test]
+    fn differential() {
+        let programs = [
+            r#"
+            fn fib(n) {
+                if (n < 2) { return n }
+                return fib(n - 1) + fib(n - 2)
+            }
+            let result = fib(12) + 2 * 3 - -1
+            "#,
+            r#"
+            let result = 0
+            let i = 0
+            while (i < 20 && !false) {
+                if (i == 3 || i == 5 || 1 > 2) result = result + 100;
+                else if (i > 6 && i <= 8 && true) result = result + 10;
+                else result = result + 1 / 2
+                i = i + 1
+                null
+                1 + 1
+            }
+            "#,
+            r#"
+            fn make(n) {
+                let total = 0 * n
+                fn add(x) {
+                    total = total + x + n * (2 - 1)
+                    return total
+                }
+                return add
+            }
+            let add = make(1)
+            add(2)
+            let result = add(3) + 0
+            "#,
+            r#"
+            class Point {
+                init(x, y) {
+                    this.x = x
+                    this.y = y + 0
+                }
+                sum() { return this.x + this.y * (1 + 1) }
+            }
+            let p = Point(1, 2)
+            let result = p.sum() + Point(3, 4).sum()
+            "#,
+            r#"
+            let result = "a" + "b" + ("c" + "d")
+            let s = result == "abcd"
+            result = result + "e"
+            "#,
+            r#"
+            fn f(x) { return x - (1 + 2) }
+            let result = 1 + 2
+            f("three")
+            "#,
+            r#"
+            let result = (1 == 1) == !null
+            let x = -"a" + 1
+            "#,
+            r#"
+            let result = 0
+            fn risky(n) {
+                try {
+                    if (n > 1 + 1) throw n * (2 + 3)
+                    return n
+                } catch (e) {
+                    1 + 1
+                    return e
+                } finally {
+                    result = result + 1 * 1
+                }
+            }
+            result = result + risky(1) + risky(3)
+            try { throw "a" + "b" } finally { result = result + 1 }
+            "#,
+        ];
+        for program in programs {
+            let chunk = compile_source(program);
+            let optimized = optimize(&chunk);
+            assert!(code_size(&optimized) < code_size(&chunk), "{}", program);
+            assert_eq!(run(optimized), run(chunk), "{}", program);
+        }
+    }
+}
@@ -0,0 +1,794 @@
+use crate::{
+    analizer::resolver::Resolver,
+    ast::*,
+    error::CompileError,
+    interpreter::visitor::{ExprVisitor, StmtVisitor},
+    position::{Loc, Pos, Span},
+    token::Operator,
+};
+
+use std::rc::Rc;
+
+use super::{
+    Instruction, Instruction::*, Module, Proto, Reg, Value, MAX_CONSTANTS, MAX_FUNCTIONS,
+    MAX_REGISTERS,
+};
+use crate::bytecode::compiler::CompileResult;
+
+/**
+ * lower the ast into register code
+ */
+pub fn compile(program: &Program) -> CompileResult<Module> {
+    Resolver::new(&mut ())
+        .resolve(program)
+        .map_err(CompileError::Resolve)?;
+
+    let mut compiler = Compiler::new(program.file.as_deref().unwrap_or("<script>"));
+    for stmt in program.body.iter() {
+        compiler.compile_stmt(stmt)?;
+    }
+    let script = compiler.states.pop().unwrap().proto;
+    Ok(Module {
+        script: Rc::new(script),
+        functions: compiler.functions,
+    })
+}
+
+// local variable pinned to a register for its whole scope
+struct Local {
+    name: String,
+    depth: usize,
+    reg: Reg,
+}
+
+// the function being compiled, nested declarations push a new state
+struct FunctionState {
+    proto: Proto,
+    locals: Vec<Local>,
+    scope_depth: usize,
+    // first free register: the locals are below, temporaries are allocated from here
+    top: usize,
+}
+
+impl FunctionState {
+    fn new(name: Option<String>, params: &[Identifier]) -> Self {
+        let mut locals = vec![];
+        // r0 holds the callee, the parameters follow it.
+        // the script has no callee, its registers start at 0
+        if name.is_some() {
+            locals.push(Local {
+                name: "".to_string(),
+                depth: 0,
+                reg: 0,
+            });
+            for (i, param) in params.iter().enumerate() {
+                locals.push(Local {
+                    name: param.name.clone(),
+                    depth: 0,
+                    reg: (i + 1) as Reg,
+                });
+            }
+        }
+        let top = locals.len();
+        Self {
+            proto: Proto {
+                name,
+                arity: params.len(),
+                registers: top,
+                ..Default::default()
+            },
+            locals,
+            scope_depth: 0,
+            top,
+        }
+    }
+
+    fn resolve_local(&self, name: &str) -> Option<Reg> {
+        self.locals
+            .iter()
+            .rev()
+            .find(|local| local.name == name)
+            .map(|local| local.reg)
+    }
+}
+
+pub struct Compiler {
+    states: Vec<FunctionState>,
+    // every nested function, `Function` instructions index it
+    functions: Vec<Rc<Proto>>,
+    // where the expression being compiled puts its value
+    dst: Reg,
+    filename: String,
+    // position of the node being compiled,
+    // used by nodes without span (eg: boolean literal)
+    pos: Pos,
+}
+
+impl Compiler {
+    pub fn new(filename: &str) -> Self {
+        Self {
+            states: vec![FunctionState::new(None, &[])],
+            functions: vec![],
+            dst: 0,
+            filename: filename.to_string(),
+            pos: Pos::new(1, 1),
+        }
+    }
+
+    fn compile_stmt(&mut self, stmt: &Statement) -> CompileResult<()> {
+        self.walk_stmt(stmt)
+    }
+
+    // compile `expr`, its value ends up in `dst`
+    fn compile_expr(&mut self, expr: &Expr, dst: Reg) -> CompileResult<()> {
+        let outer = std::mem::replace(&mut self.dst, dst);
+        let result = self.walk_expr(expr);
+        self.dst = outer;
+        result
+    }
+
+    // register holding the value of `expr`: a local is read in place,
+    // anything else goes into a new temporary
+    fn operand(&mut self, expr: &Expr) -> CompileResult<Reg> {
+        if let Expr::Identifier(ident) = expr {
+            if let Some(reg) = self.state().resolve_local(&ident.name) {
+                return Ok(reg);
+            }
+        }
+        let reg = self.alloc()?;
+        self.compile_expr(expr, reg)?;
+        Ok(reg)
+    }
+
+    fn state(&mut self) -> &mut FunctionState {
+        self.states.last_mut().unwrap()
+    }
+
+    fn alloc(&mut self) -> CompileResult<Reg> {
+        let state = self.state();
+        if state.top >= MAX_REGISTERS {
+            return self.error("too many registers in function");
+        }
+        let reg = state.top as Reg;
+        state.top += 1;
+        state.proto.registers = state.proto.registers.max(state.top);
+        Ok(reg)
+    }
+
+    // release the temporaries allocated since `top`
+    fn free(&mut self, top: usize) {
+        self.state().top = top;
+    }
+
+    fn is_local(&mut self, reg: Reg) -> bool {
+        self.state().locals.iter().any(|local| local.reg == reg)
+    }
+
+    fn emit(&mut self, instruction: Instruction) -> usize {
+        let pos = (self.pos.ln, self.pos.col);
+        let proto = &mut self.state().proto;
+        proto.code.push(instruction);
+        proto.positions.push(pos);
+        proto.code.len() - 1
+    }
+
+    // make the jump at `at` land on the next emitted instruction
+    fn patch_jump(&mut self, at: usize) {
+        let proto = &mut self.state().proto;
+        let target = proto.code.len() as u32;
+        proto.code[at] = match proto.code[at] {
+            Jump(_) => Jump(target),
+            JumpIfFalse(reg, _) => JumpIfFalse(reg, target),
+            JumpIfTrue(reg, _) => JumpIfTrue(reg, target),
+            instruction => unreachable!("not a jump: {}", instruction),
+        };
+    }
+
+    fn make_constant(&mut self, value: Value) -> CompileResult<u16> {
+        let constants = &mut self.state().proto.constants;
+        if constants.len() >= MAX_CONSTANTS {
+            return self.error("too many constants in one function");
+        }
+        constants.push(value);
+        Ok((constants.len() - 1) as u16)
+    }
+
+    fn identifier_constant(&mut self, name: &str) -> CompileResult<u16> {
+        self.make_constant(Value::string(name.into()))
+    }
+
+    fn load_constant(&mut self, value: Value) -> CompileResult<()> {
+        let idx = self.make_constant(value)?;
+        self.emit(LoadConst(self.dst, idx));
+        Ok(())
+    }
+
+    fn emit_return(&mut self) -> CompileResult<()> {
+        let reg = self.alloc()?;
+        self.emit(LoadNull(reg));
+        self.emit(Return(reg));
+        Ok(())
+    }
+
+    fn begin_scope(&mut self) {
+        self.state().scope_depth += 1;
+    }
+
+    // the registers of the scope's locals are free again
+    fn end_scope(&mut self) {
+        let state = self.state();
+        state.scope_depth -= 1;
+        let depth = state.scope_depth;
+        while let Some(local) = state.locals.last() {
+            if local.depth <= depth {
+                break;
+            }
+            state.top = local.reg as usize;
+            state.locals.pop();
+        }
+    }
+
+    // a local in the current scope gets the next register, None for a global
+    fn declare_variable(&mut self, name: &str) -> CompileResult<Option<Reg>> {
+        if self.state().scope_depth == 0 {
+            return Ok(None);
+        }
+        let reg = self.alloc()?;
+        let state = self.state();
+        let depth = state.scope_depth;
+        state.locals.push(Local {
+            name: name.to_string(),
+            depth,
+            reg,
+        });
+        Ok(Some(reg))
+    }
+
+    // a local of the current function, or a global. the locals of the
+    // enclosing functions would need upvalues, the register machine has none
+    fn resolve_variable(&mut self, name: &str) -> CompileResult<Option<Reg>> {
+        if let Some(reg) = self.state().resolve_local(name) {
+            return Ok(Some(reg));
+        }
+        let enclosing = &self.states[..self.states.len() - 1];
+        if enclosing.iter().any(|s| s.resolve_local(name).is_some()) {
+            return self.unsupported("closure variable");
+        }
+        Ok(None)
+    }
+
+    // compile the function into its own proto, then put it in `dst`
+    fn function(&mut self, decl: &FunctionDeclaration, dst: Reg) -> CompileResult<()> {
+        let FunctionDeclaration { id, params, body } = decl;
+        if params.len() + 1 > MAX_REGISTERS {
+            return self.error("too many parameters");
+        }
+        self.states
+            .push(FunctionState::new(Some(id.name.clone()), params));
+        self.begin_scope();
+        for stmt in body.iter() {
+            self.compile_stmt(stmt)?;
+        }
+        self.emit_return()?;
+        let proto = self.states.pop().unwrap().proto;
+
+        self.set_pos(&id.span);
+        if self.functions.len() >= MAX_FUNCTIONS {
+            return self.error("too many functions");
+        }
+        self.functions.push(Rc::new(proto));
+        self.emit(Function(dst, (self.functions.len() - 1) as u16));
+        Ok(())
+    }
+
+    // `dst` is None when the value of the assignment is unused
+    fn assign(&mut self, assign: &AssignExpr, dst: Option<Reg>) -> CompileResult<()> {
+        let AssignExpr { op: _, left, right } = assign;
+        self.set_pos(&left.span);
+        match self.resolve_variable(&left.name)? {
+            // the value is computed right into the variable
+            Some(reg) => {
+                self.compile_expr(right, reg)?;
+                if let Some(dst) = dst.filter(|dst| *dst != reg) {
+                    self.emit(Move(dst, reg));
+                }
+            }
+            None => {
+                let top = self.state().top;
+                let reg = match dst {
+                    Some(dst) => dst,
+                    None => self.alloc()?,
+                };
+                self.compile_expr(right, reg)?;
+                self.set_pos(&left.span);
+                let name = self.identifier_constant(&left.name)?;
+                self.emit(SetGlobal(reg, name));
+                self.free(top);
+            }
+        }
+        Ok(())
+    }
+
+    // following instructions are emitted at the start of `span`
+    fn set_pos(&mut self, span: &Span) {
+        self.pos = span.loc.start;
+    }
+
+    fn span(&self) -> Span {
+        Span::new(self.filename.clone(), Loc::new(self.pos, self.pos))
+    }
+
+    fn error<T>(&self, msg: &str) -> CompileResult<T> {
+        Err(CompileError::Error(msg.to_string(), self.span()))
+    }
+
+    fn unsupported<T>(&self, what: &str) -> CompileResult<T> {
+        Err(CompileError::Unsupported(what.to_string(), self.span()))
+    }
+}
+
+// whether evaluating `expr` may assign a variable
+fn assigns(expr: &Expr) -> bool {
+    match expr {
+        Expr::Assign(_) => true,
+        Expr::Binary(BinaryExpr { left, right, .. })
+        | Expr::Logical(LogicalExpr { left, right, .. }) => assigns(left) || assigns(right),
+        Expr::Unary(UnaryExpr { argument, .. }) => assigns(argument),
+        Expr::Call(CallExpr {
+            callee, arguments, ..
+        }) => assigns(callee) || arguments.iter().any(assigns),
+        _ => false,
+    }
+}
+
+impl StmtVisitor for Compiler {
+    type Item = CompileResult<()>;
+
+    fn visit_expr_stmt(&mut self, expr: &Expr) -> Self::Item {
+        if let Expr::Assign(assign) = expr {
+            return self.assign(assign, None);
+        }
+        let top = self.state().top;
+        let reg = self.alloc()?;
+        self.compile_expr(expr, reg)?;
+        self.free(top);
+        Ok(())
+    }
+
+    fn visit_block(&mut self, block: &[Statement]) -> Self::Item {
+        self.begin_scope();
+        for stmt in block {
+            self.compile_stmt(stmt)?;
+        }
+        self.end_scope();
+        Ok(())
+    }
+
+    fn visit_empty(&mut self) -> Self::Item {
+        Ok(())
+    }
+
+    fn visit_variable_declare(&mut self, decl: &VariableDeclaration) -> Self::Item {
+        let VariableDeclaration { id, init } = decl;
+        self.set_pos(&id.span);
+        let top = self.state().top;
+        // the initializer can't refer to the variable, it goes straight into its register
+        let reg = self.alloc()?;
+        match init {
+            Some(expr) => self.compile_expr(expr, reg)?,
+            None => {
+                self.emit(LoadNull(reg));
+            }
+        }
+
+        self.set_pos(&id.span);
+        self.free(top);
+        match self.declare_variable(&id.name)? {
+            Some(local) => debug_assert_eq!(local, reg),
+            None => {
+                let name = self.identifier_constant(&id.name)?;
+                self.emit(DefineGlobal(reg, name));
+            }
+        }
+        Ok(())
+    }
+
+    fn visit_function_declare(&mut self, decl: &FunctionDeclaration) -> Self::Item {
+        self.set_pos(&decl.id.span);
+        match self.declare_variable(&decl.id.name)? {
+            Some(reg) => self.function(decl, reg)?,
+            None => {
+                let top = self.state().top;
+                let reg = self.alloc()?;
+                self.function(decl, reg)?;
+                let name = self.identifier_constant(&decl.id.name)?;
+                self.emit(DefineGlobal(reg, name));
+                self.free(top);
+            }
+        }
+        Ok(())
+    }
+
+    fn visit_class_declare(&mut self, class: &ClassDeclaration) -> Self::Item {
+        self.set_pos(&class.id.span);
+        self.unsupported("class")
+    }
+
+    fn visit_throw_stmt(&mut self, stmt: &ThrowStatement) -> Self::Item {
+        self.set_pos(&stmt.span);
+        self.unsupported("throw")
+    }
+
+    fn visit_try_stmt(&mut self, _stmt: &TryStatement) -> Self::Item {
+        self.unsupported("try")
+    }
+
+    fn visit_if_stmt(&mut self, stmt: &IfStatement) -> Self::Item {
+        let IfStatement {
+            test,
+            consequent,
+            alternate,
+        } = stmt;
+        let top = self.state().top;
+        let test = self.operand(test)?;
+        let then_jump = self.emit(JumpIfFalse(test, 0));
+        self.free(top);
+
+        self.compile_stmt(consequent)?;
+        match alternate {
+            Some(alternate) => {
+                let else_jump = self.emit(Jump(0));
+                self.patch_jump(then_jump);
+                self.compile_stmt(alternate)?;
+                self.patch_jump(else_jump);
+            }
+            None => self.patch_jump(then_jump),
+        }
+        Ok(())
+    }
+
+    fn visit_return_stmt(&mut self, stmt: &ReturnStatement) -> Self::Item {
+        let top = self.state().top;
+        match &stmt.argument {
+            Some(expr) => {
+                let reg = self.operand(expr)?;
+                self.emit(Return(reg));
+            }
+            None => self.emit_return()?,
+        }
+        self.free(top);
+        Ok(())
+    }
+
+    fn visit_print_stmt(&mut self, expr: &Expr) -> Self::Item {
+        let top = self.state().top;
+        let reg = self.operand(expr)?;
+        self.emit(Print(reg));
+        self.free(top);
+        Ok(())
+    }
+
+    fn visit_while_stmt(&mut self, stmt: &WhileStmt) -> Self::Item {
+        let WhileStmt { test, body } = stmt;
+        let loop_start = self.state().proto.code.len() as u32;
+        let top = self.state().top;
+        let test = self.operand(test)?;
+        let exit_jump = self.emit(JumpIfFalse(test, 0));
+        self.free(top);
+
+        self.compile_stmt(body)?;
+        self.emit(Jump(loop_start));
+        self.patch_jump(exit_jump);
+        Ok(())
+    }
+}
+
+impl ExprVisitor for Compiler {
+    type Item = CompileResult<()>;
+
+    fn visit_binary(&mut self, binary: &BinaryExpr) -> Self::Item {
+        let BinaryExpr { left, op, right } = binary;
+        let (dst, top) = (self.dst, self.state().top);
+        // a local read in place must not change before the right operand is evaluated
+        let x = if assigns(right) {
+            let reg = self.alloc()?;
+            self.compile_expr(left, reg)?;
+            reg
+        } else {
+            self.operand(left)?
+        };
+        let y = self.operand(right)?;
+
+        self.set_pos(&op.span());
+        let instruction = match op.value {
+            Operator::Add => Add(dst, x, y),
+            Operator::Min => Subtract(dst, x, y),
+            Operator::Mul => Multiply(dst, x, y),
+            Operator::Div => Divide(dst, x, y),
+            Operator::Equal => Equal(dst, x, y),
+            Operator::NotEqual => NotEqual(dst, x, y),
+            Operator::GreaterThan => Greater(dst, x, y),
+            Operator::GreaterThanEqual => GreaterEqual(dst, x, y),
+            Operator::LessThan => Less(dst, x, y),
+            Operator::LessThanEqual => LessEqual(dst, x, y),
+            _ => return self.unsupported(&format!("operator '{}'", op.value)),
+        };
+        self.emit(instruction);
+        self.free(top);
+        Ok(())
+    }
+
+    fn visit_unary(&mut self, unary: &UnaryExpr) -> Self::Item {
+        let UnaryExpr { op, argument } = unary;
+        let (dst, top) = (self.dst, self.state().top);
+        let x = self.operand(argument)?;
+
+        self.set_pos(&op.span());
+        match op.value {
+            Operator::Min => {
+                self.emit(Negate(dst, x));
+            }
+            Operator::Not => {
+                self.emit(Not(dst, x));
+            }
+            Operator::Add if x != dst => {
+                self.emit(Move(dst, x));
+            }
+            Operator::Add => (),
+            _ => return self.unsupported(&format!("operator '{}'", op.value)),
+        }
+        self.free(top);
+        Ok(())
+    }
+
+    fn visit_assign(&mut self, assign: &AssignExpr) -> Self::Item {
+        self.assign(assign, Some(self.dst))
+    }
+
+    fn visit_ident(&mut self, ident: &Identifier) -> Self::Item {
+        self.set_pos(&ident.span);
+        match self.resolve_variable(&ident.name)? {
+            Some(reg) if reg == self.dst => (),
+            Some(reg) => {
+                self.emit(Move(self.dst, reg));
+            }
+            None => {
+                let name = self.identifier_constant(&ident.name)?;
+                self.emit(GetGlobal(self.dst, name));
+            }
+        }
+        Ok(())
+    }
+
+    fn visit_call(&mut self, call: &CallExpr) -> Self::Item {
+        let CallExpr {
+            callee,
+            arguments,
+            span,
+        } = call;
+        if arguments.len() > u8::MAX as usize {
+            self.set_pos(span);
+            return self.error("too many arguments");
+        }
+        let (dst, top) = (self.dst, self.state().top);
+        // the callee and the arguments need consecutive registers at the top,
+        // a destination just allocated is the first of them
+        let base = if dst as usize + 1 == top && !self.is_local(dst) {
+            dst
+        } else {
+            self.alloc()?
+        };
+        self.compile_expr(callee, base)?;
+        for arg in arguments.iter() {
+            let reg = self.alloc()?;
+            self.compile_expr(arg, reg)?;
+        }
+
+        self.set_pos(span);
+        self.emit(Call(base, arguments.len() as u8));
+        if base != dst {
+            self.emit(Move(dst, base));
+        }
+        self.free(top);
+        Ok(())
+    }
+
+    fn visit_logical(&mut self, expr: &LogicalExpr) -> Self::Item {
+        let LogicalExpr { left, op, right } = expr;
+        let (dst, top) = (self.dst, self.state().top);
+        // the left value is written before the right operand is evaluated,
+        // which could read the variable being assigned
+        let reg = if self.is_local(dst) {
+            self.alloc()?
+        } else {
+            dst
+        };
+        self.compile_expr(left, reg)?;
+
+        self.set_pos(&op.span());
+        let end_jump = match op.value {
+            Operator::And => self.emit(JumpIfFalse(reg, 0)),
+            Operator::Or => self.emit(JumpIfTrue(reg, 0)),
+            _ => return self.unsupported(&format!("operator '{}'", op.value)),
+        };
+        self.compile_expr(right, reg)?;
+        self.patch_jump(end_jump);
+        if reg != dst {
+            self.emit(Move(dst, reg));
+        }
+        self.free(top);
+        Ok(())
+    }
+
+    fn visit_get(&mut self, expr: &GetExpr) -> Self::Item {
+        self.set_pos(&expr.property.span);
+        self.unsupported("property")
+    }
+
+    fn visit_set(&mut self, expr: &SetExpr) -> Self::Item {
+        self.set_pos(&expr.property.span);
+        self.unsupported("property")
+    }
+
+    fn visit_this(&mut self, this: &ThisExpr) -> Self::Item {
+        self.set_pos(&this.span);
+        self.unsupported("this")
+    }
+
+    fn visit_super(&mut self, expr: &SuperExpr) -> Self::Item {
+        self.set_pos(&expr.span);
+        self.unsupported("super")
+    }
+
+    fn visit_numeric(&mut self, lit: &NumericLiteral) -> Self::Item {
+        self.set_pos(&lit.span);
+        self.load_constant(Value::number(lit.value))
+    }
+
+    fn visit_string(&mut self, lit: &StringLiteral) -> Self::Item {
+        self.set_pos(&lit.span);
+        self.load_constant(Value::string(lit.value.as_str().into()))
+    }
+
+    fn visit_boolean(&mut self, lit: bool) -> Self::Item {
+        self.emit(LoadBool(self.dst, lit));
+        Ok(())
+    }
+
+    fn visit_null(&mut self) -> Self::Item {
+        self.emit(LoadNull(self.dst));
+        Ok(())
+    }
+
+    fn visit_array(&mut self, lit: &ArrayLiteral) -> Self::Item {
+        self.set_pos(&lit.span);
+        self.unsupported("array")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{bytecode::register::debug::write_module, lexer::Lexer, parser::parser::Parser};
+
+    use super::*;
+
+    fn compile_source(source: &str) -> CompileResult<Module> {
+        let lexer = Lexer::new(source.as_bytes(), "test.tx");
+        compile(&Parser::new(lexer).parse().unwrap())
+    }
+
+    #[test]
+    fn locals_are_operands() {
+        let module = compile_source("{ let a = 1; let b = 2; a = a + b; print a; }").unwrap();
+        assert_eq!(
+            module.script.code,
+            vec![LoadConst(0, 0), LoadConst(1, 1), Add(0, 0, 1), Print(0),]
+        );
+        assert_eq!(module.script.registers, 2);
+    }
+
+    #[test]
+    fn temporaries() {
+        // temporaries above the locals are reused by the next statement
+        let module =
+            compile_source("{ let a = 1; print a * 2 + 3; print -(a - 1); let b = a; }").unwrap();
+        assert_eq!(
+            module.script.code,
+            vec![
+                LoadConst(0, 0),
+                LoadConst(3, 1),
+                Multiply(2, 0, 3),
+                LoadConst(3, 2),
+                Add(1, 2, 3),
+                Print(1),
+                LoadConst(3, 3),
+                Subtract(2, 0, 3),
+                Negate(1, 2),
+                Print(1),
+                Move(1, 0),
+            ]
+        );
+        assert_eq!(module.script.registers, 4);
+    }
+
+    #[test]
+    fn calls() {
+        let module =
+            compile_source("fn f(a, b) { let c = a; return c + b; } let x = f(1, 2);").unwrap();
+        let f = &module.functions[0];
+        assert_eq!((f.arity, f.registers), (2, 5));
+        assert_eq!(f.code[..2], [Move(3, 1), Add(4, 3, 2)]);
+        // the callee and its arguments are consecutive, the result replaces the callee
+        assert_eq!(
+            module.script.code,
+            vec![
+                Function(0, 0),
+                DefineGlobal(0, 0),
+                GetGlobal(0, 1),
+                LoadConst(1, 2),
+                LoadConst(2, 3),
+                Call(0, 2),
+                DefineGlobal(0, 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn logical_into_a_local() {
+        // the right operand must read the variable before it's assigned
+        let module = compile_source("{ let a = true; let b = false; a = b && a; }").unwrap();
+        assert_eq!(
+            module.script.code[2..],
+            [Move(2, 1), JumpIfFalse(2, 5), Move(2, 0), Move(0, 2)]
+        );
+    }
+
+    #[test]
+    fn disassemble() {
+        let module = compile_source("fn f(x) {\n  return x * 2;\n}\nprint f(1);").unwrap();
+        let mut out = vec![];
+        write_module(&mut out, &module).unwrap();
+        let listing = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = listing.lines().map(str::trim_end).collect();
+        assert_eq!(
+            lines,
+            [
+                "",
+                "== script (arity 0, 2 registers) ==",
+                "0000  <1:4> FUNCTION   r0, 0    <fn f>",
+                "0001  <1:4> DEFGLOBAL  r0, 0    '\"f\"'",
+                "0002  <4:7> GETGLOBAL  r0, 1    '\"f\"'",
+                "0003  <4:9> LOADK      r1, 2    '1'",
+                "0004  <4:7> CALL       r0, 1",
+                "0005  <4:7> PRINT      r0",
+                "",
+                "== f (arity 1, 4 registers) ==",
+                "0000  <2:14> LOADK      r3, 0    '2'",
+                "0001  <2:12> MUL        r2, r1, r3",
+                "0002  <2:12> RET        r2",
+                "0003  <2:12> LOADNULL   r2",
+                "0004  <2:12> RET        r2",
+            ]
+        );
+    }
+
+    #[test]
+    fn unsupported() {
+        for source in [
+            "class A {}",
+            "fn outer() { let a = 1; fn inner() { return a; } }",
+            "let a = [1];",
+        ] {
+            assert!(
+                matches!(compile_source(source), Err(CompileError::Unsupported(..))),
+                "{}",
+                source
+            );
+        }
+        // the scoping rules of the resolver still apply
+        assert!(matches!(
+            compile_source("{ let a = a; }"),
+            Err(CompileError::Resolve(_))
+        ));
+    }
+}
@@ -0,0 +1,90 @@
+use std::{
+    io::{self, Write},
+    rc::Rc,
+};
+
+use super::{Instruction::*, Module, Proto};
+
+pub fn disassemble_module(module: &Module) {
+    write_module(&mut io::stdout(), module).expect("failed to write to stdout");
+}
+
+pub fn write_module(out: &mut dyn Write, module: &Module) -> io::Result<()> {
+    write_proto(out, &module.script, &module.functions)?;
+    for function in module.functions.iter() {
+        write_proto(out, function, &module.functions)?;
+    }
+    Ok(())
+}
+
+fn write_proto(out: &mut dyn Write, proto: &Proto, functions: &[Rc<Proto>]) -> io::Result<()> {
+    writeln!(
+        out,
+        "\n== {} (arity {}, {} registers) ==",
+        proto.name(),
+        proto.arity,
+        proto.registers
+    )?;
+    for at in 0..proto.code.len() {
+        write_instruction(out, proto, functions, at)?;
+    }
+    Ok(())
+}
+
+/**
+ * write the instruction at index `at` of `proto`, eg: `ADD r1, r2, r3`
+ */
+pub fn write_instruction(
+    out: &mut dyn Write,
+    proto: &Proto,
+    functions: &[Rc<Proto>],
+    at: usize,
+) -> io::Result<()> {
+    write!(out, "{:04} ", at)?;
+    let pos = proto.positions[at];
+    write!(out, " <{}:{}> ", pos.0, pos.1)?;
+
+    let instruction = proto.code[at];
+    let name = format!("{:<10}", instruction.to_string());
+    let constant = |idx: u16| match proto.constants.get(idx as usize) {
+        Some(value) => format!("'{}'", value),
+        None => "<invalid>".to_string(),
+    };
+    match instruction {
+        LoadConst(dst, idx) | GetGlobal(dst, idx) => {
+            writeln!(out, "{} r{}, {:<4} {}", name, dst, idx, constant(idx))
+        }
+        SetGlobal(src, idx) | DefineGlobal(src, idx) => {
+            writeln!(out, "{} r{}, {:<4} {}", name, src, idx, constant(idx))
+        }
+        LoadNull(reg) | Return(reg) | Print(reg) => writeln!(out, "{} r{}", name, reg),
+        LoadBool(dst, b) => writeln!(out, "{} r{}, {}", name, dst, b),
+        Move(x, y) | Negate(x, y) | Not(x, y) => writeln!(out, "{} r{}, r{}", name, x, y),
+        Add(dst, x, y)
+        | Subtract(dst, x, y)
+        | Multiply(dst, x, y)
+        | Divide(dst, x, y)
+        | Equal(dst, x, y)
+        | NotEqual(dst, x, y)
+        | Greater(dst, x, y)
+        | GreaterEqual(dst, x, y)
+        | Less(dst, x, y)
+        | LessEqual(dst, x, y) => writeln!(out, "{} r{}, r{}, r{}", name, dst, x, y),
+        Jump(target) => writeln!(out, "{} -> {:04}", name, target),
+        JumpIfFalse(cond, target) | JumpIfTrue(cond, target) => {
+            writeln!(out, "{} r{} -> {:04}", name, cond, target)
+        }
+        Call(callee, argc) => writeln!(out, "{} r{}, {}", name, callee, argc),
+        Function(dst, idx) => match functions.get(idx as usize) {
+            Some(function) => writeln!(
+                out,
+                "{} r{}, {:<4} <fn {}>",
+                name,
+                dst,
+                idx,
+                function.name()
+            ),
+            None => writeln!(out, "{} r{}, {:<4} <invalid>", name, dst, idx),
+        },
+    }
+}
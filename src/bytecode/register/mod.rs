@@ -0,0 +1,136 @@
+// experimental register machine, an alternative backend to the stack vm.
+// compiled from the same ast: locals live in registers, temporaries are
+// allocated above them, so `a = a + b` is one instruction instead of four.
+// covers functions, not closures capturing locals nor classes
+use std::rc::Rc;
+
+use super::{Pos, Value};
+
+pub mod compiler;
+pub mod debug;
+pub mod vm;
+
+/**
+ * register of the current frame, r0 holds the callee of a function
+ */
+pub type Reg = u8;
+
+pub const MAX_REGISTERS: usize = 256;
+pub const MAX_CONSTANTS: usize = 1 << 16;
+pub const MAX_FUNCTIONS: usize = 1 << 16;
+
+/**
+ * instruction of the register machine, operands are registers unless stated:
+ * the destination comes first. jumps hold the index of their target
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instruction {
+    LoadConst(Reg, u16),
+    LoadNull(Reg),
+    LoadBool(Reg, bool),
+    Move(Reg, Reg),
+    // name is a constant
+    GetGlobal(Reg, u16),
+    SetGlobal(Reg, u16),
+    DefineGlobal(Reg, u16),
+    Add(Reg, Reg, Reg),
+    Subtract(Reg, Reg, Reg),
+    Multiply(Reg, Reg, Reg),
+    Divide(Reg, Reg, Reg),
+    Equal(Reg, Reg, Reg),
+    NotEqual(Reg, Reg, Reg),
+    Greater(Reg, Reg, Reg),
+    GreaterEqual(Reg, Reg, Reg),
+    Less(Reg, Reg, Reg),
+    LessEqual(Reg, Reg, Reg),
+    Negate(Reg, Reg),
+    Not(Reg, Reg),
+    Jump(u32),
+    JumpIfFalse(Reg, u32),
+    JumpIfTrue(Reg, u32),
+    // the callee, followed by the arguments. the result replaces the callee
+    Call(Reg, u8),
+    Return(Reg),
+    Print(Reg),
+    // index in the functions of the module
+    Function(Reg, u16),
+}
+
+impl std::fmt::Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use Instruction::*;
+        let name = match self {
+            LoadConst(..) => "LOADK",
+            LoadNull(_) => "LOADNULL",
+            LoadBool(..) => "LOADBOOL",
+            Move(..) => "MOVE",
+            GetGlobal(..) => "GETGLOBAL",
+            SetGlobal(..) => "SETGLOBAL",
+            DefineGlobal(..) => "DEFGLOBAL",
+            Add(..) => "ADD",
+            Subtract(..) => "SUB",
+            Multiply(..) => "MUL",
+            Divide(..) => "DIV",
+            Equal(..) => "EQ",
+            NotEqual(..) => "NE",
+            Greater(..) => "GT",
+            GreaterEqual(..) => "GE",
+            Less(..) => "LT",
+            LessEqual(..) => "LE",
+            Negate(..) => "NEG",
+            Not(..) => "NOT",
+            Jump(_) => "JMP",
+            JumpIfFalse(..) => "JMPF",
+            JumpIfTrue(..) => "JMPT",
+            Call(..) => "CALL",
+            Return(_) => "RET",
+            Print(_) => "PRINT",
+            Function(..) => "FUNCTION",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/**
+ * compiled function of the register machine
+ */
+#[derive(Debug, Default)]
+pub struct Proto {
+    pub name: Option<String>,
+    pub arity: usize,
+    // size of a frame: the callee, the parameters, the locals and the temporaries
+    pub registers: usize,
+    pub code: Vec<Instruction>,
+    // source position of each instruction
+    pub positions: Vec<Pos>,
+    pub constants: Vec<Value>,
+}
+
+impl Proto {
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or("script")
+    }
+}
+
+/**
+ * a compiled program: the script and every function declared in it
+ */
+#[derive(Debug)]
+pub struct Module {
+    pub script: Rc<Proto>,
+    pub functions: Vec<Rc<Proto>>,
+}
+
+impl Module {
+    /**
+     * instructions of the script and of the functions
+     */
+    pub fn instruction_count(&self) -> usize {
+        self.script.code.len()
+            + self
+                .functions
+                .iter()
+                .map(|function| function.code.len())
+                .sum::<usize>()
+    }
+}
@@ -0,0 +1,479 @@
+use std::{collections::HashMap, io::Write, rc::Rc};
+
+use super::{debug, Instruction, Instruction::*, Module, Proto, Reg};
+use crate::bytecode::{
+    object::ObjRef,
+    vm::{InterpretResult, Sink, FRAMES_MAX},
+    Pos, Unpacked, Value,
+};
+use crate::error::{VmError, VmFrame};
+
+pub fn interpret(module: Module) -> InterpretResult<()> {
+    let mut vm = RegisterVm::new(module);
+    vm.run()
+}
+
+#[derive(Debug)]
+struct CallFrame {
+    proto: Rc<Proto>,
+    ip: usize,
+    // index of the frame's r0 in the register file
+    base: usize,
+}
+
+impl CallFrame {
+    // position of the instruction being executed, or of the pending call
+    fn position(&self) -> Pos {
+        self.proto.positions[self.ip.saturating_sub(1)]
+    }
+}
+
+/**
+ * vm of the register machine. it has no heap: a function value is an object
+ * whose handle indexes the functions of the module
+ */
+#[derive(Debug)]
+pub struct RegisterVm {
+    functions: Vec<Rc<Proto>>,
+    frames: Vec<CallFrame>,
+    // the windows of the frames, a callee's window starts at its callee register
+    registers: Vec<Value>,
+    globals: HashMap<Rc<str>, Value>,
+    // the registers and every instruction are written here before executing it
+    trace: Option<Sink>,
+    // printed values, stdout if not set
+    output: Option<Sink>,
+}
+
+impl RegisterVm {
+    pub fn new(module: Module) -> Self {
+        let Module { script, functions } = module;
+        let registers = vec![Value::NULL; script.registers];
+        Self {
+            functions,
+            frames: vec![CallFrame {
+                proto: script,
+                ip: 0,
+                base: 0,
+            }],
+            registers,
+            globals: HashMap::new(),
+            trace: None,
+            output: None,
+        }
+    }
+
+    pub fn run(&mut self) -> InterpretResult<()> {
+        loop {
+            let Some(frame) = self.frames.last_mut() else {
+                return Ok(());
+            };
+            let Some(&instruction) = frame.proto.code.get(frame.ip) else {
+                // the end of the script, functions always end with RET
+                return Ok(());
+            };
+            frame.ip += 1;
+            let base = frame.base;
+            if self.trace.is_some() {
+                self.trace();
+            }
+
+            let r = |reg: Reg| base + reg as usize;
+            match instruction {
+                LoadConst(dst, idx) => {
+                    let value = self.frame().proto.constants[idx as usize].clone();
+                    self.registers[r(dst)] = value;
+                }
+                LoadNull(dst) => self.registers[r(dst)] = Value::NULL,
+                LoadBool(dst, b) => self.registers[r(dst)] = Value::boolean(b),
+                Move(dst, src) => self.registers[r(dst)] = self.registers[r(src)].clone(),
+                GetGlobal(dst, idx) => {
+                    let name = self.name(idx);
+                    match self.globals.get(name).cloned() {
+                        Some(value) => self.registers[r(dst)] = value,
+                        None => return Err(self.error(format!("{} is not defined", name))),
+                    }
+                }
+                SetGlobal(src, idx) => {
+                    let value = self.registers[r(src)].clone();
+                    // the frames and the globals are borrowed apart
+                    let frame = self.frames.last().unwrap();
+                    let name = frame.proto.constants[idx as usize].as_string().unwrap();
+                    match self.globals.get_mut(name) {
+                        Some(slot) => *slot = value,
+                        None => return Err(self.error(format!("{} is not defined", name))),
+                    }
+                }
+                DefineGlobal(src, idx) => {
+                    let name = self.name(idx).clone();
+                    let value = self.registers[r(src)].clone();
+                    self.globals.insert(name, value);
+                }
+                Add(dst, x, y)
+                | Subtract(dst, x, y)
+                | Multiply(dst, x, y)
+                | Divide(dst, x, y)
+                | Greater(dst, x, y)
+                | GreaterEqual(dst, x, y)
+                | Less(dst, x, y)
+                | LessEqual(dst, x, y) => {
+                    self.binary_op(instruction, r(dst), r(x), r(y))?;
+                }
+                Equal(dst, x, y) => {
+                    let value = Value::boolean(self.registers[r(x)] == self.registers[r(y)]);
+                    self.registers[r(dst)] = value;
+                }
+                NotEqual(dst, x, y) => {
+                    let value = Value::boolean(self.registers[r(x)] != self.registers[r(y)]);
+                    self.registers[r(dst)] = value;
+                }
+                Negate(dst, src) => match self.registers[r(src)].as_number() {
+                    Some(n) => self.registers[r(dst)] = Value::number(-n),
+                    None => {
+                        return Err(self.error(format!(
+                            "invalid operand for '-': {}",
+                            self.type_name(&self.registers[r(src)])
+                        )))
+                    }
+                },
+                Not(dst, src) => {
+                    let value = Value::boolean(!self.registers[r(src)].is_truthy());
+                    self.registers[r(dst)] = value;
+                }
+                Jump(target) => self.frame_mut().ip = target as usize,
+                JumpIfFalse(cond, target) => {
+                    if !self.registers[r(cond)].is_truthy() {
+                        self.frame_mut().ip = target as usize;
+                    }
+                }
+                JumpIfTrue(cond, target) => {
+                    if self.registers[r(cond)].is_truthy() {
+                        self.frame_mut().ip = target as usize;
+                    }
+                }
+                Call(callee, argc) => self.call(r(callee), argc as usize)?,
+                Return(src) => {
+                    let result = self.registers[r(src)].clone();
+                    self.frames.pop();
+                    // the result replaces the callee, in the caller's window
+                    self.registers[base] = result;
+                }
+                Print(src) => {
+                    let line = format!(" > print: {}", self.format(&self.registers[r(src)]));
+                    match &mut self.output {
+                        // like the trace, a failing sink doesn't stop the program
+                        Some(sink) => {
+                            let _ = writeln!(sink.0, "{}", line);
+                        }
+                        None => println!("{}", line),
+                    }
+                }
+                Function(dst, idx) => {
+                    self.registers[r(dst)] = Value::object(ObjRef(idx as usize));
+                }
+            }
+        }
+    }
+
+    /**
+     * trace the execution into `sink`, for debugging
+     */
+    pub fn with_trace(mut self, sink: impl Write + 'static) -> Self {
+        self.trace = Some(Sink(Box::new(sink)));
+        self
+    }
+
+    /**
+     * write printed values into `sink` instead of stdout
+     */
+    pub fn with_output(mut self, sink: impl Write + 'static) -> Self {
+        self.output = Some(Sink(Box::new(sink)));
+        self
+    }
+
+    pub fn global(&self, name: &str) -> Option<&Value> {
+        self.globals.get(name)
+    }
+
+    /**
+     * display a value, objects are functions of the module
+     */
+    pub fn format(&self, value: &Value) -> String {
+        match value.as_object() {
+            Some(r) => format!("<fn {}>", self.functions[r.0].name()),
+            None => value.to_string(),
+        }
+    }
+
+    fn type_name(&self, value: &Value) -> &'static str {
+        match value.as_object() {
+            Some(_) => "function",
+            None => value.type_name(),
+        }
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().unwrap()
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().unwrap()
+    }
+
+    fn name(&self, idx: u16) -> &Rc<str> {
+        self.frame().proto.constants[idx as usize]
+            .as_string()
+            .expect("global name is a string")
+    }
+
+    // `callee` is the register holding the function, the arguments follow it
+    fn call(&mut self, callee: usize, argc: usize) -> InterpretResult<()> {
+        let Some(r) = self.registers[callee].as_object() else {
+            return Err(self.error(format!(
+                "{} is not callable",
+                self.type_name(&self.registers[callee])
+            )));
+        };
+        let proto = self.functions[r.0].clone();
+        if argc != proto.arity {
+            return Err(self.error(format!(
+                "{} expected {} arguments but got {}",
+                proto.name(),
+                proto.arity,
+                argc
+            )));
+        }
+        if self.frames.len() >= FRAMES_MAX {
+            return Err(self.error("stack overflow".to_string()));
+        }
+
+        let end = callee + proto.registers;
+        if self.registers.len() < end {
+            self.registers.resize(end, Value::NULL);
+        }
+        self.frames.push(CallFrame {
+            proto,
+            ip: 0,
+            base: callee,
+        });
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn binary_op(
+        &mut self,
+        op: Instruction,
+        dst: usize,
+        x: usize,
+        y: usize,
+    ) -> InterpretResult<()> {
+        let (x, y) = (&self.registers[x], &self.registers[y]);
+        let result = match (x.unpack(), y.unpack()) {
+            (Unpacked::Number(x), Unpacked::Number(y)) => match op {
+                Add(..) => Value::number(x + y),
+                Subtract(..) => Value::number(x - y),
+                Multiply(..) => Value::number(x * y),
+                Divide(..) => Value::number(x / y),
+                Greater(..) => Value::boolean(x > y),
+                GreaterEqual(..) => Value::boolean(x >= y),
+                Less(..) => Value::boolean(x < y),
+                LessEqual(..) => Value::boolean(x <= y),
+                _ => unreachable!(),
+            },
+            (Unpacked::String(x), Unpacked::String(y)) if matches!(op, Add(..)) => {
+                Value::string(format!("{}{}", x, y).into())
+            }
+            _ => {
+                return Err(self.error(format!(
+                    "invalid operands for '{}': {} and {}",
+                    operator(op),
+                    self.type_name(x),
+                    self.type_name(y)
+                )))
+            }
+        };
+        self.registers[dst] = result;
+        Ok(())
+    }
+
+    // runtime error located at the instruction being executed
+    fn error(&self, msg: String) -> VmError {
+        // a frame's call site is where its caller stopped
+        let backtrace = self
+            .frames
+            .windows(2)
+            .map(|pair| VmFrame {
+                name: pair[1].proto.name().to_string(),
+                call_site: pair[0].position(),
+            })
+            .collect();
+        VmError::new(msg, self.frame().position(), backtrace)
+    }
+
+    fn trace(&mut self) {
+        let Some(mut sink) = self.trace.take() else {
+            return;
+        };
+        let frame = self.frame();
+        let registers: String = self.registers[frame.base..frame.base + frame.proto.registers]
+            .iter()
+            .map(|value| format!("[ {} ]", self.format(value)))
+            .collect();
+        // the trace is best effort, a failing sink doesn't stop the program
+        let _ = writeln!(sink.0, "          {}", registers).and_then(|_| {
+            debug::write_instruction(&mut sink.0, &frame.proto, &self.functions, frame.ip - 1)
+        });
+        self.trace = Some(sink);
+    }
+}
+
+// source operator of a binary instruction, for error messages
+fn operator(op: Instruction) -> &'static str {
+    match op {
+        Add(..) => "+",
+        Subtract(..) => "-",
+        Multiply(..) => "*",
+        Divide(..) => "/",
+        Greater(..) => ">",
+        GreaterEqual(..) => ">=",
+        Less(..) => "<",
+        LessEqual(..) => "<=",
+        _ => "?",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::bytecode::{compiler, vm::Vm};
+    use crate::{lexer::Lexer, parser::parser::Parser};
+
+    // a sink the test can read back
+    #[derive(Clone, Default)]
+    struct Buffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Buffer {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.take()).unwrap()
+        }
+    }
+
+    // what the program printed and how it failed, on the stack vm and on the register vm
+    fn both(source: &str) -> [(String, Option<VmError>); 2] {
+        let ast = Parser::new(Lexer::new(source.as_bytes(), "test.tx"))
+            .parse()
+            .unwrap();
+        let output = Buffer::default();
+        let mut vm = Vm::new(compiler::compile(&ast).unwrap()).with_output(output.clone());
+        let error = vm.run().err();
+        let stack = (output.text(), error);
+
+        let mut vm = RegisterVm::new(super::super::compiler::compile(&ast).unwrap())
+            .with_output(output.clone());
+        let error = vm.run().err();
+        [stack, (output.text(), error)]
+    }
+
+    fn differential(source: &str) {
+        let [stack, register] = both(source);
+        assert_eq!(register, stack, "{}", source);
+    }
+
+    #[test]
+    fn same_results() {
+        let programs = [
+            "print 1 + 2 * 3 - 4 / 8; print -(2 + 3); print !null; print +4;",
+            r#"let s = "a" + "b"; print s == "ab"; print s != "ab"; print 1 < 2; print 2 <= 2;"#,
+            "{ let a = 1; let b = 2; a = a + b; b = a * b; print a; print b; }",
+            "{ let a = 1; { let b = a + 1; { let c = b + 1; print a + b + c; } } let d = 4; print d; }",
+            "let g = 1; g = g + 1; print g; { let l = g; g = l * 10; } print g;",
+            "{ let i = 0; let sum = 0; while (i < 10) { sum = sum + i; i = i + 1; } print sum; }",
+            "let i = 0; while (i < 3) { if (i == 1) print \"one\"; else print i; i = i + 1; }",
+            "print 1 && 2; print null && 2; print null || 3; print false || false;",
+            "{ let a = false; let b = true; a = b && a; print a; a = a || b; print a; }",
+            "{ let x = 1; print x + (x = 2); print x; let y = x = 5; print y; }",
+            "{ let a = 1; let b = 2; a = b = a + b; print a == b; }",
+            r#"
+            fn fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+            print fib(15);
+            "#,
+            r#"
+            fn add(a, b, c) { return a + b + c; }
+            fn twice(f, x) { return f(f(x, 1, 1), 1, 1); }
+            print twice(add, 1);
+            print add(add(1, 2, 3), add(4, 5, 6), 7);
+            "#,
+            r#"
+            fn counter() { let n = 0; while (n < 5) n = n + 1; return n; }
+            fn nothing() {}
+            let f = counter;
+            print f(); print nothing(); print counter; print nothing;
+            "#,
+            r#"
+            {
+                fn square(x) { return x * x; }
+                let a = square(3);
+                a = square(a);
+                print a;
+            }
+            "#,
+        ];
+        for source in programs {
+            differential(source);
+        }
+    }
+
+    #[test]
+    fn same_errors() {
+        let programs = [
+            "print missing;",
+            "missing = 1;",
+            "print 1 + true;",
+            r#"print -"a";"#,
+            "let a = 1; a();",
+            "fn f(a) {} f(1, 2);",
+            r#"
+            fn inner(x) { return x + null; }
+            fn outer() { print "before"; return inner(1); }
+            outer();
+            "#,
+            "fn forever(n) { return forever(n + 1); } forever(0);",
+        ];
+        for source in programs {
+            let [stack, register] = both(source);
+            assert!(register.1.is_some(), "{}", source);
+            assert_eq!(register, stack, "{}", source);
+        }
+    }
+
+    #[test]
+    fn trace() {
+        let buffer = Buffer::default();
+        let module = super::super::compiler::compile(
+            &Parser::new(Lexer::new("{ let a = 1; a = a + a; }".as_bytes(), "t.tx"))
+                .parse()
+                .unwrap(),
+        )
+        .unwrap();
+        let mut vm = RegisterVm::new(module).with_trace(buffer.clone());
+        vm.run().unwrap();
+        let trace = buffer.text();
+        let lines: Vec<_> = trace.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[1].ends_with("LOADK      r0, 0    '1'"));
+        assert_eq!(lines[2].trim(), "[ 1 ]");
+        assert!(lines[3].ends_with("ADD        r0, r0, r0"));
+    }
+}
@@ -0,0 +1,499 @@
+use std::rc::Rc;
+
+use crate::error::BytecodeError;
+
+use super::{
+    object::{Function, UpvalueDesc},
+    opcode::MAX_CACHES,
+    verify::verify,
+    Chunk, Handler, PosRun, Unpacked, Value,
+};
+
+/**
+ * bytecode file layout, integers are little endian:
+ *
+ *  File
+ *      : MAGIC VERSION:u16 CHECKSUM:u32 Chunk
+ *      ;
+ *  Chunk
+ *      : count:u32 Constant*  len:u32 code:u8*  count:u32 (start:u32 ln:u32 col:u32)*
+ *        count:u32 (start:u32 end:u32 target:u32 depth:u32)*  caches:u32
+ *      ;
+ *  Constant
+ *      : TAG_NUMBER f64 | TAG_STRING String | TAG_BOOLEAN u8 | TAG_NULL
+ *      | TAG_FUNCTION name:(u8 String?) arity:u32 count:u32 (is_local:u8 index:u32)* Chunk
+ *      ;
+ *
+ * the code is the in-memory encoding (see `OpCode::write`), positions are run-length encoded.
+ * exception handlers are code offsets, in the order of `Chunk::handlers`.
+ * only the number of inline caches is stored, they start empty.
+ * the checksum covers everything after the header
+ */
+pub const MAGIC: &[u8; 4] = b"TXBC";
+pub const VERSION: u16 = 4;
+const HEADER_LEN: usize = 4 + 2 + 4;
+
+const TAG_NUMBER: u8 = 0;
+const TAG_STRING: u8 = 1;
+const TAG_BOOLEAN: u8 = 2;
+const TAG_NULL: u8 = 3;
+const TAG_FUNCTION: u8 = 4;
+
+// nested function declarations deeper than this are rejected
+const MAX_NESTING: usize = 256;
+
+type Result<T> = std::result::Result<T, BytecodeError>;
+
+pub fn serialize(chunk: &Chunk) -> Result<Vec<u8>> {
+    let mut writer = Writer { buf: vec![] };
+    writer.chunk(chunk)?;
+
+    let mut out = Vec::with_capacity(HEADER_LEN + writer.buf.len());
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    out.extend_from_slice(&checksum(&writer.buf).to_le_bytes());
+    out.extend_from_slice(&writer.buf);
+    Ok(out)
+}
+
+pub fn deserialize(bytes: &[u8]) -> Result<Chunk> {
+    if bytes.len() < MAGIC.len() || &bytes[..MAGIC.len()] != MAGIC {
+        return Err(BytecodeError::BadMagic);
+    }
+    let mut reader = Reader {
+        bytes,
+        pos: MAGIC.len(),
+        depth: 0,
+    };
+    let version = reader.u16()?;
+    if version != VERSION {
+        return Err(BytecodeError::UnsupportedVersion(version));
+    }
+    let sum = reader.u32()?;
+    if checksum(&bytes[HEADER_LEN..]) as usize != sum {
+        return Err(BytecodeError::ChecksumMismatch);
+    }
+
+    let chunk = reader.chunk()?;
+    if reader.pos != bytes.len() {
+        return Err(BytecodeError::Invalid("trailing bytes".to_string()));
+    }
+    // so a loaded file can't crash the vm
+    verify(&chunk).map_err(|e| BytecodeError::Invalid(e.to_string()))?;
+    Ok(chunk)
+}
+
+// FNV-1a
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c9dc5, |hash, b| {
+        (hash ^ *b as u32).wrapping_mul(0x01000193)
+    })
+}
+
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, v: u8) {
+        self.buf.push(v);
+    }
+
+    fn u32(&mut self, v: usize) -> Result<()> {
+        let v = u32::try_from(v)
+            .map_err(|_| BytecodeError::Unserializable(format!("operand {}", v)))?;
+        self.buf.extend_from_slice(&v.to_le_bytes());
+        Ok(())
+    }
+
+    fn string(&mut self, s: &str) -> Result<()> {
+        self.u32(s.len())?;
+        self.buf.extend_from_slice(s.as_bytes());
+        Ok(())
+    }
+
+    fn chunk(&mut self, chunk: &Chunk) -> Result<()> {
+        self.u32(chunk.constants.len())?;
+        for constant in chunk.constants.iter() {
+            self.constant(constant)?;
+        }
+
+        self.u32(chunk.code.len())?;
+        self.buf.extend_from_slice(&chunk.code);
+
+        self.u32(chunk.positions.len())?;
+        for run in chunk.positions.iter() {
+            self.u32(run.start as usize)?;
+            self.u32(run.ln as usize)?;
+            self.u32(run.col as usize)?;
+        }
+
+        self.u32(chunk.handlers.len())?;
+        for handler in chunk.handlers.iter() {
+            self.u32(handler.start)?;
+            self.u32(handler.end)?;
+            self.u32(handler.target)?;
+            self.u32(handler.depth)?;
+        }
+
+        self.u32(chunk.caches.len())?;
+        Ok(())
+    }
+
+    fn constant(&mut self, constant: &Value) -> Result<()> {
+        match constant.unpack() {
+            Unpacked::Number(n) => {
+                self.u8(TAG_NUMBER);
+                self.buf.extend_from_slice(&n.to_le_bytes());
+            }
+            Unpacked::String(s) => {
+                self.u8(TAG_STRING);
+                self.string(s)?;
+            }
+            Unpacked::Boolean(b) => {
+                self.u8(TAG_BOOLEAN);
+                self.u8(b as u8);
+            }
+            Unpacked::Null => self.u8(TAG_NULL),
+            Unpacked::Function(function) => {
+                self.u8(TAG_FUNCTION);
+                match &function.name {
+                    Some(name) => {
+                        self.u8(1);
+                        self.string(name)?;
+                    }
+                    None => self.u8(0),
+                }
+                self.u32(function.arity)?;
+                self.u32(function.upvalues.len())?;
+                for upvalue in function.upvalues.iter() {
+                    self.u8(upvalue.is_local as u8);
+                    self.u32(upvalue.index)?;
+                }
+                self.chunk(&function.chunk)?;
+            }
+            Unpacked::Native(native) => {
+                return Err(BytecodeError::Unserializable(format!(
+                    "native function constant {}",
+                    native.name()
+                )))
+            }
+            Unpacked::Object(_) => {
+                return Err(BytecodeError::Unserializable(
+                    "heap object constant".to_string(),
+                ))
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    // nesting of function constants
+    depth: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() - self.pos < n {
+            return Err(BytecodeError::Truncated);
+        }
+        let slice = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<usize> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()) as usize)
+    }
+
+    // element count, each element needs at least `min_size` bytes
+    fn count(&mut self, min_size: usize) -> Result<usize> {
+        let count = self.u32()?;
+        if count.saturating_mul(min_size) > self.bytes.len() - self.pos {
+            return Err(BytecodeError::Truncated);
+        }
+        Ok(count)
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let len = self.u32()?;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec())
+            .map_err(|_| BytecodeError::Invalid("invalid utf-8 string".to_string()))
+    }
+
+    fn chunk(&mut self) -> Result<Chunk> {
+        let mut chunk = Chunk::new();
+
+        let count = self.count(1)?;
+        for _ in 0..count {
+            let constant = self.constant()?;
+            chunk.constants.push(constant);
+        }
+
+        let len = self.u32()?;
+        chunk.code = self.take(len)?.to_vec();
+
+        let count = self.count(12)?;
+        for _ in 0..count {
+            let start = self.u32()? as u32;
+            let ln = self.u32()? as u32;
+            let col = self.u32()? as u32;
+            chunk.positions.push(PosRun { start, ln, col });
+        }
+
+        let count = self.count(16)?;
+        for _ in 0..count {
+            let start = self.u32()?;
+            let end = self.u32()?;
+            let target = self.u32()?;
+            let depth = self.u32()?;
+            chunk.handlers.push(Handler {
+                start,
+                end,
+                target,
+                depth,
+            });
+        }
+
+        let caches = self.u32()?;
+        if caches > MAX_CACHES {
+            return Err(BytecodeError::Invalid(format!("{} inline caches", caches)));
+        }
+        for _ in 0..caches {
+            chunk.add_cache();
+        }
+
+        Ok(chunk)
+    }
+
+    fn constant(&mut self) -> Result<Value> {
+        Ok(match self.u8()? {
+            TAG_NUMBER => Value::number(f64::from_le_bytes(self.take(8)?.try_into().unwrap())),
+            TAG_STRING => Value::string(self.string()?.into()),
+            TAG_BOOLEAN => Value::boolean(self.u8()? != 0),
+            TAG_NULL => Value::NULL,
+            TAG_FUNCTION => {
+                if self.depth >= MAX_NESTING {
+                    return Err(BytecodeError::Invalid(
+                        "functions nested too deep".to_string(),
+                    ));
+                }
+                let name = match self.u8()? {
+                    0 => None,
+                    _ => Some(self.string()?),
+                };
+                let arity = self.u32()?;
+                let count = self.count(5)?;
+                let mut upvalues = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let is_local = self.u8()? != 0;
+                    let index = self.u32()?;
+                    upvalues.push(UpvalueDesc { is_local, index });
+                }
+
+                self.depth += 1;
+                let chunk = self.chunk()?;
+                self.depth -= 1;
+                Value::function(Rc::new(Function {
+                    name,
+                    arity,
+                    upvalues,
+                    chunk,
+                }))
+            }
+            tag => {
+                return Err(BytecodeError::Invalid(format!(
+                    "unknown constant tag {}",
+                    tag
+                )))
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        bytecode::{compiler::compile, OpCode},
+        lexer::Lexer,
+        parser::parser::Parser,
+    };
+
+    use super::*;
+
+    fn compile_source(source: &str) -> Chunk {
+        let lexer = Lexer::new(source.as_bytes(), "test.tx");
+        let ast = Parser::new(lexer).parse().unwrap();
+        compile(&ast).unwrap()
+    }
+
+    // the same chunk, compared through the debug output
+    fn assert_same(x: &Chunk, y: &Chunk) {
+        assert_eq!(format!("{:?}", x), format!("{:?}", y));
+    }
+
+    const SOURCE: &str = r#"
+        class A {
+            init(n) { this.n = n }
+            get() {
+                fn inner() { return this.n }
+                return inner
+            }
+        }
+        let a = A(1.5)
+        if (a.get()() > 1 && true) print "big"
+        let i = 0
+        while (i < 3) i = i + 1
+        print null
+        fn risky() {
+            try {
+                throw "x"
+            } catch (e) {
+                return e
+            } finally {
+                i = i + 1
+            }
+        }
+        risky()
+    "#;
+
+    #[test]
+    fn round_trip() {
+        let chunk = compile_source(SOURCE);
+        let bytes = serialize(&chunk).unwrap();
+        assert_eq!(&bytes[..4], MAGIC);
+
+        let loaded = deserialize(&bytes).unwrap();
+        assert_same(&chunk, &loaded);
+        let risky = loaded
+            .constants
+            .iter()
+            .find_map(|c| c.as_function().filter(|f| f.name() == "risky"));
+        assert_eq!(risky.unwrap().chunk.handlers.len(), 2);
+        crate::bytecode::vm::interpret(loaded).unwrap();
+    }
+
+    #[test]
+    fn reject_invalid() {
+        let bytes = serialize(&compile_source(SOURCE)).unwrap();
+
+        assert_eq!(deserialize(b"").unwrap_err(), BytecodeError::BadMagic);
+        assert_eq!(deserialize(b"#!/bin").unwrap_err(), BytecodeError::BadMagic);
+
+        let mut version = bytes.clone();
+        version[4] = 9;
+        assert_eq!(
+            deserialize(&version).unwrap_err(),
+            BytecodeError::UnsupportedVersion(9)
+        );
+
+        let mut corrupted = bytes.clone();
+        corrupted[HEADER_LEN + 20] ^= 0xff;
+        assert_eq!(
+            deserialize(&corrupted).unwrap_err(),
+            BytecodeError::ChecksumMismatch
+        );
+
+        // every truncation is an error, never a panic,
+        // even with a checksum matching the truncated payload
+        for len in 0..bytes.len() {
+            assert!(deserialize(&bytes[..len]).is_err());
+            if len >= HEADER_LEN {
+                let payload = &bytes[HEADER_LEN..len];
+                let mut truncated = bytes[..6].to_vec();
+                truncated.extend_from_slice(&checksum(payload).to_le_bytes());
+                truncated.extend_from_slice(payload);
+                assert!(deserialize(&truncated).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn reject_out_of_range() {
+        let mut chunk = Chunk::new();
+        chunk.write(OpCode::OpConstant(3), (1, 1));
+        let bytes = serialize(&chunk).unwrap();
+        assert!(matches!(
+            deserialize(&bytes).unwrap_err(),
+            BytecodeError::Invalid(msg) if msg.contains("constant index")
+        ));
+
+        let mut chunk = Chunk::new();
+        chunk.write(OpCode::OpNull, (1, 1));
+        chunk.write(OpCode::OpThrow, (1, 1));
+        chunk.handlers.push(Handler {
+            start: 0,
+            end: 2,
+            target: 7,
+            depth: 0,
+        });
+        let bytes = serialize(&chunk).unwrap();
+        assert!(matches!(
+            deserialize(&bytes).unwrap_err(),
+            BytecodeError::Invalid(msg) if msg.contains("handler target")
+        ));
+
+        let mut chunk = Chunk::new();
+        chunk.write(OpCode::OpJump(5), (1, 1));
+        let bytes = serialize(&chunk).unwrap();
+        assert!(matches!(
+            deserialize(&bytes).unwrap_err(),
+            BytecodeError::Invalid(msg) if msg.contains("jump target")
+        ));
+
+        // into the operand of an instruction
+        let mut chunk = Chunk::new();
+        chunk.add_constant(1.into());
+        chunk.write(OpCode::OpJump(1), (1, 1));
+        chunk.write(OpCode::OpConstant(0), (1, 2));
+        let bytes = serialize(&chunk).unwrap();
+        assert!(matches!(
+            deserialize(&bytes).unwrap_err(),
+            BytecodeError::Invalid(msg) if msg.contains("jump target")
+        ));
+    }
+
+    // corrupted files that still verify fail when run, never panic
+    #[test]
+    fn run_corrupted() {
+        let source = r#"
+            fn outer(a, b) {
+                fn inner() { return b }
+                return b
+            }
+            print outer(1, 2)
+        "#;
+        let bytes = serialize(&compile_source(source)).unwrap();
+        let get_local = OpCode::OpGetLocal(0).tag();
+        let mut loaded = 0;
+        for at in HEADER_LEN..bytes.len() {
+            if bytes[at] != get_local {
+                continue;
+            }
+            let mut corrupted = bytes.clone();
+            corrupted[at] = OpCode::OpDivide.tag();
+            let sum = checksum(&corrupted[HEADER_LEN..]);
+            corrupted[6..HEADER_LEN].copy_from_slice(&sum.to_le_bytes());
+            if let Ok(chunk) = deserialize(&corrupted) {
+                loaded += 1;
+                let mut vm = crate::bytecode::vm::Vm::new(chunk);
+                let _ = vm.run();
+                // the vm is reusable after the error
+                vm.interpret(compile_source("print 1")).unwrap();
+            }
+        }
+        assert!(loaded > 0);
+    }
+}
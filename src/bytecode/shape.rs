@@ -0,0 +1,111 @@
+use std::{collections::HashMap, rc::Rc};
+
+use super::object::ObjRef;
+
+pub type ShapeId = usize;
+
+// shape of the instances without fields
+pub const EMPTY_SHAPE: ShapeId = 0;
+
+/**
+ * layout of an instance: the slot of each field in `Instance::fields`.
+ * instances given the same fields in the same order share a shape,
+ * so comparing shapes tells where a field is, or that it is missing
+ */
+#[derive(Debug, Default)]
+struct Shape {
+    slots: HashMap<Rc<str>, usize>,
+    // shape after adding a field
+    transitions: HashMap<Rc<str>, ShapeId>,
+}
+
+/**
+ * every shape created so far, they live as long as the vm
+ */
+#[derive(Debug)]
+pub struct Shapes(Vec<Shape>);
+
+impl Default for Shapes {
+    fn default() -> Self {
+        Self(vec![Shape::default()])
+    }
+}
+
+impl Shapes {
+    pub fn slot(&self, shape: ShapeId, name: &str) -> Option<usize> {
+        self.0[shape].slots.get(name).copied()
+    }
+
+    /**
+     * shape with the field `name` added in the next slot
+     */
+    pub fn add_field(&mut self, shape: ShapeId, name: &Rc<str>) -> ShapeId {
+        if let Some(next) = self.0[shape].transitions.get(name) {
+            return *next;
+        }
+        let mut slots = self.0[shape].slots.clone();
+        slots.insert(name.clone(), slots.len());
+        let next = self.0.len();
+        self.0.push(Shape {
+            slots,
+            transitions: HashMap::new(),
+        });
+        self.0[shape].transitions.insert(name.clone(), next);
+        next
+    }
+}
+
+/**
+ * what a property instruction resolved the last time it ran.
+ * valid while the receiver has the same shape and its class the same version.
+ * versions are unique in the process, so a chunk shared by several vms
+ * never sees an entry made by another one
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum InlineCache {
+    #[default]
+    Empty,
+    // the field in this slot
+    Field {
+        shape: ShapeId,
+        version: u64,
+        slot: usize,
+    },
+    // no such field, a method of the class
+    Method {
+        shape: ShapeId,
+        version: u64,
+        method: ObjRef,
+    },
+    // setting a missing field moves the instance to another shape
+    Transition {
+        from: ShapeId,
+        version: u64,
+        to: ShapeId,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transitions() {
+        let mut shapes = Shapes::default();
+        let (x, y): (Rc<str>, Rc<str>) = ("x".into(), "y".into());
+        let xy = shapes.add_field(EMPTY_SHAPE, &x);
+        let xy = shapes.add_field(xy, &y);
+        assert_eq!(shapes.slot(xy, "x"), Some(0));
+        assert_eq!(shapes.slot(xy, "y"), Some(1));
+        assert_eq!(shapes.slot(EMPTY_SHAPE, "x"), None);
+
+        // the same fields in the same order give the same shape
+        let again = shapes.add_field(EMPTY_SHAPE, &x);
+        assert_eq!(shapes.add_field(again, &y), xy);
+        let y_only = shapes.add_field(EMPTY_SHAPE, &y);
+        let yx = shapes.add_field(y_only, &x);
+        assert_ne!(yx, xy);
+        assert_eq!(shapes.slot(yx, "x"), Some(1));
+        assert_eq!(shapes.0.len(), 5);
+    }
+}
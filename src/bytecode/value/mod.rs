@@ -0,0 +1,214 @@
+use std::rc::Rc;
+
+use super::object::{Function, ObjRef};
+use crate::builtin::HostFunction;
+
+// a tagged enum by default, 8 bytes with the `nan-boxing` feature.
+// both have the same api, the compiler and the vm don't know which one they use
+#[cfg(feature = "nan-boxing")]
+mod nan_boxed;
+#[cfg(not(feature = "nan-boxing"))]
+mod tagged;
+
+#[cfg(feature = "nan-boxing")]
+pub use nan_boxed::Value;
+#[cfg(not(feature = "nan-boxing"))]
+pub use tagged::Value;
+
+/**
+ * what a value holds, to match on it whatever its representation
+ */
+#[derive(Debug, Clone, Copy)]
+pub enum Unpacked<'a> {
+    Number(f64),
+    Boolean(bool),
+    Null,
+    String(&'a Rc<str>),
+    Function(&'a Rc<Function>),
+    // builtin implemented in rust
+    Native(&'a Rc<dyn HostFunction>),
+    // closures, upvalues... owned by the vm heap
+    Object(ObjRef),
+}
+
+impl PartialEq for Value {
+    #[inline(always)]
+    fn eq(&self, other: &Self) -> bool {
+        match (self.unpack(), other.unpack()) {
+            (Unpacked::Number(x), Unpacked::Number(y)) => x == y,
+            (Unpacked::Boolean(x), Unpacked::Boolean(y)) => x == y,
+            (Unpacked::Null, Unpacked::Null) => true,
+            (Unpacked::String(x), Unpacked::String(y)) => x == y,
+            (Unpacked::Function(x), Unpacked::Function(y)) => Rc::ptr_eq(x, y),
+            (Unpacked::Native(x), Unpacked::Native(y)) => Rc::ptr_eq(x, y),
+            (Unpacked::Object(x), Unpacked::Object(y)) => x == y,
+            _ => false,
+        }
+    }
+}
+
+impl Value {
+    #[inline(always)]
+    pub fn is_truthy(&self) -> bool {
+        match self.unpack() {
+            Unpacked::Boolean(b) => b,
+            Unpacked::Null => false,
+            _ => true,
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self.unpack() {
+            Unpacked::Number(_) => "number",
+            Unpacked::Boolean(_) => "boolean",
+            Unpacked::Null => "null",
+            Unpacked::String(_) => "string",
+            Unpacked::Function(_) | Unpacked::Native(_) => "function",
+            Unpacked::Object(_) => "object",
+        }
+    }
+
+    #[inline(always)]
+    pub fn as_number(&self) -> Option<f64> {
+        match self.unpack() {
+            Unpacked::Number(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub fn as_string(&self) -> Option<&Rc<str>> {
+        match self.unpack() {
+            Unpacked::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_function(&self) -> Option<&Rc<Function>> {
+        match self.unpack() {
+            Unpacked::Function(function) => Some(function),
+            _ => None,
+        }
+    }
+
+    pub fn as_native(&self) -> Option<&Rc<dyn HostFunction>> {
+        match self.unpack() {
+            Unpacked::Native(native) => Some(native),
+            _ => None,
+        }
+    }
+
+    #[inline(always)]
+    pub fn as_object(&self) -> Option<ObjRef> {
+        match self.unpack() {
+            Unpacked::Object(r) => Some(r),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Debug for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.unpack().fmt(f)
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.unpack() {
+            Unpacked::Number(n) => write!(f, "{}", n),
+            Unpacked::Boolean(b) => write!(f, "{}", b),
+            Unpacked::Null => write!(f, "null"),
+            Unpacked::String(s) => write!(f, "\"{}\"", s),
+            Unpacked::Function(fun) => write!(f, "<fn {}>", fun.name()),
+            Unpacked::Native(native) => write!(f, "<native fn {}>", native.name()),
+            Unpacked::Object(r) => write!(f, "<object {:?}>", r),
+        }
+    }
+}
+
+impl From<f64> for Value {
+    fn from(val: f64) -> Self {
+        Self::number(val)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(val: i32) -> Self {
+        Self::number(val.into())
+    }
+}
+
+impl From<bool> for Value {
+    fn from(val: bool) -> Self {
+        Self::boolean(val)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(val: &str) -> Self {
+        Self::string(val.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let name: Rc<str> = "name".into();
+        let function = Rc::new(Function::default());
+        for n in [
+            0.0,
+            -0.0,
+            1.5,
+            -3e300,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            f64::MIN_POSITIVE,
+        ] {
+            assert_eq!(
+                Value::number(n).as_number().map(f64::to_bits),
+                Some(n.to_bits())
+            );
+        }
+        assert!(Value::number(f64::NAN).as_number().unwrap().is_nan());
+        assert!(Value::number(-f64::NAN).as_number().unwrap().is_nan());
+        assert_ne!(Value::number(f64::NAN), Value::number(f64::NAN));
+        assert!(matches!(
+            Value::boolean(true).unpack(),
+            Unpacked::Boolean(true)
+        ));
+        assert!(matches!(
+            Value::boolean(false).unpack(),
+            Unpacked::Boolean(false)
+        ));
+        assert!(matches!(Value::NULL.unpack(), Unpacked::Null));
+        assert_eq!(Value::object(ObjRef(7)).as_object(), Some(ObjRef(7)));
+
+        let string = Value::string(name.clone());
+        assert_eq!(string.as_string(), Some(&name));
+        assert_eq!(string, Value::from("name"));
+        let value = Value::function(function.clone());
+        assert!(Rc::ptr_eq(value.as_function().unwrap(), &function));
+        assert_eq!(value.type_name(), "function");
+    }
+
+    #[test]
+    fn reference_counts() {
+        let name: Rc<str> = "name".into();
+        let function = Rc::new(Function::default());
+        let values = vec![
+            Value::string(name.clone()),
+            Value::function(function.clone()),
+        ];
+        let copies = values.clone();
+        drop(values);
+        assert_eq!(copies[0], Value::from("name"));
+        drop(copies);
+        assert_eq!(
+            (Rc::strong_count(&name), Rc::strong_count(&function)),
+            (1, 1)
+        );
+    }
+}
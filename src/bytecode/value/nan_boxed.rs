@@ -0,0 +1,137 @@
+use std::{marker::PhantomData, rc::Rc};
+
+use super::Unpacked;
+use crate::{
+    builtin::HostFunction,
+    bytecode::object::{Function, ObjRef},
+};
+
+// a quiet nan, with the bit after the quiet bit also set:
+// no arithmetic produces it, the other values live in its payload
+const QNAN: u64 = 0x7ffc_0000_0000_0000;
+const SIGN: u64 = 1 << 63;
+
+const NULL: u64 = QNAN | 1;
+const FALSE: u64 = QNAN | 2;
+const TRUE: u64 = QNAN | 3;
+
+// pointers have the sign bit set, their kind in the two bits above the 48 bit payload
+const KIND: u64 = SIGN | QNAN | 3 << 48;
+const OBJECT: u64 = SIGN | QNAN;
+const STRING: u64 = SIGN | QNAN | 1 << 48;
+const FUNCTION: u64 = SIGN | QNAN | 2 << 48;
+const NATIVE: u64 = SIGN | QNAN | 3 << 48;
+const PAYLOAD: u64 = (1 << 48) - 1;
+
+/**
+ * value of the vm in 8 bytes: a number is its f64, the other values are nans.
+ * heap objects are indices. strings, functions and natives are boxed once more, for a
+ * thin pointer to their `Rc`, and each value owns a count of that box
+ */
+#[repr(transparent)]
+pub struct Value(u64, PhantomData<Rc<()>>);
+
+impl Value {
+    pub const NULL: Value = Value(NULL, PhantomData);
+
+    #[inline(always)]
+    pub fn number(n: f64) -> Self {
+        // other nans could look like a tagged value
+        let n = if n.is_nan() { f64::NAN } else { n };
+        Self(n.to_bits(), PhantomData)
+    }
+
+    #[inline(always)]
+    pub fn boolean(b: bool) -> Self {
+        Self(if b { TRUE } else { FALSE }, PhantomData)
+    }
+
+    pub fn string(s: Rc<str>) -> Self {
+        Self::pointer(STRING, Rc::into_raw(Rc::new(s)) as usize)
+    }
+
+    pub fn function(function: Rc<Function>) -> Self {
+        Self::pointer(FUNCTION, Rc::into_raw(Rc::new(function)) as usize)
+    }
+
+    pub fn native(native: Rc<dyn HostFunction>) -> Self {
+        Self::pointer(NATIVE, Rc::into_raw(Rc::new(native)) as usize)
+    }
+
+    #[inline(always)]
+    pub fn object(r: ObjRef) -> Self {
+        Self::pointer(OBJECT, r.0)
+    }
+
+    #[inline(always)]
+    fn pointer(kind: u64, payload: usize) -> Self {
+        assert!(
+            payload as u64 & !PAYLOAD == 0,
+            "pointer doesn't fit in 48 bits"
+        );
+        Self(kind | payload as u64, PhantomData)
+    }
+
+    #[inline(always)]
+    fn payload(&self) -> usize {
+        (self.0 & PAYLOAD) as usize
+    }
+
+    #[inline(always)]
+    pub fn unpack(&self) -> Unpacked<'_> {
+        if self.0 & QNAN != QNAN {
+            return Unpacked::Number(f64::from_bits(self.0));
+        }
+        // SAFETY: string, function and native payloads come from `Rc::into_raw` and the
+        // value holds a count, so they stay alive at least as long as `self`
+        match self.0 & KIND {
+            OBJECT => Unpacked::Object(ObjRef(self.payload())),
+            STRING => Unpacked::String(unsafe { &*(self.payload() as *const Rc<str>) }),
+            FUNCTION => Unpacked::Function(unsafe { &*(self.payload() as *const Rc<Function>) }),
+            NATIVE => {
+                Unpacked::Native(unsafe { &*(self.payload() as *const Rc<dyn HostFunction>) })
+            }
+            _ => match self.0 {
+                NULL => Unpacked::Null,
+                TRUE => Unpacked::Boolean(true),
+                FALSE => Unpacked::Boolean(false),
+                bits => unreachable!("invalid value {:#x}", bits),
+            },
+        }
+    }
+}
+
+impl Clone for Value {
+    #[inline(always)]
+    fn clone(&self) -> Self {
+        // SAFETY: see `unpack`
+        match self.0 & KIND {
+            STRING => unsafe { Rc::increment_strong_count(self.payload() as *const Rc<str>) },
+            FUNCTION => unsafe {
+                Rc::increment_strong_count(self.payload() as *const Rc<Function>)
+            },
+            NATIVE => unsafe {
+                Rc::increment_strong_count(self.payload() as *const Rc<dyn HostFunction>)
+            },
+            _ => (),
+        }
+        Self(self.0, PhantomData)
+    }
+}
+
+impl Drop for Value {
+    #[inline(always)]
+    fn drop(&mut self) {
+        // SAFETY: see `unpack`, this gives the count of the value back
+        match self.0 & KIND {
+            STRING => unsafe { Rc::decrement_strong_count(self.payload() as *const Rc<str>) },
+            FUNCTION => unsafe {
+                Rc::decrement_strong_count(self.payload() as *const Rc<Function>)
+            },
+            NATIVE => unsafe {
+                Rc::decrement_strong_count(self.payload() as *const Rc<dyn HostFunction>)
+            },
+            _ => (),
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use std::rc::Rc;
+
+use super::Unpacked;
+use crate::{
+    builtin::HostFunction,
+    bytecode::object::{Function, ObjRef},
+};
+
+/**
+ * value of the vm as a rust enum: the tag next to the largest payload.
+ * match on `unpack()` rather than on the variants, the nan-boxed value has none.
+ * (a newtype hiding them made the vm loop about 1.5x slower)
+ */
+#[derive(Clone)]
+pub enum Value {
+    Number(f64),
+    Boolean(bool),
+    Null,
+    String(Rc<str>),
+    Function(Rc<Function>),
+    Native(Rc<dyn HostFunction>),
+    Object(ObjRef),
+}
+
+impl Value {
+    pub const NULL: Value = Value::Null;
+
+    #[inline(always)]
+    pub fn number(n: f64) -> Self {
+        Self::Number(n)
+    }
+
+    #[inline(always)]
+    pub fn boolean(b: bool) -> Self {
+        Self::Boolean(b)
+    }
+
+    pub fn string(s: Rc<str>) -> Self {
+        Self::String(s)
+    }
+
+    pub fn function(function: Rc<Function>) -> Self {
+        Self::Function(function)
+    }
+
+    pub fn native(native: Rc<dyn HostFunction>) -> Self {
+        Self::Native(native)
+    }
+
+    #[inline(always)]
+    pub fn object(r: ObjRef) -> Self {
+        Self::Object(r)
+    }
+
+    #[inline(always)]
+    pub fn unpack(&self) -> Unpacked<'_> {
+        match self {
+            Value::Number(n) => Unpacked::Number(*n),
+            Value::Boolean(b) => Unpacked::Boolean(*b),
+            Value::Null => Unpacked::Null,
+            Value::String(s) => Unpacked::String(s),
+            Value::Function(function) => Unpacked::Function(function),
+            Value::Native(native) => Unpacked::Native(native),
+            Value::Object(r) => Unpacked::Object(*r),
+        }
+    }
+}
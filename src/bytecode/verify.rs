@@ -0,0 +1,442 @@
+use crate::error::VerifyError;
+
+use super::{Chunk, OpCode, OpCode::*, Unpacked, Value};
+
+type Result<T> = std::result::Result<T, VerifyError>;
+
+/**
+ * check a chunk before running it, so bad bytecode is an error instead of a vm crash:
+ * instructions decode, constants exist with the right type, positions cover the code,
+ * jumps and exception handlers land on instructions, and every instruction sees
+ * the same stack depth on all paths, never less than it needs
+ */
+pub fn verify(chunk: &Chunk) -> Result<()> {
+    Verifier {
+        name: "script",
+        chunk,
+        entry: 0,
+        upvalues: 0,
+        is_script: true,
+    }
+    .verify()
+}
+
+struct Verifier<'a> {
+    name: &'a str,
+    chunk: &'a Chunk,
+    // stack depth when the code starts: the callee and the arguments
+    entry: usize,
+    upvalues: usize,
+    // the script may end without OP_RETURN
+    is_script: bool,
+}
+
+impl<'a> Verifier<'a> {
+    fn error<T>(&self, offset: usize, msg: String) -> Result<T> {
+        Err(VerifyError::new(msg, self.name.to_string(), offset))
+    }
+
+    fn verify(&self) -> Result<()> {
+        self.positions()?;
+        let codes = self.decode()?;
+        self.handlers(&codes)?;
+        self.stack_depths(&codes)
+    }
+
+    // handlers cover whole instructions, and go to one
+    fn handlers(&self, codes: &[Option<(OpCode, usize)>]) -> Result<()> {
+        let len = codes.len();
+        let is_boundary = |at: usize| at == len || codes.get(at).copied().flatten().is_some();
+        for handler in self.chunk.handlers.iter() {
+            let covers = handler.start < handler.end && handler.end <= len;
+            if !covers || !is_boundary(handler.start) || !is_boundary(handler.end) {
+                return self.error(handler.start, "handler range out of range".to_string());
+            }
+            if handler.target >= len || !is_boundary(handler.target) {
+                return self.error(handler.start, "handler target out of range".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    // every run starts inside the code, in order, the first one at 0
+    fn positions(&self) -> Result<()> {
+        let len = self.chunk.code.len();
+        let starts: Vec<_> = self
+            .chunk
+            .positions
+            .iter()
+            .map(|run| run.start as usize)
+            .collect();
+        let ordered = starts.windows(2).all(|pair| pair[0] < pair[1]);
+        let inside = starts.iter().all(|start| *start < len);
+        if !ordered || !inside || (len > 0 && starts.first() != Some(&0)) {
+            return self.error(0, "position table doesn't match the code".to_string());
+        }
+        Ok(())
+    }
+
+    // the instruction starting at each offset, with its constants checked
+    fn decode(&self) -> Result<Vec<Option<(OpCode, usize)>>> {
+        let len = self.chunk.code.len();
+        let mut codes = vec![None; len];
+        let mut at = 0;
+        while at < len {
+            let Some((code, next)) = OpCode::read(&self.chunk.code, at) else {
+                return self.error(at, "unknown or truncated instruction".to_string());
+            };
+            self.constant(at, code)?;
+            codes[at] = Some((code, next));
+            at = next;
+        }
+        Ok(codes)
+    }
+
+    fn constant(&self, at: usize, code: OpCode) -> Result<()> {
+        let Some(idx) = code.get_const_index() else {
+            return Ok(());
+        };
+        let Some(constant) = self.chunk.constants.get(idx) else {
+            return self.error(at, format!("{} constant index {} out of range", code, idx));
+        };
+        match (code, constant.unpack()) {
+            (OpConstant(_) | OpConstantLong(_), _) => Ok(()),
+            (OpClosure(_), Unpacked::Function(function)) => Verifier {
+                name: function.name(),
+                chunk: &function.chunk,
+                entry: function.arity + 1,
+                upvalues: function.upvalues.len(),
+                is_script: false,
+            }
+            .verify(),
+            (OpClosure(_), _) => self.error(at, "closure of a non-function constant".to_string()),
+            (_, Unpacked::String(_)) => Ok(()),
+            _ => self.error(at, format!("{} name constant is not a string", code)),
+        }
+    }
+
+    // propagate the stack depth along every path of the code
+    fn stack_depths(&self, codes: &[Option<(OpCode, usize)>]) -> Result<()> {
+        let len = codes.len();
+        let mut depths: Vec<Option<usize>> = vec![None; len + 1];
+        depths[0] = Some(self.entry);
+        let mut pending = vec![0];
+        // a handler starts with the thrown value above the kept slots
+        for handler in self.chunk.handlers.iter() {
+            let depth = handler.depth + 1;
+            match depths[handler.target] {
+                None => {
+                    depths[handler.target] = Some(depth);
+                    pending.push(handler.target);
+                }
+                Some(existing) if existing != depth => {
+                    return self.error(
+                        handler.target,
+                        format!("handler depth {} doesn't match depth {}", depth, existing),
+                    )
+                }
+                Some(_) => (),
+            }
+        }
+
+        while let Some(at) = pending.pop() {
+            let depth = depths[at].unwrap_or(0);
+            let Some((code, next)) = codes.get(at).copied().flatten() else {
+                // `at` is the end of the code
+                if !self.is_script {
+                    return self.error(at, "function doesn't end with OP_RETURN".to_string());
+                }
+                continue;
+            };
+
+            let (pops, pushes) = effect(code);
+            if depth < pops {
+                return self.error(
+                    at,
+                    format!(
+                        "stack underflow, {} needs {} values, found {}",
+                        code, pops, depth
+                    ),
+                );
+            }
+            self.operands(at, code, depth)?;
+            let after = depth - pops + pushes;
+
+            if let Some(handler) = self.chunk.handler(at) {
+                if depth < handler.depth {
+                    return self.error(
+                        at,
+                        format!(
+                            "stack depth {} below the {} slots of its handler",
+                            depth, handler.depth
+                        ),
+                    );
+                }
+            }
+
+            let targets = match code {
+                OpReturn | OpThrow | OpRethrow(_) => vec![],
+                OpJump(offset) => vec![Some(next + offset)],
+                OpJumpIfFalse(offset) => vec![Some(next), Some(next + offset)],
+                OpLoop(offset) => vec![next.checked_sub(offset)],
+                _ => vec![Some(next)],
+            };
+            for target in targets {
+                let target = match target {
+                    Some(target)
+                        if target == len || codes.get(target).copied().flatten().is_some() =>
+                    {
+                        target
+                    }
+                    _ => return self.error(at, "jump target out of range".to_string()),
+                };
+                match depths[target] {
+                    None => {
+                        depths[target] = Some(after);
+                        pending.push(target);
+                    }
+                    Some(existing) if existing != after => {
+                        return self.error(
+                            at,
+                            format!(
+                                "stack depth {} doesn't match depth {} at {:04}",
+                                after, existing, target
+                            ),
+                        )
+                    }
+                    Some(_) => (),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // local slots must be on the stack, upvalues in the closure
+    fn operands(&self, at: usize, code: OpCode, depth: usize) -> Result<()> {
+        match code {
+            OpGetLocal(slot) | OpSetLocal(slot) | OpRethrow(slot) if slot >= depth => {
+                self.error(at, format!("local slot {} out of range", slot))
+            }
+            OpGetUpvalue(idx) | OpSetUpvalue(idx) if idx >= self.upvalues => {
+                self.error(at, format!("upvalue {} out of range", idx))
+            }
+            OpGetProperty(_, cache) | OpSetProperty(_, cache) | OpInvoke(_, _, cache)
+                if cache >= self.chunk.caches.len() =>
+            {
+                self.error(at, format!("inline cache {} out of range", cache))
+            }
+            OpClosure(idx) => {
+                let Some(function) = self.chunk.constants.get(idx).and_then(Value::as_function)
+                else {
+                    return Ok(());
+                };
+                for upvalue in function.upvalues.iter() {
+                    let count = if upvalue.is_local {
+                        depth
+                    } else {
+                        self.upvalues
+                    };
+                    if upvalue.index >= count {
+                        return self.error(
+                            at,
+                            format!("captured variable {} out of range", upvalue.index),
+                        );
+                    }
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// (values popped, values pushed)
+fn effect(code: OpCode) -> (usize, usize) {
+    match code {
+        OpReturn | OpPrint | OpPop | OpDefineGlobal(_) | OpCloseUpvalue | OpThrow => (1, 0),
+        OpConstant(_) | OpConstantLong(_) | OpNull | OpTrue | OpFalse | OpGetGlobal(_)
+        | OpGetLocal(_) | OpClosure(_) | OpGetUpvalue(_) | OpClass(_) => (0, 1),
+        OpNegate | OpNot | OpSetGlobal(_) | OpSetLocal(_) | OpJumpIfFalse(_) | OpSetUpvalue(_)
+        | OpGetProperty(..) => (1, 1),
+        OpAdd | OpSubtract | OpMultiply | OpDivide | OpEqual | OpNotEqual | OpGreater
+        | OpGreaterEqual | OpLess | OpLessEqual | OpInherit | OpMethod(_) | OpSetProperty(..)
+        | OpGetSuper(_) => (2, 1),
+        OpJump(_) | OpLoop(_) | OpRethrow(_) => (0, 0),
+        OpCall(argc) | OpInvoke(_, argc, _) => (argc + 1, 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bytecode::{assembler::assemble, vm::Vm};
+
+    use super::*;
+
+    fn error(listing: &str) -> String {
+        let chunk = assemble(listing).unwrap();
+        verify(&chunk).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn valid() {
+        let chunk = assemble(
+            r#"
+            OP_CLOSURE add
+            OP_CONSTANT 1
+            OP_CONSTANT 2
+            OP_CALL 2
+            OP_DEFINE_GLOBAL three
+
+            == add (arity 2) ==
+            OP_GET_LOCAL 1
+            OP_GET_LOCAL 2
+            OP_ADD
+            OP_RETURN
+            "#,
+        )
+        .unwrap();
+        verify(&chunk).unwrap();
+        let mut vm = Vm::load(chunk).unwrap();
+        vm.run().unwrap();
+        assert_eq!(vm.global("three"), Some(&Value::number(3.0)));
+    }
+
+    #[test]
+    fn stack_depth() {
+        assert_eq!(
+            error("OP_CONSTANT 1\nOP_ADD"),
+            "VerifyError: stack underflow, OP_ADD needs 2 values, found 1, in script at 0002"
+        );
+        // the branches leave different depths
+        assert_eq!(
+            error(
+                "OP_TRUE
+                OP_JUMP_IF_FALSE else
+                OP_CONSTANT 1
+                else:
+                OP_POP"
+            ),
+            "VerifyError: stack depth 2 doesn't match depth 1 at 0006, in script at 0004"
+        );
+        // each iteration pushes a value
+        assert_eq!(
+            error("start:\nOP_NULL\nOP_LOOP start"),
+            "VerifyError: stack depth 1 doesn't match depth 0 at 0000, in script at 0001"
+        );
+        assert_eq!(
+            error("OP_NULL\nOP_GET_LOCAL 1"),
+            "VerifyError: local slot 1 out of range, in script at 0001"
+        );
+    }
+
+    #[test]
+    fn functions() {
+        assert_eq!(
+            error("OP_CLOSURE f\n== f (arity 0) ==\nOP_NULL"),
+            "VerifyError: function doesn't end with OP_RETURN, in f at 0001"
+        );
+        assert_eq!(
+            error("OP_CLOSURE f\n== f (arity 0) ==\nOP_GET_UPVALUE 0\nOP_RETURN"),
+            "VerifyError: upvalue 0 out of range, in f at 0000"
+        );
+        assert_eq!(
+            error("OP_CLOSURE f\n| local 0\n== f (arity 0) ==\nOP_NULL\nOP_RETURN"),
+            "VerifyError: captured variable 0 out of range, in script at 0000"
+        );
+    }
+
+    #[test]
+    fn handlers() {
+        let chunk = assemble(
+            r#"
+            OP_NULL
+            start:
+            OP_CONSTANT 1
+            OP_THROW
+            end:
+            OP_CONSTANT 2
+            OP_DEFINE_GLOBAL caught
+            catch:
+            OP_DEFINE_GLOBAL caught
+            OP_POP
+            try start..end -> catch (depth 1)
+            "#,
+        )
+        .unwrap();
+        verify(&chunk).unwrap();
+        let mut vm = Vm::load(chunk).unwrap();
+        vm.run().unwrap();
+        assert_eq!(vm.global("caught"), Some(&Value::number(1.0)));
+
+        assert_eq!(
+            error("OP_NULL\nOP_THROW\nOP_NULL\ntry 0000..0001 -> 0003 (depth 0)"),
+            "VerifyError: handler target out of range, in script at 0000"
+        );
+        assert_eq!(
+            error("OP_CONSTANT 1\nOP_THROW\nOP_POP\ntry 0001..0002 -> 0003 (depth 0)"),
+            "VerifyError: handler range out of range, in script at 0001"
+        );
+        // the handler keeps a slot the covered code doesn't have
+        assert_eq!(
+            error("OP_NULL\nOP_THROW\nOP_POP\nOP_NULL\ntry 0000..0002 -> 0002 (depth 1)"),
+            "VerifyError: stack depth 0 below the 1 slots of its handler, in script at 0000"
+        );
+        // the handler and the code reaching its target disagree
+        assert_eq!(
+            error("OP_NULL\nOP_NULL\nOP_POP\ntry 0000..0001 -> 0002 (depth 0)"),
+            "VerifyError: stack depth 2 doesn't match depth 1 at 0002, in script at 0001"
+        );
+    }
+
+    #[test]
+    fn bad_chunks() {
+        let mut chunk = Chunk::new();
+        chunk.write(OpConstant(3), (1, 1));
+        assert_eq!(
+            verify(&chunk).unwrap_err().message,
+            "OP_CONSTANT constant index 3 out of range"
+        );
+
+        let mut chunk = Chunk::new();
+        chunk.add_constant(1.into());
+        chunk.write(OpGetGlobal(0), (1, 1));
+        assert!(verify(&chunk).unwrap_err().message.contains("not a string"));
+
+        // into the operand of OP_CONSTANT
+        let mut chunk = Chunk::new();
+        chunk.add_constant(1.into());
+        chunk.write(OpJump(1), (1, 1));
+        chunk.write(OpConstant(0), (1, 2));
+        assert_eq!(
+            verify(&chunk).unwrap_err().message,
+            "jump target out of range"
+        );
+
+        // written without allocating its cache
+        let mut chunk = Chunk::new();
+        chunk.add_constant("x".into());
+        chunk.write(OpNull, (1, 1));
+        chunk.write(OpGetProperty(0, 0), (1, 2));
+        assert_eq!(
+            verify(&chunk).unwrap_err().message,
+            "inline cache 0 out of range"
+        );
+
+        let mut chunk = Chunk::new();
+        chunk.write(OpNull, (1, 1));
+        chunk.code.push(200);
+        assert_eq!(
+            verify(&chunk).unwrap_err().message,
+            "unknown or truncated instruction"
+        );
+
+        let mut chunk = Chunk::new();
+        chunk.write(OpNull, (1, 1));
+        chunk.positions[0].start = 1;
+        assert!(verify(&chunk)
+            .unwrap_err()
+            .message
+            .contains("position table"));
+        assert!(Vm::load(chunk).is_err());
+    }
+}
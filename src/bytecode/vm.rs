@@ -1,72 +1,548 @@
+use std::{
+    collections::HashMap,
+    io::Write,
+    rc::Rc,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
 use super::debug;
+use super::heap::{Heap, HeapStats};
+use super::object::{BoundMethod, Class, Closure, Function, Instance, Obj, ObjRef, Upvalue};
+use super::opcode::{CacheIndex, ConstantIndex, MAX_SLOTS};
+use super::shape::{InlineCache, Shapes, EMPTY_SHAPE};
+use super::verify::verify;
 use super::Chunk;
 use super::OpCode;
 use super::OpCode::*;
-use super::Value;
+use super::Pos;
+use super::{Unpacked, Value};
+use crate::builtin::{self, HostFunction};
+use crate::error::{VerifyError, VmError, VmFrame};
 
-#[derive(Debug)]
-pub enum InterpretError {
-    CompileError,
-    RuntimeError,
-}
+// maximum depth of nested calls
+pub const FRAMES_MAX: usize = 1024;
+// maximum number of values on the stack, room for a full frame per call
+pub const STACK_MAX: usize = FRAMES_MAX * MAX_SLOTS;
+
+const CONSTRUCTOR_INITIALIZER: &str = "init";
+
+// last version given to a class
+static CLASS_VERSION: AtomicU64 = AtomicU64::new(0);
 
-pub fn interpret(chunk: Chunk) -> Result<(), InterpretError> {
+pub type InterpretResult<T> = Result<T, VmError>;
+
+pub fn interpret(chunk: Chunk) -> InterpretResult<()> {
     let mut vm = Vm::new(chunk);
     vm.run()
 }
 
 #[derive(Debug)]
+struct CallFrame {
+    closure: ObjRef,
+    // the function of `closure`, kept here to read the code without going through the heap
+    function: Rc<Function>,
+    ip: usize,    // instruction pointer
+    slots: usize, // stack index of the frame's slot 0
+}
+
+impl CallFrame {
+    // position of the instruction being executed, or of the pending call
+    fn position(&self) -> Pos {
+        self.function.chunk.position(self.ip.saturating_sub(1))
+    }
+}
+
+#[derive(Debug, Default)]
 pub struct Vm {
-    chunk: Chunk,
-    ip: usize, // instruction pointer
+    frames: Vec<CallFrame>,
     stack: Vec<Value>,
+    globals: HashMap<Rc<str>, Value>,
+    heap: Heap,
+    // upvalues still pointing to the stack, ordered by slot
+    open_upvalues: Vec<ObjRef>,
+    // the stack and every instruction are written here before executing it
+    trace: Option<Sink>,
+    // printed values, stdout if not set
+    output: Option<Sink>,
+    // field layouts of the instances
+    shapes: Shapes,
+    // look every property up, ignoring the inline caches
+    uncached: bool,
+    limits: Limits,
+    // error of each exception a handler took, with the stack slot of the value:
+    // a finally block throws it again as it was
+    handled: Vec<(usize, VmError)>,
 }
 
-impl Default for Vm {
+// bounds of the call stack, checked when a call starts: within a frame,
+// verified code always has the same stack depth at a given instruction
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    frames: usize,
+    stack: usize,
+}
+
+impl Default for Limits {
     fn default() -> Self {
         Self {
-            chunk: Default::default(),
-            ip: Default::default(),
-            stack: Default::default(),
+            frames: FRAMES_MAX,
+            stack: STACK_MAX,
         }
     }
 }
 
+// destination of the execution trace or of printed values
+pub struct Sink(pub(super) Box<dyn Write>);
+
+impl std::fmt::Debug for Sink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Sink")
+    }
+}
+
 impl Vm {
-    fn new(chunk: Chunk) -> Self {
-        Self {
-            chunk,
-            ip: Default::default(),
-            stack: Default::default(),
+    /**
+     * vm ready to run `chunk`, with the builtins defined
+     */
+    pub fn new(chunk: Chunk) -> Self {
+        let mut vm = Self::default();
+        for native in builtin::builtins() {
+            vm.define_native(native);
         }
+        vm.load_script(chunk);
+        vm
+    }
+
+    /**
+     * verify the chunk before running it, for bytecode not produced by the compiler
+     */
+    pub fn load(chunk: Chunk) -> Result<Self, VerifyError> {
+        verify(&chunk)?;
+        Ok(Self::new(chunk))
+    }
+
+    /**
+     * run another script on this vm, with the globals left by the previous ones
+     */
+    pub fn interpret(&mut self, chunk: Chunk) -> InterpretResult<()> {
+        self.reset();
+        self.load_script(chunk);
+        self.run()
+    }
+
+    /**
+     * run the loaded script. after an error the vm is empty again, ready for
+     * `interpret`: only the globals remain
+     */
+    pub fn run(&mut self) -> InterpretResult<()> {
+        let result = self.execute();
+        if result.is_err() {
+            self.reset();
+        }
+        result
+    }
+
+    fn load_script(&mut self, chunk: Chunk) {
+        let function = Rc::new(Function {
+            chunk,
+            ..Default::default()
+        });
+        let closure = self.alloc(Obj::Closure(Closure {
+            function: function.clone(),
+            upvalues: vec![],
+        }));
+        // the script has no callee slot, its locals start at 0
+        self.frames.push(CallFrame {
+            closure,
+            function,
+            ip: 0,
+            slots: 0,
+        });
     }
 
-    fn run(&mut self) -> Result<(), InterpretError> {
-        while self.ip < self.chunk.codes.len() {
-            self.trace();
+    // drop what an unfinished script left on the stack,
+    // closures still referencing it get their values
+    fn reset(&mut self) {
+        self.close_upvalues(0);
+        self.stack.clear();
+        self.frames.clear();
+        self.handled.clear();
+    }
+
+    fn execute(&mut self) -> InterpretResult<()> {
+        loop {
+            let Some(frame) = self.frames.last_mut() else {
+                return Ok(());
+            };
+            let chunk = &frame.function.chunk;
+            if frame.ip >= chunk.code.len() {
+                // the end of the script, functions always end with OP_RETURN
+                return Ok(());
+            }
+            let (code, next) = chunk.read(frame.ip);
+            let offset = std::mem::replace(&mut frame.ip, next);
+            if self.trace.is_some() {
+                self.trace(offset);
+            }
 
-            match self.chunk.codes.get(self.ip).unwrap() {
+            match code {
                 OpReturn => {
-                    let value = self.pop();
-                    println!("\n\n-----------------\nRETURN VALUE: \n");
-                    println!("> {:?}\n\n", value);
+                    let result = self.pop();
+                    let frame = self.frames.pop().unwrap();
+                    self.close_upvalues(frame.slots);
+                    self.stack.truncate(frame.slots);
+                    if self.frames.is_empty() {
+                        return Ok(());
+                    }
+                    self.push(result);
                 }
-                OpConstant(idx) => {
-                    let value = self.chunk.read_constant(*idx);
+                OpConstant(idx) | OpConstantLong(idx) => {
+                    let value = self.read_constant(idx);
                     self.push(value);
                 }
+                OpNull => self.push(Value::NULL),
+                OpTrue => self.push(Value::boolean(true)),
+                OpFalse => self.push(Value::boolean(false)),
                 OpNegate => {
-                    let val = -self.pop();
-                    self.push(val);
+                    let value = self.pop();
+                    match value.as_number() {
+                        Some(n) => self.push(Value::number(-n)),
+                        None => {
+                            return Err(self.error(format!(
+                                "invalid operand for '-': {}",
+                                self.type_name(&value)
+                            )))
+                        }
+                    }
+                }
+                OpNot => {
+                    let value = self.pop();
+                    self.push(Value::boolean(!value.is_truthy()));
+                }
+                OpEqual => {
+                    let y = self.pop();
+                    let x = self.pop();
+                    self.push(Value::boolean(x == y));
+                }
+                OpNotEqual => {
+                    let y = self.pop();
+                    let x = self.pop();
+                    self.push(Value::boolean(x != y));
+                }
+                OpAdd | OpSubtract | OpMultiply | OpDivide | OpGreater | OpGreaterEqual
+                | OpLess | OpLessEqual => self.binary_op(code)?,
+                OpPrint => {
+                    let value = self.pop();
+                    let line = format!(" > print: {}", self.format(&value));
+                    match &mut self.output {
+                        // like the trace, a failing sink doesn't stop the program
+                        Some(sink) => {
+                            let _ = writeln!(sink.0, "{}", line);
+                        }
+                        None => println!("{}", line),
+                    }
+                }
+                OpPop => {
+                    self.pop();
+                }
+                OpDefineGlobal(idx) => {
+                    let name = self.read_name(idx)?;
+                    let value = self.pop();
+                    self.globals.insert(name, value);
+                }
+                OpGetGlobal(idx) => {
+                    let name = self.read_name(idx)?;
+                    match self.globals.get(&name) {
+                        Some(value) => self.push(value.clone()),
+                        None => return Err(self.error(format!("{} is not defined", name))),
+                    }
+                }
+                OpSetGlobal(idx) => {
+                    let name = self.read_name(idx)?;
+                    // assignment is an expression, the value stays on the stack
+                    let value = self.peek().clone();
+                    match self.globals.get_mut(&name) {
+                        Some(slot) => *slot = value,
+                        None => return Err(self.error(format!("{} is not defined", name))),
+                    }
+                }
+                OpGetLocal(slot) => {
+                    let value = self.stack[self.frame().slots + slot].clone();
+                    self.push(value);
+                }
+                OpSetLocal(slot) => {
+                    let slot = self.frame().slots + slot;
+                    self.stack[slot] = self.peek().clone();
+                }
+                // the condition is left on the stack, the compiler pops it
+                OpJump(offset) => self.frame_mut().ip += offset,
+                OpJumpIfFalse(offset) => {
+                    if !self.peek().is_truthy() {
+                        self.frame_mut().ip += offset;
+                    }
+                }
+                OpLoop(offset) => self.frame_mut().ip -= offset,
+                OpCall(argc) => {
+                    let callee = self.stack[self.stack.len() - 1 - argc].clone();
+                    self.call_value(callee, argc)?;
+                }
+                OpClosure(idx) => {
+                    let constant = self.read_constant(idx);
+                    let Some(function) = constant.as_function().cloned() else {
+                        return Err(self.error("closure of a non-function constant".to_string()));
+                    };
+                    let slots = self.frame().slots;
+                    let enclosing = self.frame().closure;
+                    let upvalues = function
+                        .upvalues
+                        .iter()
+                        .map(|desc| match desc.is_local {
+                            true => self.capture_upvalue(slots + desc.index),
+                            false => self.heap.closure(enclosing).upvalues[desc.index],
+                        })
+                        .collect();
+                    let closure = self.alloc(Obj::Closure(Closure { function, upvalues }));
+                    self.push(Value::object(closure));
+                }
+                OpGetUpvalue(idx) => {
+                    let upvalue = self.heap.closure(self.frame().closure).upvalues[idx];
+                    let value = match self.heap.upvalue(upvalue) {
+                        Upvalue::Open(slot) => match self.stack.get(*slot) {
+                            Some(value) => value.clone(),
+                            None => return Err(self.upvalue_error(*slot)),
+                        },
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.push(value);
+                }
+                OpSetUpvalue(idx) => {
+                    let upvalue = self.heap.closure(self.frame().closure).upvalues[idx];
+                    let value = self.peek().clone();
+                    match self.heap.upvalue_mut(upvalue) {
+                        Upvalue::Open(slot) => match self.stack.get_mut(*slot) {
+                            Some(variable) => *variable = value,
+                            None => {
+                                let slot = *slot;
+                                return Err(self.upvalue_error(slot));
+                            }
+                        },
+                        Upvalue::Closed(closed) => *closed = value,
+                    }
+                }
+                OpCloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                OpClass(idx) => {
+                    let name = self.read_name(idx)?;
+                    let version = next_class_version();
+                    let class = self.alloc(Obj::Class(Class {
+                        name,
+                        methods: HashMap::new(),
+                        version,
+                    }));
+                    self.push(Value::object(class));
+                }
+                OpInherit => {
+                    // [superclass, subclass], the superclass stays as the `super` local
+                    let subclass = self.pop();
+                    let superclass = self.peek().clone();
+                    let methods = match superclass.as_object().map(|r| self.heap.get(r)) {
+                        Some(Obj::Class(class)) => Some(class.methods.clone()),
+                        _ => None,
+                    };
+                    let Some(methods) = methods else {
+                        return Err(self.error("superclass must be a class".to_string()));
+                    };
+                    // copied before the subclass methods are defined, so they can override
+                    let subclass = self.expect_class(&subclass)?;
+                    let version = next_class_version();
+                    let class = self.heap.class_mut(subclass);
+                    class.methods.extend(methods);
+                    class.version = version;
+                }
+                OpMethod(idx) => {
+                    let name = self.read_name(idx)?;
+                    let method = self.pop();
+                    let method = self.expect_closure(&method)?;
+                    let class = self.expect_class(self.peek())?;
+                    let version = next_class_version();
+                    let class = self.heap.class_mut(class);
+                    class.methods.insert(name, method);
+                    class.version = version;
+                }
+                OpGetProperty(idx, cache) => {
+                    let receiver = self.pop();
+                    let instance = self.expect_instance(&receiver)?;
+                    let value = match self.property(instance, idx, cache)? {
+                        Property::Field(value) => value,
+                        Property::Method(method) => {
+                            let bound =
+                                self.alloc(Obj::BoundMethod(BoundMethod { receiver, method }));
+                            Value::object(bound)
+                        }
+                    };
+                    self.push(value);
+                }
+                OpSetProperty(idx, cache) => {
+                    let value = self.pop();
+                    let receiver = self.pop();
+                    let instance = self.expect_instance(&receiver)?;
+                    self.set_property(instance, idx, cache, value.clone())?;
+                    self.push(value);
+                }
+                OpGetSuper(idx) => {
+                    let name = self.read_name(idx)?;
+                    let superclass = self.pop();
+                    let receiver = self.pop();
+                    let superclass = self.expect_class(&superclass)?;
+                    let method = self.bind_method(superclass, receiver, &name)?;
+                    self.push(method);
+                }
+                OpInvoke(idx, argc, cache) => self.invoke(idx, argc, cache)?,
+                OpThrow => {
+                    let value = self.pop();
+                    let error = self.error(format!("uncaught exception: {}", self.format(&value)));
+                    self.throw(value, error)?;
+                }
+                OpRethrow(slot) => {
+                    let slot = self.frame().slots + slot;
+                    let value = self.stack[slot].clone();
+                    // the values above are gone with the finally block
+                    self.handled.retain(|(handled, _)| *handled <= slot);
+                    let error = match self.handled.last() {
+                        Some((handled, _)) if *handled == slot => self.handled.pop().unwrap().1,
+                        // not put there by a handler
+                        _ => self.error(format!("uncaught exception: {}", self.format(&value))),
+                    };
+                    self.throw(value, error)?;
                 }
-                OpAdd => self.binary_op(OpAdd),
-                OpSubtract => self.binary_op(OpSubtract),
-                OpMultiply => self.binary_op(OpMultiply),
-                OpDivide => self.binary_op(OpDivide),
             };
-            self.ip += 1;
         }
-        Ok(())
+    }
+
+    /**
+     * collect garbage before every allocation, for debugging
+     */
+    pub fn with_stress_gc(mut self, stress: bool) -> Self {
+        self.heap.stress = stress;
+        self
+    }
+
+    /**
+     * trace the execution into `sink`, for debugging
+     */
+    pub fn with_trace(mut self, sink: impl Write + 'static) -> Self {
+        self.trace = Some(Sink(Box::new(sink)));
+        self
+    }
+
+    /**
+     * write printed values into `sink` instead of stdout
+     */
+    pub fn with_output(mut self, sink: impl Write + 'static) -> Self {
+        self.output = Some(Sink(Box::new(sink)));
+        self
+    }
+
+    /**
+     * limit the depth of nested calls, the script counts as one
+     */
+    pub fn with_max_frames(mut self, frames: usize) -> Self {
+        self.limits.frames = frames;
+        self
+    }
+
+    /**
+     * limit the number of values on the stack
+     */
+    pub fn with_max_stack(mut self, values: usize) -> Self {
+        self.limits.stack = values;
+        self
+    }
+
+    /**
+     * resolve every property access by name, for comparing with the inline caches
+     */
+    pub fn with_inline_caches(mut self, enabled: bool) -> Self {
+        self.uncached = !enabled;
+        self
+    }
+
+    pub fn heap_stats(&self) -> HeapStats {
+        self.heap.stats()
+    }
+
+    pub fn collect_garbage(&mut self) {
+        self.collect(None);
+    }
+
+    // allocation may trigger a collection
+    fn alloc(&mut self, obj: Obj) -> ObjRef {
+        if self.heap.should_collect() {
+            self.collect(Some(&obj));
+        }
+        self.heap.alloc(obj)
+    }
+
+    // `pending` is the object being allocated, what it references is still in use
+    fn collect(&mut self, pending: Option<&Obj>) {
+        for value in self.stack.iter() {
+            self.heap.mark_value(value);
+        }
+        for value in self.globals.values() {
+            self.heap.mark_value(value);
+        }
+        for frame in self.frames.iter() {
+            self.heap.mark_object(frame.closure);
+        }
+        for upvalue in self.open_upvalues.iter() {
+            self.heap.mark_object(*upvalue);
+        }
+        if let Some(obj) = pending {
+            let mut refs = vec![];
+            obj.references(&mut refs);
+            refs.into_iter().for_each(|r| self.heap.mark_object(r));
+        }
+        self.heap.collect();
+    }
+
+    /**
+     * define a function implemented in rust as a global, under its name.
+     * the same `Rc` can be defined in an interpreter too
+     */
+    pub fn define_native(&mut self, native: Rc<dyn HostFunction>) {
+        self.globals
+            .insert(native.name().into(), Value::native(native));
+    }
+
+    pub fn global(&self, name: &str) -> Option<&Value> {
+        self.globals.get(name)
+    }
+
+    /**
+     * display a value, objects are looked up in the heap
+     */
+    pub fn format(&self, value: &Value) -> String {
+        match value.as_object() {
+            Some(r) => self.heap.format(r),
+            None => value.to_string(),
+        }
+    }
+
+    fn type_name(&self, value: &Value) -> &'static str {
+        match value.as_object() {
+            Some(r) => self.heap.get(r).type_name(),
+            None => value.type_name(),
+        }
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().unwrap()
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().unwrap()
     }
 
     fn push(&mut self, value: Value) {
@@ -74,32 +550,423 @@ impl Vm {
     }
 
     fn pop(&mut self) -> Value {
-        self.stack.pop().unwrap_or(Value::Null)
+        self.stack.pop().unwrap_or(Value::NULL)
+    }
+
+    // verified code never peeks at an empty stack
+    fn peek(&self) -> &Value {
+        self.stack.last().expect("stack underflow")
+    }
+
+    fn read_constant(&self, idx: usize) -> Value {
+        self.frame().function.chunk.read_constant(idx)
+    }
+
+    // variable names are string constants
+    fn read_name(&self, idx: usize) -> InterpretResult<Rc<str>> {
+        let constant = self.read_constant(idx);
+        match constant.as_string() {
+            Some(name) => Ok(name.clone()),
+            None => Err(self.error(format!("invalid name constant {}", self.format(&constant)))),
+        }
+    }
+
+    fn call_value(&mut self, callee: Value, argc: usize) -> InterpretResult<()> {
+        let slot = self.stack.len() - 1 - argc;
+        if let Some(native) = callee.as_native() {
+            return self.call_native(native, argc);
+        }
+        if let Some(r) = callee.as_object() {
+            match self.heap.get(r) {
+                Obj::Closure(_) => return self.call(r, argc),
+                Obj::BoundMethod(bound) => {
+                    // the receiver takes the callee slot, it is `this` in the method
+                    let method = bound.method;
+                    self.stack[slot] = bound.receiver.clone();
+                    return self.call(method, argc);
+                }
+                Obj::Class(class) => {
+                    let init = class.methods.get(CONSTRUCTOR_INITIALIZER).copied();
+                    let instance = self.alloc(Obj::Instance(Instance {
+                        class: r,
+                        shape: EMPTY_SHAPE,
+                        fields: Vec::new(),
+                    }));
+                    self.stack[slot] = Value::object(instance);
+                    return match init {
+                        Some(init) => self.call(init, argc),
+                        None if argc != 0 => Err(self.error(format!(
+                            "{} expected 0 arguments but got {}",
+                            self.heap.class(r).name,
+                            argc
+                        ))),
+                        None => Ok(()),
+                    };
+                }
+                _ => (),
+            }
+        }
+        Err(self.error(format!("{} is not callable", self.type_name(&callee))))
+    }
+
+    // no frame: the arguments are read in place, the result replaces them and the callee
+    fn call_native(&mut self, native: &Rc<dyn HostFunction>, argc: usize) -> InterpretResult<()> {
+        builtin::check_arity(native.as_ref(), argc).map_err(|msg| self.error(msg))?;
+        let slot = self.stack.len() - 1 - argc;
+        let result = native
+            .call_vm(&self.stack[slot + 1..])
+            .map_err(|msg| self.error(msg))?;
+        self.stack.truncate(slot);
+        self.stack.push(result);
+        Ok(())
+    }
+
+    fn call(&mut self, closure: ObjRef, argc: usize) -> InterpretResult<()> {
+        let function = self.heap.closure(closure).function.clone();
+        if argc != function.arity {
+            return Err(self.error(format!(
+                "{} expected {} arguments but got {}",
+                function.name(),
+                function.arity,
+                argc
+            )));
+        }
+        if self.frames.len() >= self.limits.frames || self.stack.len() > self.limits.stack {
+            return Err(self.error("stack overflow".to_string()));
+        }
+
+        self.frames.push(CallFrame {
+            closure,
+            function,
+            ip: 0,
+            slots: self.stack.len() - argc - 1,
+        });
+        Ok(())
+    }
+
+    // call a method without allocating the bound method
+    fn invoke(
+        &mut self,
+        idx: ConstantIndex,
+        argc: usize,
+        cache: CacheIndex,
+    ) -> InterpretResult<()> {
+        let slot = self.stack.len() - 1 - argc;
+        let instance = self.expect_instance(&self.stack[slot])?;
+        match self.property(instance, idx, cache)? {
+            // a field holding a function
+            Property::Field(value) => {
+                self.stack[slot] = value.clone();
+                self.call_value(value, argc)
+            }
+            Property::Method(method) => self.call(method, argc),
+        }
+    }
+
+    // a field of the instance, or else a method of its class.
+    // the cache of the instruction remembers where it was found
+    fn property(
+        &self,
+        instance: ObjRef,
+        idx: ConstantIndex,
+        cache: CacheIndex,
+    ) -> InterpretResult<Property> {
+        let Instance {
+            class,
+            shape,
+            ref fields,
+        } = *self.heap.instance(instance);
+        // versions are unique, a matching one also means the same class and vm
+        let version = self.heap.class(class).version;
+        let cache = &self.frame().function.chunk.caches[cache];
+        if !self.uncached {
+            match cache.get() {
+                InlineCache::Field {
+                    shape: s,
+                    version: v,
+                    slot,
+                } if s == shape && v == version => {
+                    return Ok(Property::Field(fields[slot].clone()))
+                }
+                InlineCache::Method {
+                    shape: s,
+                    version: v,
+                    method,
+                } if s == shape && v == version => return Ok(Property::Method(method)),
+                _ => (),
+            }
+        }
+
+        let name = self.read_name(idx)?;
+        let (property, entry) = match self.shapes.slot(shape, &name) {
+            Some(slot) => (
+                Property::Field(fields[slot].clone()),
+                InlineCache::Field {
+                    shape,
+                    version,
+                    slot,
+                },
+            ),
+            None => match self.heap.class(class).methods.get(&name).copied() {
+                Some(method) => (
+                    Property::Method(method),
+                    InlineCache::Method {
+                        shape,
+                        version,
+                        method,
+                    },
+                ),
+                None => return Err(self.error(format!("undefined property {}", name))),
+            },
+        };
+        if !self.uncached {
+            cache.set(entry);
+        }
+        Ok(property)
+    }
+
+    // a missing field is added in the next slot, moving the instance to a new shape
+    fn set_property(
+        &mut self,
+        instance: ObjRef,
+        idx: ConstantIndex,
+        cache: CacheIndex,
+        value: Value,
+    ) -> InterpretResult<()> {
+        let Instance { class, shape, .. } = *self.heap.instance(instance);
+        let version = self.heap.class(class).version;
+        let entry = match self.frame().function.chunk.caches[cache].get() {
+            entry @ (InlineCache::Field {
+                shape: s,
+                version: v,
+                ..
+            }
+            | InlineCache::Transition {
+                from: s,
+                version: v,
+                ..
+            }) if s == shape && v == version && !self.uncached => entry,
+            _ => {
+                let name = self.read_name(idx)?;
+                let entry = match self.shapes.slot(shape, &name) {
+                    Some(slot) => InlineCache::Field {
+                        shape,
+                        version,
+                        slot,
+                    },
+                    None => InlineCache::Transition {
+                        from: shape,
+                        version,
+                        to: self.shapes.add_field(shape, &name),
+                    },
+                };
+                if !self.uncached {
+                    self.frame().function.chunk.caches[cache].set(entry);
+                }
+                entry
+            }
+        };
+        let instance = self.heap.instance_mut(instance);
+        match entry {
+            InlineCache::Field { slot, .. } => instance.fields[slot] = value,
+            InlineCache::Transition { to, .. } => {
+                instance.shape = to;
+                instance.fields.push(value);
+            }
+            // never chosen above
+            InlineCache::Method { .. } | InlineCache::Empty => (),
+        }
+        Ok(())
+    }
+
+    fn bind_method(
+        &mut self,
+        class: ObjRef,
+        receiver: Value,
+        name: &str,
+    ) -> InterpretResult<Value> {
+        match self.heap.class(class).methods.get(name).copied() {
+            Some(method) => {
+                let bound = self.alloc(Obj::BoundMethod(BoundMethod { receiver, method }));
+                Ok(Value::object(bound))
+            }
+            None => Err(self.error(format!("undefined property {}", name))),
+        }
+    }
+
+    fn expect_instance(&self, value: &Value) -> InterpretResult<ObjRef> {
+        match value.as_object() {
+            Some(r) if matches!(self.heap.get(r), Obj::Instance(_)) => Ok(r),
+            _ => Err(self.error(format!(
+                "only instances have properties, found {}",
+                self.type_name(value)
+            ))),
+        }
+    }
+
+    // operands the compiler guarantees to be classes and closures,
+    // the verifier doesn't follow the kinds of values
+    fn expect_class(&self, value: &Value) -> InterpretResult<ObjRef> {
+        match value.as_object() {
+            Some(r) if matches!(self.heap.get(r), Obj::Class(_)) => Ok(r),
+            _ => Err(self.error(format!("expect class, found {}", self.type_name(value)))),
+        }
+    }
+
+    fn expect_closure(&self, value: &Value) -> InterpretResult<ObjRef> {
+        match value.as_object() {
+            Some(r) if matches!(self.heap.get(r), Obj::Closure(_)) => Ok(r),
+            _ => Err(self.error(format!("expect function, found {}", self.type_name(value)))),
+        }
+    }
+
+    // reuse the open upvalue of the slot, so closures share the variable
+    fn capture_upvalue(&mut self, slot: usize) -> ObjRef {
+        let mut at = self.open_upvalues.len();
+        for (i, r) in self.open_upvalues.iter().enumerate().rev() {
+            match self.heap.upvalue(*r) {
+                Upvalue::Open(s) if *s == slot => return *r,
+                Upvalue::Open(s) if *s < slot => break,
+                _ => at = i,
+            }
+        }
+        let upvalue = self.alloc(Obj::Upvalue(Upvalue::Open(slot)));
+        self.open_upvalues.insert(at, upvalue);
+        upvalue
+    }
+
+    // move the variables at or above `last` off the stack into their upvalues.
+    // compiled code closes a variable before popping it, a corrupted chunk may not:
+    // such a variable is closed as null
+    fn close_upvalues(&mut self, last: usize) {
+        while let Some(r) = self.open_upvalues.last().copied() {
+            let upvalue = self.heap.upvalue_mut(r);
+            match upvalue {
+                Upvalue::Open(slot) if *slot >= last => {
+                    let value = self.stack.get(*slot).cloned().unwrap_or(Value::NULL);
+                    *upvalue = Upvalue::Closed(value);
+                    self.open_upvalues.pop();
+                }
+                _ => break,
+            }
+        }
+    }
+
+    fn upvalue_error(&self, slot: usize) -> VmError {
+        self.error(format!(
+            "captured variable in slot {} is off the stack",
+            slot
+        ))
+    }
+
+    // unwind to the innermost handler covering the throw or a pending call,
+    // `error` is the result when no handler takes the value
+    fn throw(&mut self, value: Value, error: VmError) -> InterpretResult<()> {
+        while let Some(frame) = self.frames.last_mut() {
+            // the ip is past the throw, or past the call of the frame above
+            let offset = frame.ip.saturating_sub(1);
+            if let Some(handler) = frame.function.chunk.handler(offset).copied() {
+                frame.ip = handler.target;
+                let base = frame.slots + handler.depth;
+                self.close_upvalues(base);
+                self.stack.truncate(base);
+                self.push(value);
+                // the errors of the values cut off the stack are left behind
+                self.handled.retain(|(slot, _)| *slot < base);
+                self.handled.push((base, error));
+                return Ok(());
+            }
+            self.frames.pop();
+        }
+        Err(error)
     }
 
-    fn binary_op(&mut self, op: OpCode) {
+    fn binary_op(&mut self, op: OpCode) -> InterpretResult<()> {
         let y = self.pop();
         let x = self.pop();
-        let result = match op {
-            OpAdd => x + y,
-            OpSubtract => x - y,
-            OpMultiply => x * y,
-            OpDivide => x / y,
-            _ => unreachable!(),
+        let result = match (x.unpack(), y.unpack()) {
+            (Unpacked::Number(x), Unpacked::Number(y)) => match op {
+                OpAdd => Value::number(x + y),
+                OpSubtract => Value::number(x - y),
+                OpMultiply => Value::number(x * y),
+                OpDivide => Value::number(x / y),
+                OpGreater => Value::boolean(x > y),
+                OpGreaterEqual => Value::boolean(x >= y),
+                OpLess => Value::boolean(x < y),
+                OpLessEqual => Value::boolean(x <= y),
+                _ => unreachable!(),
+            },
+            (Unpacked::String(x), Unpacked::String(y)) if op == OpAdd => {
+                Value::string(format!("{}{}", x, y).into())
+            }
+            _ => {
+                return Err(self.error(format!(
+                    "invalid operands for '{}': {} and {}",
+                    operator(op),
+                    self.type_name(&x),
+                    self.type_name(&y)
+                )))
+            }
         };
         self.push(result);
+        Ok(())
     }
 
-    fn trace(&self) {
-        print!("          ");
-        for i in &self.stack {
-            print!("[ ");
-            print!("{:?}", i);
-            print!(" ]");
-        }
-        println!();
+    // runtime error located at the instruction being executed,
+    // its last byte shares its position
+    fn error(&self, msg: String) -> VmError {
+        // a frame's call site is where its caller stopped
+        let backtrace = self
+            .frames
+            .windows(2)
+            .map(|pair| VmFrame {
+                name: pair[1].function.name().to_string(),
+                call_site: pair[0].position(),
+            })
+            .collect();
+        VmError::new(msg, self.frame().position(), backtrace)
+    }
 
-        debug::disassemble_instruction(&self.chunk, self.ip);
+    fn trace(&mut self, offset: usize) {
+        let Some(mut sink) = self.trace.take() else {
+            return;
+        };
+        let stack: String = self
+            .stack
+            .iter()
+            .map(|value| format!("[ {} ]", self.format(value)))
+            .collect();
+        // the trace is best effort, a failing sink doesn't stop the program
+        let _ = writeln!(sink.0, "          {}", stack).and_then(|_| {
+            debug::write_instruction(&mut sink.0, &self.frame().function.chunk, offset)
+        });
+        self.trace = Some(sink);
+    }
+}
+
+// a class gets a new version whenever its methods change
+fn next_class_version() -> u64 {
+    CLASS_VERSION.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+// what a property name resolves to on an instance
+enum Property {
+    Field(Value),
+    Method(ObjRef),
+}
+
+// source operator of a binary instruction, for error messages
+fn operator(op: OpCode) -> &'static str {
+    match op {
+        OpAdd => "+",
+        OpSubtract => "-",
+        OpMultiply => "*",
+        OpDivide => "/",
+        OpGreater => ">",
+        OpGreaterEqual => ">=",
+        OpLess => "<",
+        OpLessEqual => "<=",
+        _ => "?",
     }
 }
 
@@ -136,4 +1003,607 @@ mod tests {
 
         interpret(chunk).unwrap();
     }
+
+    fn run_chunk(codes: &[OpCode], constants: Vec<Value>) -> InterpretResult<Vec<Value>> {
+        let mut chunk = Chunk::new();
+        chunk.constants = constants;
+        for (i, code) in codes.iter().enumerate() {
+            chunk.write(*code, (1, i + 1));
+        }
+        let mut vm = Vm::new(chunk);
+        vm.run()?;
+        Ok(vm.stack)
+    }
+
+    #[test]
+    fn comparison() {
+        let stack = run_chunk(
+            &[OpConstant(0), OpConstant(1), OpLess, OpNull, OpNot, OpEqual],
+            vec![1.into(), 2.into()],
+        )
+        .unwrap();
+        assert_eq!(stack, vec![Value::boolean(true)]);
+
+        let stack = run_chunk(
+            &[
+                OpConstant(0),
+                OpConstant(1),
+                OpNotEqual,
+                OpFalse,
+                OpNull,
+                OpEqual,
+            ],
+            vec![1.into(), "1".into()],
+        )
+        .unwrap();
+        assert_eq!(stack, vec![Value::boolean(true), Value::boolean(false)]);
+    }
+
+    #[test]
+    fn string_concat() {
+        let stack = run_chunk(
+            &[OpConstant(0), OpConstant(1), OpAdd, OpConstant(0), OpAdd],
+            vec!["ab".into(), "cd".into()],
+        )
+        .unwrap();
+        assert_eq!(stack, vec!["abcdab".into()]);
+    }
+
+    #[test]
+    fn type_mismatch() {
+        let err = run_chunk(&[OpTrue, OpNegate], vec![]).unwrap_err();
+        assert_eq!(err.pos, (1, 2));
+        assert!(err.backtrace.is_empty());
+
+        let err = run_chunk(&[OpConstant(0), OpTrue, OpMultiply], vec!["a".into()]).unwrap_err();
+        assert_eq!(err.pos, (1, 3));
+        assert!(err.message.contains("'*'"));
+    }
+
+    // a sink the test can read back
+    #[derive(Clone, Default)]
+    struct Buffer(Rc<std::cell::RefCell<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn trace() {
+        let buffer = Buffer::default();
+        let mut vm = Vm::new(compile_source("let a = 1 + 2")).with_trace(buffer.clone());
+        vm.run().unwrap();
+        let trace = String::from_utf8(buffer.0.take()).unwrap();
+        let lines: Vec<_> = trace.lines().collect();
+        assert_eq!(lines.len(), 8);
+        assert!(lines[1].ends_with("OP_CONSTANT    0 '1'"));
+        assert_eq!(lines[4].trim(), "[ 1 ][ 2 ]");
+        assert!(lines[5].ends_with("OP_ADD"));
+        assert!(lines[7].contains("OP_DEFINE_GLOBAL"));
+
+        // off by default
+        let mut vm = Vm::new(compile_source("let a = 1"));
+        assert!(vm.trace.is_none());
+        vm.run().unwrap();
+    }
+
+    fn compile_source(source: &str) -> Chunk {
+        let lexer = crate::lexer::Lexer::new(source.as_bytes(), "test.tx");
+        let ast = crate::parser::parser::Parser::new(lexer).parse().unwrap();
+        super::super::compiler::compile(&ast).unwrap()
+    }
+
+    #[test]
+    fn stress_gc() {
+        let source = r#"
+            class Node {
+                init(value) {
+                    this.value = value
+                    this.self = this
+                }
+                get() {
+                    fn inner() { return this.value }
+                    return inner
+                }
+            }
+            fn makeAdder(n) {
+                fn add(x) { return x + n }
+                return add
+            }
+            let sum = 0
+            let i = 0
+            while (i < 20) {
+                let node = Node(i)
+                let get = node.get()
+                sum = sum + makeAdder(get())(1)
+                i = i + 1
+            }
+        "#;
+        let mut vm = Vm::new(compile_source(source)).with_stress_gc(true);
+        vm.run().unwrap();
+        assert_eq!(vm.global("sum"), Some(&Value::number(210.0)));
+        assert!(vm.heap_stats().collections > 50);
+    }
+
+    #[test]
+    fn stress_gc_single_allocation() {
+        // each loop allocates only closures, or only bound methods
+        let closures = r#"
+            let i = 0
+            while (i < 100) {
+                fn f() { return 1 }
+                i = i + f()
+            }
+        "#;
+        let methods = r#"
+            class A { m() { return 1 } }
+            let a = A()
+            let i = 0
+            while (i < 100) {
+                let m = a.m
+                i = i + m()
+            }
+        "#;
+        let supers = r#"
+            class A { m() { return 1 } }
+            class B extends A {
+                m() {
+                    let i = 0
+                    while (i < 100) {
+                        let m = super.m
+                        i = i + m()
+                    }
+                    return i
+                }
+            }
+            let i = B().m()
+        "#;
+        for source in [closures, methods, supers] {
+            let mut vm = Vm::new(compile_source(source)).with_stress_gc(true);
+            vm.run().unwrap();
+            assert_eq!(vm.global("i"), Some(&Value::number(100.0)));
+            assert!(vm.heap_stats().collections >= 100, "{}", source);
+
+            // without stress, the allocations alone reach the threshold
+            let source = source.replace("100", "50000");
+            let mut vm = Vm::new(compile_source(&source));
+            vm.run().unwrap();
+            assert!(vm.heap_stats().collections > 0, "{}", source);
+        }
+    }
+
+    #[test]
+    fn collect_cycles() {
+        let source = r#"
+            class Node {}
+            let keep = Node()
+            keep.next = keep
+            let i = 0
+            while (i < 100) {
+                let a = Node()
+                let b = Node()
+                a.next = b
+                b.next = a
+                i = i + 1
+            }
+        "#;
+        let mut vm = Vm::new(compile_source(source));
+        vm.run().unwrap();
+        let before = vm.heap_stats();
+        assert!(before.objects > 200);
+
+        vm.collect_garbage();
+        let after = vm.heap_stats();
+        // the script closure, the class and `keep` survive
+        assert_eq!(after.objects, 3);
+        assert_eq!(after.collections, before.collections + 1);
+        assert!(after.bytes_allocated < before.bytes_allocated);
+        assert_eq!(vm.format(vm.global("keep").unwrap()), "<instance of Node>");
+    }
+    #[test]
+    fn inline_caches() {
+        let source = r#"
+            class A {
+                init() { this.x = 1 }
+                m() { return 10 }
+            }
+            class B {
+                init() {
+                    this.y = 0
+                    this.x = 2
+                }
+                m() { return 20 }
+            }
+            // one call site sees both classes
+            fn call(o) { return o.m() + o.x }
+            let sum = 0
+            let i = 0
+            while (i < 10) {
+                sum = sum + call(A()) + call(B())
+                i = i + 1
+            }
+
+            // a field shadows the method
+            fn three() { return 3 }
+            let a = A()
+            a.m = three
+            let shadowed = call(a)
+
+            // the same fields in another order
+            class P {}
+            fn make(first) {
+                let p = P()
+                if (first) {
+                    p.a = 1
+                    p.b = 2
+                } else {
+                    p.b = 3
+                    p.a = 4
+                }
+                return p
+            }
+            fn read(p) { return p.a * 10 + p.b }
+            let ordered = read(make(true)) + read(make(false)) + read(make(true))
+        "#;
+        for cached in [true, false] {
+            let mut vm = Vm::new(compile_source(source)).with_inline_caches(cached);
+            vm.run().unwrap();
+            assert_eq!(vm.global("sum"), Some(&Value::number(330.0)));
+            assert_eq!(vm.global("shadowed"), Some(&Value::number(4.0)));
+            assert_eq!(
+                vm.global("ordered"),
+                Some(&Value::number(12.0 + 43.0 + 12.0))
+            );
+        }
+    }
+
+    #[test]
+    fn method_cache_invalidation() {
+        // the same OP_INVOKE runs before and after the method is replaced
+        let chunk = super::super::assembler::assemble(
+            r#"
+            OP_CLASS A
+            OP_DEFINE_GLOBAL A
+            OP_GET_GLOBAL A
+            OP_CLOSURE one
+            OP_METHOD m
+            OP_POP
+            OP_GET_GLOBAL A
+            OP_CALL 0
+            OP_DEFINE_GLOBAL a
+            OP_CLOSURE call
+            OP_DEFINE_GLOBAL call
+            OP_GET_GLOBAL call
+            OP_CALL 0
+            OP_DEFINE_GLOBAL before
+            OP_GET_GLOBAL A
+            OP_CLOSURE two
+            OP_METHOD m
+            OP_POP
+            OP_GET_GLOBAL call
+            OP_CALL 0
+            OP_DEFINE_GLOBAL after
+            == one (arity 0) ==
+            OP_CONSTANT 1
+            OP_RETURN
+            == call (arity 0) ==
+            OP_GET_GLOBAL a
+            OP_INVOKE (0 args) m
+            OP_RETURN
+            == two (arity 0) ==
+            OP_CONSTANT 2
+            OP_RETURN
+            "#,
+        )
+        .unwrap();
+        let mut vm = Vm::load(chunk).unwrap();
+        vm.run().unwrap();
+        assert_eq!(vm.global("before"), Some(&Value::number(1.0)));
+        assert_eq!(vm.global("after"), Some(&Value::number(2.0)));
+    }
+
+    // verified chunks with operands of the wrong kind fail instead of panicking
+    #[test]
+    fn invalid_operands() {
+        let run = |listing: &str| {
+            let chunk = super::super::assembler::assemble(listing).unwrap();
+            let mut vm = Vm::load(chunk).unwrap();
+            vm.run().unwrap_err().message
+        };
+        let f = "\n== f (arity 0) ==\nOP_NULL\nOP_RETURN";
+        assert_eq!(
+            run(&format!("OP_TRUE\nOP_CLOSURE f\nOP_METHOD m{}", f)),
+            "expect class, found boolean"
+        );
+        assert_eq!(
+            run(&format!(
+                "OP_CLOSURE f\nOP_CLOSURE g\nOP_METHOD m{}\n== g (arity 0) ==\nOP_NULL\nOP_RETURN",
+                f
+            )),
+            "expect class, found function"
+        );
+        assert_eq!(
+            run("OP_CLASS A\nOP_NULL\nOP_METHOD m"),
+            "expect function, found null"
+        );
+        assert_eq!(
+            run("OP_NULL\nOP_TRUE\nOP_GET_SUPER m"),
+            "expect class, found boolean"
+        );
+        assert_eq!(
+            run("OP_CLASS A\nOP_TRUE\nOP_INHERIT"),
+            "expect class, found boolean"
+        );
+
+        // a captured variable popped without closing it
+        let popped = "OP_NULL\nOP_NULL\nOP_NULL\nOP_CLOSURE f\n| local 2\nOP_DEFINE_GLOBAL g
+            OP_POP\nOP_POP\nOP_POP\nOP_GET_GLOBAL g\nOP_CALL 0\n== f (arity 0) ==\n";
+        assert_eq!(
+            run(&format!("{}OP_GET_UPVALUE 0\nOP_RETURN", popped)),
+            "captured variable in slot 2 is off the stack"
+        );
+        assert_eq!(
+            run(&format!("{}OP_NULL\nOP_SET_UPVALUE 0\nOP_RETURN", popped)),
+            "captured variable in slot 2 is off the stack"
+        );
+
+        // what the verifier rejects, run without it
+        let mut chunk = Chunk::new();
+        let idx = chunk.add_constant(1.into());
+        chunk.write(OpClosure(idx), (1, 1));
+        let err = Vm::new(chunk).run().unwrap_err();
+        assert_eq!(err.message, "closure of a non-function constant");
+
+        let mut chunk = Chunk::new();
+        let idx = chunk.add_constant(1.into());
+        chunk.write(OpGetGlobal(idx), (1, 1));
+        let err = Vm::new(chunk).run().unwrap_err();
+        assert_eq!(err.message, "invalid name constant 1");
+    }
+
+    #[test]
+    fn stack_overflow() {
+        let source = "fn down(n) {\n  return down(n + 1)\n}\ndown(0)";
+        let mut vm = Vm::new(compile_source(source)).with_max_frames(16);
+        let err = vm.run().unwrap_err();
+        assert_eq!(err.message, "stack overflow");
+        assert_eq!(err.pos, (2, 10));
+        // the script isn't in the backtrace, every call is
+        assert_eq!(err.backtrace.len(), 15);
+        assert_eq!(err.backtrace[0].call_site, (4, 1));
+        assert!(err.backtrace[1..]
+            .iter()
+            .all(|frame| frame.name == "down" && frame.call_site == (2, 10)));
+
+        // a deep stack with few frames
+        let source = "fn wide(a, b, c, d, e, f, g, h) {\n  return wide(a, b, c, d, e, f, g, h)\n}\nwide(1, 2, 3, 4, 5, 6, 7, 8)";
+        let mut vm = Vm::new(compile_source(source)).with_max_stack(100);
+        let err = vm.run().unwrap_err();
+        assert_eq!(err.message, "stack overflow");
+        assert!(err.backtrace.len() < 16);
+    }
+
+    #[test]
+    fn reusable_after_error() {
+        let output = Buffer::default();
+        let mut vm = Vm::default().with_max_frames(8).with_output(output.clone());
+        // the closure still reads its variable once the stack is gone
+        let source = r#"
+        let get
+        {
+            let local = "captured"
+            fn read() { return local }
+            get = read
+            fn down() { return down() }
+            down()
+        }
+        "#;
+        let err = vm.interpret(compile_source(source)).unwrap_err();
+        assert_eq!(err.message, "stack overflow");
+
+        vm.interpret(compile_source("print get()\nlet done = true"))
+            .unwrap();
+        assert_eq!(vm.global("done"), Some(&Value::boolean(true)));
+        let printed = String::from_utf8(output.0.take()).unwrap();
+        assert_eq!(printed, " > print: \"captured\"\n");
+
+        // and can overflow again
+        let err = vm
+            .interpret(compile_source("fn f() { return f() }\nf()"))
+            .unwrap_err();
+        assert_eq!(
+            (err.message.as_str(), err.backtrace.len()),
+            ("stack overflow", 7)
+        );
+    }
+
+    #[test]
+    fn exceptions() {
+        let output = Buffer::default();
+        let source = r#"
+        fn risky(n) {
+            let local = n * 2
+            if (n > 1) throw "too big"
+            return local
+        }
+        fn call(n) {
+            let a = 1
+            return risky(n) + a
+        }
+        let log = ""
+        {
+            let before = "kept"
+            try {
+                log = log + "try "
+                call(1)
+                call(2)
+                log = log + "unreachable "
+            } catch (e) {
+                log = log + e + " " + before
+            } finally {
+                log = log + " finally"
+            }
+        }
+        // each exit runs the finally blocks, innermost first
+        fn exits(n) {
+            try {
+                try {
+                    if (n == 1) return "return"
+                    if (n == 2) throw "thrown"
+                } finally {
+                    print "inner"
+                }
+            } catch (e) {
+                return "caught " + e
+            } finally {
+                print "outer"
+            }
+            return "end"
+        }
+        let first = exits(1)
+        let second = exits(2)
+        let third = exits(3)
+        // the variables of the unwound frames are closed
+        let get
+        fn capture() {
+            let value = "captured"
+            fn read() { return value }
+            get = read
+            throw "done"
+        }
+        try { capture() } catch (e) {}
+        let captured = get()
+        "#;
+        let mut vm = Vm::load(compile_source(source))
+            .unwrap()
+            .with_output(output.clone());
+        vm.run().unwrap();
+        let global = |name| vm.global(name).map(|value| vm.format(value));
+        assert_eq!(
+            global("log").as_deref(),
+            Some("\"try too big kept finally\"")
+        );
+        assert_eq!(global("first").as_deref(), Some("\"return\""));
+        assert_eq!(global("second").as_deref(), Some("\"caught thrown\""));
+        assert_eq!(global("third").as_deref(), Some("\"end\""));
+        assert_eq!(global("captured").as_deref(), Some("\"captured\""));
+        let printed = String::from_utf8(output.0.take()).unwrap();
+        assert_eq!(printed.matches("inner\"\n > print: \"outer").count(), 3);
+        assert!(vm.stack.is_empty());
+    }
+
+    #[test]
+    fn uncaught_exception() {
+        let source = "fn fail() {\n  throw 42\n}\ntry {\n  fail()\n} finally {\n  print 1\n}";
+        let err = Vm::new(compile_source(source)).run().unwrap_err();
+        assert_eq!(err.message, "uncaught exception: 42");
+        // where it was thrown, not where the finally block threw it again
+        assert_eq!(err.pos, (2, 3));
+        assert_eq!(err.backtrace.len(), 1);
+        assert_eq!(err.backtrace[0].name, "fail");
+        assert_eq!(err.backtrace[0].call_site, (5, 3));
+        assert_eq!(
+            err.to_string(),
+            "RuntimeError: uncaught exception: 42, at: 2:3\nstack backtrace (most recent call last):\n    at fail (5:3)"
+        );
+        // the finally block handles exceptions of its own
+        let source = "try {\n  throw 1\n} finally {\n  try { throw 2 } catch (e) {}\n}";
+        let err = Vm::new(compile_source(source)).run().unwrap_err();
+        assert_eq!(
+            (err.message.as_str(), err.pos),
+            ("uncaught exception: 1", (2, 3))
+        );
+
+        // errors of the vm aren't exceptions
+        let source = "try {\n  missing\n} catch (e) {\n  print e\n}";
+        let err = Vm::new(compile_source(source)).run().unwrap_err();
+        assert_eq!(err.message, "missing is not defined");
+    }
+
+    // repeat(s, n): `s` repeated `n` times, twice by default
+    struct Repeat;
+
+    impl builtin::Builtin for Repeat {
+        fn name(&self) -> &str {
+            "repeat"
+        }
+
+        fn arity(&self) -> (usize, usize) {
+            (1, 2)
+        }
+
+        fn call<V: builtin::HostValue>(&self, args: &[V]) -> Result<V, String> {
+            let s = builtin::expect_string("repeat", &args[0])?;
+            let n = match args.get(1) {
+                Some(n) => builtin::expect_number("repeat", n)?,
+                None => 2.0,
+            };
+            Ok(V::from_str(&s.repeat(n as usize)))
+        }
+    }
+
+    #[test]
+    fn natives() {
+        let mut vm = Vm::new(compile_source(
+            "fn twice(s) { return repeat(s) }\nlet a = repeat(\"ab\", 3) + twice(\"c\")\nlet b = clock() > 0\nlet c = repeat",
+        ));
+        vm.define_native(Rc::new(Repeat));
+        vm.run().unwrap();
+        assert_eq!(vm.global("a"), Some(&"abababcc".into()));
+        assert_eq!(vm.global("b"), Some(&true.into()));
+        let c = vm.global("c").unwrap();
+        assert_eq!(vm.format(c), "<native fn repeat>");
+        // the arguments and the callee are gone
+        assert!(vm.stack.is_empty());
+
+        let err = vm
+            .interpret(compile_source("let x = 1\nrepeat()"))
+            .unwrap_err();
+        assert_eq!(
+            (err.message.as_str(), err.pos),
+            ("repeat expected 1 to 2 arguments but got 0", (2, 1))
+        );
+        let err = vm
+            .interpret(compile_source("fn f() {\n  return repeat(1)\n}\nf()"))
+            .unwrap_err();
+        assert_eq!(err.message, "repeat: expected string, found number");
+        assert_eq!(err.pos, (2, 10));
+        assert_eq!(err.backtrace.len(), 1);
+
+        let err = vm.interpret(compile_source("clock(1)")).unwrap_err();
+        assert_eq!(err.message, "clock expected 0 arguments but got 1");
+    }
+
+    #[test]
+    fn shared_natives() {
+        let repeat: Rc<dyn HostFunction> = Rc::new(Repeat);
+        let source = "let r = repeat(\"x\", 2)\nr";
+
+        let mut vm = Vm::new(compile_source(source));
+        vm.define_native(Rc::clone(&repeat));
+        vm.run().unwrap();
+        assert_eq!(vm.global("r"), Some(&"xx".into()));
+
+        let mut interpreter = crate::interpreter::Interpreter::new();
+        interpreter.define_native(Rc::clone(&repeat));
+        let lexer = crate::lexer::Lexer::new(source.as_bytes(), "test.tx");
+        let ast = crate::parser::parser::Parser::new(lexer).parse().unwrap();
+        crate::analizer::resolver::Resolver::new(&mut interpreter)
+            .resolve(&ast)
+            .unwrap();
+        let result = interpreter.eval(ast).unwrap();
+        assert_eq!(result, Some(crate::value::Value::String("xx".to_string())));
+
+        // the values of the vm hold counts of the function
+        assert_eq!(Rc::strong_count(&repeat), 3);
+        drop(vm);
+        drop(interpreter);
+        assert_eq!(Rc::strong_count(&repeat), 1);
+    }
 }
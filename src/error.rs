@@ -1,4 +1,5 @@
 use crate::{
+    bytecode::Pos as BytecodePos,
     position::{Pos, Span},
     token::{Token, TokenKind},
     value::Value,
@@ -76,7 +77,87 @@ pub enum RuntimeError {
     ReferenceError(String, Span),
     ReturnedValue(Value), // for return stmt result
     ArgsMismatched(Span),
-    Error(String), // TODO: add span
+    Exit(i32), // exit() builtin, stop the script
+    StackOverflow(Span),
+    StepLimitExceeded(u64),
+    Timeout(std::time::Duration),
+    Error(String, Span),
+    // value of a `throw` statement, until a `catch` takes it
+    Thrown(Box<Value>, Span),
+}
+
+const BACKTRACE_EDGE_FRAMES: usize = 10;
+
+fn write_backtrace(
+    f: &mut std::fmt::Formatter<'_>,
+    frames: &[impl std::fmt::Display],
+) -> std::fmt::Result {
+    write!(f, "\nstack backtrace (most recent call last):")?;
+    // deep recursion: only the outermost and innermost frames
+    let edge = BACKTRACE_EDGE_FRAMES;
+    for (i, frame) in frames.iter().enumerate() {
+        if frames.len() > edge * 2 && i >= edge && i < frames.len() - edge {
+            if i == edge {
+                write!(f, "\n    ... {} more frames", frames.len() - edge * 2)?;
+            }
+            continue;
+        }
+        write!(f, "\n    {}", frame)?;
+    }
+    Ok(())
+}
+
+/**
+ * runtime error leaving the interpreter, with the calls it was raised in
+ */
+#[derive(Debug)]
+pub struct TracedError {
+    pub error: RuntimeError,
+    // innermost last, empty for errors outside of any function.
+    // a boxed slice keeps results with this error small
+    pub backtrace: Box<[StackFrame]>,
+}
+
+impl TracedError {
+    pub fn new(error: RuntimeError, backtrace: Vec<StackFrame>) -> Self {
+        Self {
+            error,
+            backtrace: backtrace.into_boxed_slice(),
+        }
+    }
+}
+
+impl std::fmt::Display for TracedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.error)?;
+        if !self.backtrace.is_empty() {
+            write_backtrace(f, &self.backtrace)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StackFrame {
+    pub name: String,
+    pub call_site: Span, // where the function is called
+}
+
+impl StackFrame {
+    pub fn new(name: String, call_site: Span) -> Self {
+        Self { name, call_site }
+    }
+}
+
+impl std::fmt::Display for StackFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let loc = self.call_site.loc.start;
+        write!(
+            f,
+            "at {} ({}:{}:{})",
+            self.name, self.call_site.filename, loc.ln, loc.col
+        )
+    }
 }
 
 impl std::fmt::Display for RuntimeError {
@@ -97,12 +178,159 @@ impl std::fmt::Display for RuntimeError {
                 "SyntaxError: args number mismatched, at: {}:{}:{}",
                 span.filename, span.loc.end.ln, span.loc.end.col
             ),
-            RuntimeError::Error(msg) => write!(f, "RuntimeError: {}", msg),
+            RuntimeError::Error(msg, span) => write!(
+                f,
+                "RuntimeError: {}, at: {}:{}:{}",
+                msg, span.filename, span.loc.start.ln, span.loc.start.col
+            ),
+            RuntimeError::Thrown(value, span) => write!(
+                f,
+                "RuntimeError: uncaught exception: {}, at: {}:{}:{}",
+                value, span.filename, span.loc.start.ln, span.loc.start.col
+            ),
             RuntimeError::ReturnedValue(value) => write!(f, "{}", value),
+            RuntimeError::Exit(code) => write!(f, "exit with code {}", code),
+            RuntimeError::StackOverflow(span) => write!(
+                f,
+                "RangeError: maximum call stack size exceeded, at: {}:{}:{}",
+                span.filename, span.loc.start.ln, span.loc.start.col
+            ),
+            RuntimeError::StepLimitExceeded(steps) => {
+                write!(f, "RangeError: step limit of {} exceeded", steps)
+            }
+            RuntimeError::Timeout(timeout) => {
+                write!(f, "TimeoutError: script ran longer than {:?}", timeout)
+            }
+        }
+    }
+}
+
+/**
+ * runtime error of the bytecode vm
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct VmError {
+    pub message: String,
+    // position of the failing instruction
+    pub pos: BytecodePos,
+    // active calls, the script is not a call
+    pub backtrace: Vec<VmFrame>,
+}
+
+impl VmError {
+    pub fn new(message: String, pos: BytecodePos, backtrace: Vec<VmFrame>) -> Self {
+        Self {
+            message,
+            pos,
+            backtrace,
+        }
+    }
+}
+
+impl std::fmt::Display for VmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "RuntimeError: {}, at: {}:{}",
+            self.message, self.pos.0, self.pos.1
+        )?;
+        if !self.backtrace.is_empty() {
+            write_backtrace(f, &self.backtrace)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VmFrame {
+    pub name: String,
+    pub call_site: BytecodePos, // where the function is called
+}
+
+impl std::fmt::Display for VmFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "at {} ({}:{})",
+            self.name, self.call_site.0, self.call_site.1
+        )
+    }
+}
+
+// bytecode rejected before running it
+#[derive(Debug, PartialEq)]
+pub struct VerifyError {
+    pub message: String,
+    // the function holding the bad code, and the offset in its code
+    pub function: String,
+    pub offset: usize,
+}
+
+impl VerifyError {
+    pub fn new(message: String, function: String, offset: usize) -> Self {
+        Self {
+            message,
+            function,
+            offset,
+        }
+    }
+}
+
+impl std::fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "VerifyError: {}, in {} at {:04}",
+            self.message, self.function, self.offset
+        )
+    }
+}
+
+// textual bytecode that doesn't describe a chunk
+#[derive(Debug, PartialEq)]
+pub struct AssembleError {
+    pub message: String,
+    pub line: usize,
+}
+
+impl AssembleError {
+    pub fn new(message: String, line: usize) -> Self {
+        Self { message, line }
+    }
+}
+
+impl std::fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "AssembleError: {}, at line {}", self.message, self.line)
+    }
+}
+
+#[derive(Debug)]
+pub enum CompileError {
+    Unsupported(String, Span),
+    Error(String, Span),
+    Resolve(ResolveError),
+}
+
+impl std::fmt::Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompileError::Unsupported(what, span) => write!(
+                f,
+                "CompileError: {} is not supported by the bytecode compiler, at: {}:{}:{}",
+                what, span.filename, span.loc.start.ln, span.loc.start.col
+            ),
+            CompileError::Error(msg, span) => write!(
+                f,
+                "CompileError: {}, at: {}:{}:{}",
+                msg, span.filename, span.loc.start.ln, span.loc.start.col
+            ),
+            CompileError::Resolve(e) => write!(f, "{}", e),
         }
     }
 }
 
+#[derive(Debug)]
 pub enum ResolveError {
     Error(String),
     DeclaredError(String, Span),
@@ -126,3 +354,34 @@ impl std::fmt::Display for ResolveError {
         }
     }
 }
+
+#[derive(Debug, PartialEq)]
+pub enum BytecodeError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+    ChecksumMismatch,
+    // decoded fine, but describes an impossible chunk
+    Invalid(String),
+    // the chunk holds a value that can't be written to a file
+    Unserializable(String),
+}
+
+impl std::fmt::Display for BytecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BytecodeError::BadMagic => write!(f, "BytecodeError: not a tinyx bytecode file"),
+            BytecodeError::UnsupportedVersion(v) => {
+                write!(f, "BytecodeError: unsupported bytecode version {}", v)
+            }
+            BytecodeError::Truncated => write!(f, "BytecodeError: unexpected end of file"),
+            BytecodeError::ChecksumMismatch => {
+                write!(f, "BytecodeError: checksum mismatch, the file is corrupted")
+            }
+            BytecodeError::Invalid(msg) => write!(f, "BytecodeError: {}", msg),
+            BytecodeError::Unserializable(what) => {
+                write!(f, "BytecodeError: can't serialize {}", what)
+            }
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/**
+ * what the builtins are allowed to touch on the host.
+ * the default is a sandbox: no file system and no environment variables,
+ * embedders (and the cli) have to opt in.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct Capabilities {
+    pub fs: FsAccess,
+    pub env: bool,
+    pub args: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub enum FsAccess {
+    #[default]
+    Denied,
+    // only paths inside one of these directories
+    Dirs(Vec<PathBuf>),
+    Unrestricted,
+}
+
+impl Capabilities {
+    pub fn unrestricted(args: Vec<String>) -> Self {
+        Self {
+            fs: FsAccess::Unrestricted,
+            env: true,
+            args,
+        }
+    }
+
+    /**
+     * check `path` against the fs permission,
+     * a path that does not exist yet is checked by its parent directory.
+     */
+    pub fn check_path(&self, path: &str) -> Result<(), String> {
+        let dirs = match &self.fs {
+            FsAccess::Unrestricted => return Ok(()),
+            FsAccess::Denied => return Err("file system access is disabled".to_string()),
+            FsAccess::Dirs(dirs) => dirs,
+        };
+
+        let target = canonicalize(Path::new(path))
+            .ok_or_else(|| format!("permission denied: '{}'", path))?;
+        let allowed = dirs.iter().any(|dir| match dir.canonicalize() {
+            Ok(dir) => target.starts_with(dir),
+            Err(_) => false,
+        });
+        if allowed {
+            Ok(())
+        } else {
+            Err(format!("permission denied: '{}'", path))
+        }
+    }
+}
+
+fn canonicalize(path: &Path) -> Option<PathBuf> {
+    if let Ok(p) = path.canonicalize() {
+        return Some(p);
+    }
+    let name = path.file_name()?;
+    let parent = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    Some(parent.canonicalize().ok()?.join(name))
+}
+
+/**
+ * limits for running untrusted scripts, `None` means unlimited.
+ * every script call takes several rust frames: by default a run may use
+ * `DEFAULT_MAX_STACK_SIZE` of the host stack, a call beyond it fails with
+ * a stack overflow error instead of overflowing the host thread
+ */
+#[derive(Debug, Clone)]
+pub struct Limits {
+    pub max_call_depth: Option<usize>,
+    // bytes of host stack below the call to `eval`, checked on each call
+    pub max_stack_size: Option<usize>,
+    // evaluated statements and expressions
+    pub max_steps: Option<u64>,
+    pub timeout: Option<Duration>,
+}
+
+// as deep as the vm
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1024;
+
+// half of the 2MB stack of a spawned rust thread, lower it for smaller stacks.
+// a script call takes about 8KB of stack in debug builds, 2KB in release
+pub const DEFAULT_MAX_STACK_SIZE: usize = 1024 * 1024;
+
+// stack of the cli thread, half of it for the interpreter
+pub const HOST_STACK_SIZE: usize = 64 * 1024 * 1024;
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_call_depth: Some(DEFAULT_MAX_CALL_DEPTH),
+            max_stack_size: Some(DEFAULT_MAX_STACK_SIZE),
+            max_steps: None,
+            timeout: None,
+        }
+    }
+}
@@ -10,6 +10,7 @@ use super::{
 #[derive(Debug, Clone)]
 pub struct Function {
     pub name: Option<String>,
+    pub class: Option<String>, // class name of methods
     pub params: Vec<String>,
     pub body: Vec<Statement>,
     pub closure: Env,
@@ -24,12 +25,29 @@ impl Function {
     ) -> Self {
         Function {
             name,
+            class: None,
             params,
             body,
             closure,
         }
     }
 
+    pub fn in_class(self, class: &str) -> Self {
+        Function {
+            class: Some(class.to_string()),
+            ..self
+        }
+    }
+
+    // name shown in backtrace
+    pub fn frame_name(&self) -> String {
+        let name = self.name.as_deref().unwrap_or("anonymous");
+        match &self.class {
+            Some(class) => format!("{}.{}", class, name),
+            None => name.to_string(),
+        }
+    }
+
     pub fn bind(self, instance: &Instance) -> Self {
         let mut this_env = Env::extends(&self.closure);
         this_env.define("this".to_string(), Value::Instance(instance.clone()));
@@ -52,10 +70,10 @@ impl Callable for Function {
         span: Span,
     ) -> EvalResult<Value> {
         let Function {
-            name: _,
             params,
             body,
             closure,
+            ..
         } = self;
 
         if self.arity() != args.len() {
@@ -67,12 +85,14 @@ impl Callable for Function {
             env.define(params[i].clone(), arg)
         }
 
-        match interpreter.execute_block(body, env) {
-            Ok(_) => Ok(Value::Null),
-            Err(e) => match e {
-                RuntimeError::ReturnedValue(v) => Ok(v),
-                _ => Err(e),
-            },
-        }
+        interpreter.call_in_frame(self.frame_name(), &span, |interpreter| {
+            match interpreter.execute_block(body, env) {
+                Ok(_) => Ok(Value::Null),
+                Err(e) => match e {
+                    RuntimeError::ReturnedValue(v) => Ok(v),
+                    _ => Err(e),
+                },
+            }
+        })
     }
 }
@@ -1,12 +1,22 @@
-use std::{cell::RefCell, collections::HashMap, mem, rc::Rc};
-
-use crate::{ast::*, error::RuntimeError, token::Operator, value::Value};
+use std::{cell::RefCell, collections::HashMap, io::Write, mem, rc::Rc, time::Instant};
+
+use crate::{
+    ast::*,
+    builtin::HostFunction,
+    error::{RuntimeError, StackFrame, TracedError},
+    position::Span,
+    token::Operator,
+    value::Value,
+};
 
 use super::{
     callable::Callable,
     class::Class,
+    config::{Capabilities, Limits},
     env::{Env, EnvMethod},
     function::Function,
+    native::NativeFunction,
+    stdlib,
     visitor::{ExprVisitor, StmtVisitor},
     EvalResult,
 };
@@ -16,6 +26,17 @@ pub struct Interpreter {
     env: Env,
     locals: HashMap<String, usize>,
     result: Option<Value>,
+    capabilities: Capabilities,
+    // an error keeps the frames it unwinds until it is caught,
+    // they are its backtrace
+    frames: Vec<StackFrame>,
+    limits: Limits,
+    // host stack address at the start of the run
+    stack_base: usize,
+    steps: u64,
+    deadline: Option<Instant>,
+    // printed values, stdout if not set
+    output: Option<Box<dyn Write>>,
 }
 
 impl Default for Interpreter {
@@ -26,30 +47,100 @@ impl Default for Interpreter {
 
 impl Interpreter {
     pub fn new() -> Self {
-        let globals = Env::create();
+        let mut globals = Env::create();
+        for native in stdlib::natives() {
+            globals.define(native.name.clone(), Value::NativeFunction(native));
+        }
         Interpreter {
             env: Rc::clone(&globals),
             global: Rc::clone(&globals),
             result: None,
             locals: HashMap::new(),
+            capabilities: Capabilities::default(),
+            frames: Vec::new(),
+            limits: Limits::default(),
+            stack_base: 0,
+            steps: 0,
+            deadline: None,
+            output: None,
         }
     }
 
-    pub fn interpret(&mut self, program: Program) -> EvalResult<()> {
-        match self.eval_program(program) {
-            Ok(()) => match &self.result {
-                Some(v) => println!(" > {}", v),
-                None => (),
-            },
+    pub fn with_capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    /**
+     * write printed values into `sink` instead of stdout
+     */
+    pub fn with_output(mut self, sink: impl Write + 'static) -> Self {
+        self.output = Some(Box::new(sink));
+        self
+    }
+
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /**
+     * define a function implemented in rust as a global, under its name.
+     * the same `Rc` can be defined in a vm too
+     */
+    pub fn define_native(&mut self, native: Rc<dyn HostFunction>) {
+        let native = NativeFunction::host(native);
+        self.global
+            .define(native.name.clone(), Value::NativeFunction(native));
+    }
+
+    pub fn interpret(&mut self, program: Program) -> Result<(), TracedError> {
+        match self.run(program) {
+            Ok(()) => {
+                if let Some(v) = &self.result {
+                    println!(" > {}", v)
+                }
+            }
             // Err(e) => eprintln!(" > ERROR: {}", e)
-            Err(e) => match e {
+            Err(e) => match e.error {
                 RuntimeError::ReturnedValue(v) => println!(" > {}", v),
+                RuntimeError::Exit(_) => return Err(e),
                 _ => eprintln!(" > ERROR: {}", e),
             },
         }
         Ok(())
     }
 
+    /**
+     * run the program and return the value of the last expression statement
+     */
+    pub fn eval(&mut self, program: Program) -> Result<Option<Value>, TracedError> {
+        self.result = None;
+        match self.run(program) {
+            Ok(()) => Ok(self.result.take()),
+            Err(TracedError {
+                error: RuntimeError::ReturnedValue(v),
+                ..
+            }) => Ok(Some(v)),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn run(&mut self, program: Program) -> Result<(), TracedError> {
+        // limits count from the start of each run
+        self.steps = 0;
+        self.deadline = self.limits.timeout.map(|t| Instant::now() + t);
+        self.stack_base = stack_address();
+        self.frames.clear();
+
+        self.eval_program(program)
+            .map_err(|e| TracedError::new(e, mem::take(&mut self.frames)))
+    }
+
     fn eval_program(&mut self, program: Program) -> EvalResult<()> {
         for stmt in &program.body {
             self.execute(stmt)?;
@@ -57,14 +148,85 @@ impl Interpreter {
         Ok(())
     }
 
+    /**
+     * run `f` in a new call frame.
+     * an error leaves the frame on the stack, for its backtrace
+     */
+    pub(crate) fn call_in_frame<F>(
+        &mut self,
+        name: String,
+        call_site: &Span,
+        f: F,
+    ) -> EvalResult<Value>
+    where
+        F: FnOnce(&mut Self) -> EvalResult<Value>,
+    {
+        if let Some(max) = self.limits.max_call_depth {
+            if self.frames.len() >= max {
+                return Err(RuntimeError::StackOverflow(call_site.clone()));
+            }
+        }
+        if let Some(max) = self.limits.max_stack_size {
+            if self.stack_base.saturating_sub(stack_address()) >= max {
+                return Err(RuntimeError::StackOverflow(call_site.clone()));
+            }
+        }
+        self.frames.push(StackFrame::new(name, call_site.clone()));
+        let result = f(self);
+        if !matches!(
+            &result,
+            Err(e) if !matches!(e, RuntimeError::ReturnedValue(_) | RuntimeError::Exit(_))
+        ) {
+            self.frames.pop();
+        }
+        result
+    }
+
+    pub(crate) fn call_value(
+        &mut self,
+        callee: &Value,
+        arguments: Vec<Value>,
+        span: Span,
+    ) -> EvalResult<Value> {
+        match callee {
+            Value::Function(function) => function.call(self, arguments, span),
+            Value::NativeFunction(native) => native.call(self, arguments, span),
+            Value::Class(class) => class.call(self, arguments, span),
+            _ => Err(RuntimeError::SyntaxError(
+                format!("{} is not callable", callee.type_name()),
+                span,
+            )),
+        }
+    }
+
     fn evaluate(&mut self, expr: &Expr) -> EvalResult<Value> {
+        self.step()?;
         self.walk_expr(expr)
     }
 
     fn execute(&mut self, stmt: &Statement) -> EvalResult<()> {
+        self.step()?;
         self.walk_stmt(stmt)
     }
 
+    fn step(&mut self) -> EvalResult<()> {
+        self.steps += 1;
+        if let Some(max) = self.limits.max_steps {
+            if self.steps > max {
+                return Err(RuntimeError::StepLimitExceeded(max));
+            }
+        }
+        // reading the clock on every node is too slow
+        if self.steps.is_multiple_of(256) {
+            if let (Some(deadline), Some(timeout)) = (self.deadline, self.limits.timeout) {
+                if Instant::now() >= deadline {
+                    return Err(RuntimeError::Timeout(timeout));
+                }
+            }
+        }
+        Ok(())
+    }
+
     pub(super) fn execute_block(&mut self, block: &[Statement], env: Env) -> EvalResult<()> {
         let prev_env = Rc::clone(&self.env);
         self.env = env;
@@ -105,6 +267,13 @@ impl Interpreter {
     }
 }
 
+// address of a local of this call, the host stack grows down on every supported target
+#[inline(never)]
+fn stack_address() -> usize {
+    let marker = 0u8;
+    std::hint::black_box(&marker) as *const u8 as usize
+}
+
 impl StmtVisitor for Interpreter {
     type Item = EvalResult<()>;
 
@@ -179,7 +348,8 @@ impl StmtVisitor for Interpreter {
                     m.params.iter().map(|i| i.name.to_string()).collect(),
                     m.body.clone(),
                     Rc::clone(&self.env),
-                ),
+                )
+                .in_class(&id.name),
             );
         });
 
@@ -224,7 +394,14 @@ impl StmtVisitor for Interpreter {
 
     fn visit_print_stmt(&mut self, expr: &Expr) -> Self::Item {
         let value = self.evaluate(expr)?;
-        println!(" > print: {}", value);
+        let line = format!(" > print: {}", value);
+        match &mut self.output {
+            // a failing sink doesn't stop the program
+            Some(sink) => {
+                let _ = writeln!(sink, "{}", line);
+            }
+            None => println!("{}", line),
+        }
         self.result = None;
         Ok(())
     }
@@ -238,6 +415,48 @@ impl StmtVisitor for Interpreter {
         Ok(())
     }
 
+    fn visit_throw_stmt(&mut self, stmt: &ThrowStatement) -> Self::Item {
+        let value = self.evaluate(&stmt.argument)?;
+        Err(RuntimeError::Thrown(Box::new(value), stmt.span.clone()))
+    }
+
+    fn visit_try_stmt(&mut self, stmt: &TryStatement) -> Self::Item {
+        let TryStatement {
+            block,
+            handler,
+            finalizer,
+        } = stmt;
+        let depth = self.frames.len();
+        let mut result = self.visit_block(block);
+        // only thrown values are caught, the other errors stop the script
+        if let (Err(RuntimeError::Thrown(value, _)), Some(CatchClause { param, body })) =
+            (&result, handler)
+        {
+            self.frames.truncate(depth);
+            let mut scope = Env::extends(&self.env);
+            scope.define(param.name.clone(), *value.clone());
+            result = self.execute_block(body, Env::extends(&scope));
+        }
+        match (finalizer, &result) {
+            // the finally block runs when the block completes, returns or throws,
+            // a `return` or a `throw` inside it replaces the previous outcome
+            (
+                Some(block),
+                Ok(()) | Err(RuntimeError::ReturnedValue(_) | RuntimeError::Thrown(..)),
+            ) => {
+                // the frames of a pending exception are put back after the block
+                let unwinding = self.frames.split_off(depth);
+                let finally = self.visit_block(block);
+                if finally.is_ok() {
+                    self.frames.extend(unwinding);
+                }
+                finally.and(result)
+            }
+            // errors of the interpreter and limits stop the script, as in the vm
+            _ => result,
+        }
+    }
+
     fn visit_empty(&mut self) -> Self::Item {
         Ok(())
     }
@@ -253,14 +472,27 @@ impl ExprVisitor for Interpreter {
         let right = &self.evaluate(right)?;
 
         let op_err = RuntimeError::SyntaxError(
-            format!("invalid operator at [{} {} {}]", left, op.value, right),
+            format!(
+                "invalid operands for '{}': {} and {}",
+                op.value,
+                left.type_name(),
+                right.type_name()
+            ),
             op.span(),
         );
 
         match (left, right) {
+            // values of different types are never equal
+            (l, r)
+                if matches!(op.value, Operator::Equal | Operator::NotEqual)
+                    && l.is_primitive()
+                    && r.is_primitive() =>
+            {
+                Ok(Value::Boolean((l == r) == (op.value == Operator::Equal)))
+            }
             (Value::String(l), Value::String(r)) => Ok(match op.value {
                 Operator::Add => Value::String(format!("{}{}", l, r)),
-                _ => unimplemented!(),
+                _ => return Err(op_err),
             }),
 
             (Value::Number(l), Value::Number(r)) => Ok(match op.value {
@@ -286,7 +518,7 @@ impl ExprVisitor for Interpreter {
         let UnaryExpr { op, argument } = unary;
         let value = self.evaluate(argument)?;
         let op_err = RuntimeError::SyntaxError(
-            format!("invalid operator at [{} {}]", op.value, value),
+            format!("invalid operand for '{}': {}", op.value, value.type_name()),
             op.span(),
         );
 
@@ -313,7 +545,7 @@ impl ExprVisitor for Interpreter {
         match op.value {
             Operator::Assign => {
                 let Identifier { name, span } = left;
-                let value = self.evaluate(&*right)?;
+                let value = self.evaluate(right)?;
                 if let Some(distance) = self.locals.get(&left.to_string()) {
                     match self.env.assign_at(*distance, name, value.clone()) {
                         true => Ok(value),
@@ -348,41 +580,22 @@ impl ExprVisitor for Interpreter {
             list.push(self.evaluate(arg)?);
         }
 
-        match value {
-            Value::Function(function) => function.call(self, list, span.clone()),
-            Value::Class(class) => class.call(self, list, span.clone()),
-            _ => Err(RuntimeError::SyntaxError(
-                "invalid callee".to_string(),
-                span.clone(),
-            )),
-        }
+        self.call_value(&value, list, span.clone())
     }
 
     fn visit_logical(&mut self, expr: &LogicalExpr) -> Self::Item {
         let LogicalExpr { left, op, right } = expr;
         let left = self.evaluate(left)?;
-        let right = self.evaluate(right)?;
-        let op_err = RuntimeError::SyntaxError(
-            format!("invalid operator at [{} {} {}]", left, op.value, right),
-            op.span(),
-        );
 
+        // short-circuit: the right side is only evaluated when needed
         match op.value {
-            Operator::Or => {
-                if left.is_truthy() {
-                    Ok(left)
-                } else {
-                    Ok(right)
-                }
-            }
-            Operator::And => {
-                if !left.is_truthy() {
-                    Ok(left)
-                } else {
-                    Ok(right)
-                }
-            }
-            _ => Err(op_err),
+            Operator::Or if left.is_truthy() => Ok(left),
+            Operator::And if !left.is_truthy() => Ok(left),
+            Operator::Or | Operator::And => self.evaluate(right),
+            _ => Err(RuntimeError::SyntaxError(
+                format!("invalid operator at [{} {}]", left, op.value),
+                op.span(),
+            )),
         }
     }
 
@@ -390,17 +603,20 @@ impl ExprVisitor for Interpreter {
         let GetExpr { object, property } = expr;
         let left = self.evaluate(object)?;
 
-        match left {
-            Value::Instance(instance) => {
-                return match instance.get(&property.name) {
-                    Some(v) => Ok(v),
-                    None => Err(RuntimeError::SyntaxError(
-                        format!("undefined property [ {} ]", property.name.clone()),
-                        property.span.clone(),
-                    )),
-                };
-            }
-            _ => unimplemented!(),
+        let value = match &left {
+            Value::Instance(instance) => instance.get(&property.name),
+            Value::Array(_) => stdlib::array::method(&property.name)
+                .map(|m| Value::NativeFunction(m.bind(left.clone()))),
+            Value::Object(obj) => obj.borrow().get(&property.name).cloned(),
+            _ => return Err(not_an_instance(&left, property)),
+        };
+
+        match value {
+            Some(v) => Ok(v),
+            None => Err(RuntimeError::SyntaxError(
+                format!("undefined property {}", property.name),
+                property.span.clone(),
+            )),
         }
     }
 
@@ -414,7 +630,11 @@ impl ExprVisitor for Interpreter {
         let right = self.evaluate(value)?;
         match left {
             Value::Instance(mut instance) => instance.set(&property.name, right.clone()),
-            _ => unimplemented!(),
+            Value::Object(obj) => {
+                obj.borrow_mut()
+                    .insert(property.name.clone(), right.clone());
+            }
+            _ => return Err(not_an_instance(&left, property)),
         }
         Ok(right)
     }
@@ -444,7 +664,7 @@ impl ExprVisitor for Interpreter {
                 match class.get_method(&expr.method.name) {
                     Some(method) => Ok(Value::Function(method.bind(&instance))),
                     None => Err(RuntimeError::SyntaxError(
-                        format!("Undefined property {}", expr.method.name),
+                        format!("undefined property {}", expr.method.name),
                         expr.span.clone(),
                     )),
                 }
@@ -465,4 +685,21 @@ impl ExprVisitor for Interpreter {
     fn visit_null(&mut self) -> Self::Item {
         Ok(Value::Null)
     }
+    fn visit_array(&mut self, lit: &ArrayLiteral) -> Self::Item {
+        let mut list = Vec::new();
+        for ele in lit.elements.iter() {
+            list.push(self.evaluate(ele)?);
+        }
+        Ok(Value::array(list))
+    }
+}
+
+fn not_an_instance(value: &Value, property: &Identifier) -> RuntimeError {
+    RuntimeError::SyntaxError(
+        format!(
+            "only instances have properties, found {}",
+            value.type_name()
+        ),
+        property.span.clone(),
+    )
 }
@@ -1,9 +1,13 @@
 mod callable;
 pub mod class;
+pub mod config;
 pub mod env;
 pub mod function;
 pub mod instance;
+#[allow(clippy::module_inception)]
 mod interpreter;
+pub mod native;
+pub mod stdlib;
 pub mod visitor;
 
 pub use interpreter::Interpreter;
@@ -0,0 +1,97 @@
+use std::rc::Rc;
+
+use crate::{builtin::HostFunction, error::RuntimeError, position::Span, value::Value};
+
+use super::{callable::Callable, EvalResult, Interpreter};
+
+pub type NativeFn = fn(&mut Interpreter, &[Value], &Span) -> EvalResult<Value>;
+
+/**
+ * builtin function implemented in rust.
+ * method of builtin types (eg: array.push) keep the receiver in `this`,
+ * it is passed as the first argument when called.
+ */
+#[derive(Clone)]
+pub struct NativeFunction {
+    pub name: String,
+    pub min_arity: usize,
+    pub max_arity: usize,
+    pub this: Option<Box<Value>>,
+    func: Func,
+}
+
+#[derive(Clone)]
+enum Func {
+    Interpreter(NativeFn),
+    // shared with the vm, doesn't see the interpreter
+    Host(Rc<dyn HostFunction>),
+}
+
+impl NativeFunction {
+    pub fn new(name: &str, min_arity: usize, max_arity: usize, func: NativeFn) -> Self {
+        Self {
+            name: name.to_string(),
+            min_arity,
+            max_arity,
+            this: None,
+            func: Func::Interpreter(func),
+        }
+    }
+
+    pub fn host(native: Rc<dyn HostFunction>) -> Self {
+        let (min_arity, max_arity) = native.arity();
+        Self {
+            name: native.name().to_string(),
+            min_arity,
+            max_arity,
+            this: None,
+            func: Func::Host(native),
+        }
+    }
+
+    pub fn bind(self, this: Value) -> Self {
+        NativeFunction {
+            this: Some(Box::new(this)),
+            ..self
+        }
+    }
+}
+
+impl std::fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+impl Callable for NativeFunction {
+    fn arity(&self) -> usize {
+        self.min_arity
+    }
+
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+        span: Span,
+    ) -> EvalResult<Value> {
+        if arguments.len() < self.min_arity || arguments.len() > self.max_arity {
+            return Err(RuntimeError::ArgsMismatched(span));
+        }
+
+        let args = match &self.this {
+            Some(this) => {
+                let mut args = Vec::with_capacity(arguments.len() + 1);
+                args.push((**this).clone());
+                args.extend(arguments);
+                args
+            }
+            None => arguments,
+        };
+        interpreter.call_in_frame(self.name.clone(), &span, |interpreter| match &self.func {
+            Func::Interpreter(func) => func(interpreter, &args, &span),
+            Func::Host(native) => native
+                .call_interpreter(&args)
+                .map_err(|msg| RuntimeError::Error(msg, span.clone())),
+        })
+    }
+}
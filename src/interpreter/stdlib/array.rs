@@ -0,0 +1,281 @@
+use std::cmp::Ordering;
+
+use crate::{
+    error::RuntimeError,
+    interpreter::{native::NativeFunction, EvalResult, Interpreter},
+    position::Span,
+    value::Value,
+};
+
+use super::{expect_array, expect_index, expect_number, expect_string};
+
+/**
+ * methods of array, the receiver is always `args[0]`
+ */
+pub fn method(name: &str) -> Option<NativeFunction> {
+    let method = match name {
+        "push" => NativeFunction::new("push", 1, 1, push),
+        "pop" => NativeFunction::new("pop", 0, 0, pop),
+        "insert" => NativeFunction::new("insert", 2, 2, insert),
+        "remove" => NativeFunction::new("remove", 1, 1, remove),
+        "len" => NativeFunction::new("len", 0, 0, len),
+        "slice" => NativeFunction::new("slice", 1, 2, slice),
+        "concat" => NativeFunction::new("concat", 1, 1, concat),
+        "reverse" => NativeFunction::new("reverse", 0, 0, reverse),
+        "index_of" => NativeFunction::new("index_of", 1, 1, index_of),
+        "contains" => NativeFunction::new("contains", 1, 1, contains),
+        "join" => NativeFunction::new("join", 0, 1, join),
+        "sort" => NativeFunction::new("sort", 0, 1, sort),
+        "map" => NativeFunction::new("map", 1, 1, map),
+        "filter" => NativeFunction::new("filter", 1, 1, filter),
+        "reduce" => NativeFunction::new("reduce", 1, 2, reduce),
+        "find" => NativeFunction::new("find", 1, 1, find),
+        "any" => NativeFunction::new("any", 1, 1, any),
+        "all" => NativeFunction::new("all", 1, 1, all),
+        _ => return None,
+    };
+    Some(method)
+}
+
+fn push(_: &mut Interpreter, args: &[Value], span: &Span) -> EvalResult<Value> {
+    let array = expect_array("push", &args[0], span)?;
+    array.borrow_mut().push(args[1].clone());
+    let len = array.borrow().len();
+    Ok(Value::Number(len as f64))
+}
+
+fn pop(_: &mut Interpreter, args: &[Value], span: &Span) -> EvalResult<Value> {
+    let array = expect_array("pop", &args[0], span)?;
+    let value = array.borrow_mut().pop();
+    Ok(value.unwrap_or(Value::Null))
+}
+
+fn insert(_: &mut Interpreter, args: &[Value], span: &Span) -> EvalResult<Value> {
+    let array = expect_array("insert", &args[0], span)?;
+    let idx = expect_index("insert", &args[1], span)?;
+    let len = array.borrow().len();
+    if idx > len {
+        return Err(out_of_range("insert", idx, len, span));
+    }
+    array.borrow_mut().insert(idx, args[2].clone());
+    Ok(Value::Null)
+}
+
+fn remove(_: &mut Interpreter, args: &[Value], span: &Span) -> EvalResult<Value> {
+    let array = expect_array("remove", &args[0], span)?;
+    let idx = expect_index("remove", &args[1], span)?;
+    let len = array.borrow().len();
+    if idx >= len {
+        return Err(out_of_range("remove", idx, len, span));
+    }
+    let value = array.borrow_mut().remove(idx);
+    Ok(value)
+}
+
+fn len(_: &mut Interpreter, args: &[Value], span: &Span) -> EvalResult<Value> {
+    let array = expect_array("len", &args[0], span)?;
+    let len = array.borrow().len();
+    Ok(Value::Number(len as f64))
+}
+
+fn slice(_: &mut Interpreter, args: &[Value], span: &Span) -> EvalResult<Value> {
+    let array = expect_array("slice", &args[0], span)?;
+    let array = array.borrow();
+    let start = expect_index("slice", &args[1], span)?.min(array.len());
+    let end = match args.get(2) {
+        Some(v) => expect_index("slice", v, span)?.min(array.len()),
+        None => array.len(),
+    };
+    if start >= end {
+        return Ok(Value::array(vec![]));
+    }
+    Ok(Value::array(array[start..end].to_vec()))
+}
+
+fn concat(_: &mut Interpreter, args: &[Value], span: &Span) -> EvalResult<Value> {
+    let array = expect_array("concat", &args[0], span)?;
+    let other = expect_array("concat", &args[1], span)?;
+    let mut list = array.borrow().clone();
+    list.extend(other.borrow().iter().cloned());
+    Ok(Value::array(list))
+}
+
+fn reverse(_: &mut Interpreter, args: &[Value], span: &Span) -> EvalResult<Value> {
+    let array = expect_array("reverse", &args[0], span)?;
+    array.borrow_mut().reverse();
+    Ok(args[0].clone())
+}
+
+fn index_of(_: &mut Interpreter, args: &[Value], span: &Span) -> EvalResult<Value> {
+    let array = expect_array("index_of", &args[0], span)?;
+    let idx = array.borrow().iter().position(|v| v == &args[1]);
+    Ok(Value::Number(match idx {
+        Some(i) => i as f64,
+        None => -1.0,
+    }))
+}
+
+fn contains(_: &mut Interpreter, args: &[Value], span: &Span) -> EvalResult<Value> {
+    let array = expect_array("contains", &args[0], span)?;
+    let found = array.borrow().contains(&args[1]);
+    Ok(Value::Boolean(found))
+}
+
+fn join(_: &mut Interpreter, args: &[Value], span: &Span) -> EvalResult<Value> {
+    let array = expect_array("join", &args[0], span)?;
+    let sep = match args.get(1) {
+        Some(v) => expect_string("join", v, span)?,
+        None => ",",
+    };
+    let s = array
+        .borrow()
+        .iter()
+        .map(|v| match v {
+            Value::String(s) => s.clone(),
+            _ => v.to_string(),
+        })
+        .collect::<Vec<String>>()
+        .join(sep);
+    Ok(Value::String(s))
+}
+
+/**
+ * sort in place, without comparator only numbers or strings can be compared.
+ * comparator(a, b) returns a number, `< 0` means a comes first.
+ */
+fn sort(interpreter: &mut Interpreter, args: &[Value], span: &Span) -> EvalResult<Value> {
+    let array = expect_array("sort", &args[0], span)?;
+    // sort a copy, comparator may touch the array
+    let mut list = array.borrow().clone();
+    let mut error = None;
+
+    list.sort_by(|a, b| {
+        if error.is_some() {
+            return Ordering::Equal;
+        }
+        let ord = match args.get(1) {
+            Some(cmp) => interpreter
+                .call_value(cmp, vec![a.clone(), b.clone()], span.clone())
+                .and_then(|v| expect_number("sort", &v, span))
+                .map(|n| n.partial_cmp(&0.0).unwrap_or(Ordering::Equal)),
+            None => compare(a, b, span),
+        };
+        ord.unwrap_or_else(|e| {
+            error = Some(e);
+            Ordering::Equal
+        })
+    });
+
+    if let Some(e) = error {
+        return Err(e);
+    }
+    *array.borrow_mut() = list;
+    Ok(args[0].clone())
+}
+
+fn compare(a: &Value, b: &Value, span: &Span) -> EvalResult<Ordering> {
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => Ok(x.partial_cmp(y).unwrap_or(Ordering::Equal)),
+        (Value::String(x), Value::String(y)) => Ok(x.cmp(y)),
+        _ => Err(RuntimeError::Error(
+            format!(
+                "sort: cannot compare {} with {}",
+                a.type_name(),
+                b.type_name()
+            ),
+            span.clone(),
+        )),
+    }
+}
+
+fn map(interpreter: &mut Interpreter, args: &[Value], span: &Span) -> EvalResult<Value> {
+    let list = expect_array("map", &args[0], span)?.borrow().clone();
+    let mut result = Vec::with_capacity(list.len());
+    for v in list {
+        result.push(interpreter.call_value(&args[1], vec![v], span.clone())?);
+    }
+    Ok(Value::array(result))
+}
+
+fn filter(interpreter: &mut Interpreter, args: &[Value], span: &Span) -> EvalResult<Value> {
+    let list = expect_array("filter", &args[0], span)?.borrow().clone();
+    let mut result = Vec::new();
+    for v in list {
+        if interpreter
+            .call_value(&args[1], vec![v.clone()], span.clone())?
+            .is_truthy()
+        {
+            result.push(v);
+        }
+    }
+    Ok(Value::array(result))
+}
+
+fn reduce(interpreter: &mut Interpreter, args: &[Value], span: &Span) -> EvalResult<Value> {
+    let mut list = expect_array("reduce", &args[0], span)?
+        .borrow()
+        .clone()
+        .into_iter();
+    let mut acc = match args.get(2) {
+        Some(init) => init.clone(),
+        None => match list.next() {
+            Some(first) => first,
+            None => {
+                return Err(RuntimeError::Error(
+                    "reduce: empty array with no initial value".to_string(),
+                    span.clone(),
+                ))
+            }
+        },
+    };
+    for v in list {
+        acc = interpreter.call_value(&args[1], vec![acc, v], span.clone())?;
+    }
+    Ok(acc)
+}
+
+fn find(interpreter: &mut Interpreter, args: &[Value], span: &Span) -> EvalResult<Value> {
+    let list = expect_array("find", &args[0], span)?.borrow().clone();
+    for v in list {
+        if interpreter
+            .call_value(&args[1], vec![v.clone()], span.clone())?
+            .is_truthy()
+        {
+            return Ok(v);
+        }
+    }
+    Ok(Value::Null)
+}
+
+fn any(interpreter: &mut Interpreter, args: &[Value], span: &Span) -> EvalResult<Value> {
+    let list = expect_array("any", &args[0], span)?.borrow().clone();
+    for v in list {
+        if interpreter
+            .call_value(&args[1], vec![v], span.clone())?
+            .is_truthy()
+        {
+            return Ok(Value::Boolean(true));
+        }
+    }
+    Ok(Value::Boolean(false))
+}
+
+fn all(interpreter: &mut Interpreter, args: &[Value], span: &Span) -> EvalResult<Value> {
+    let list = expect_array("all", &args[0], span)?.borrow().clone();
+    for v in list {
+        if !interpreter
+            .call_value(&args[1], vec![v], span.clone())?
+            .is_truthy()
+        {
+            return Ok(Value::Boolean(false));
+        }
+    }
+    Ok(Value::Boolean(true))
+}
+
+fn out_of_range(name: &str, idx: usize, len: usize, span: &Span) -> RuntimeError {
+    RuntimeError::Error(
+        format!("{}: index {} out of range for length {}", name, idx, len),
+        span.clone(),
+    )
+}
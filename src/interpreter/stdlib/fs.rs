@@ -0,0 +1,92 @@
+use std::{fs, io::Write};
+
+use crate::{
+    error::RuntimeError,
+    interpreter::{EvalResult, Interpreter},
+    position::Span,
+    value::Value,
+};
+
+use super::expect_string;
+
+fn checked_path<'a>(
+    name: &str,
+    interpreter: &Interpreter,
+    value: &'a Value,
+    span: &Span,
+) -> EvalResult<&'a str> {
+    let path = expect_string(name, value, span)?;
+    interpreter
+        .capabilities()
+        .check_path(path)
+        .map_err(|msg| RuntimeError::Error(format!("{}: {}", name, msg), span.clone()))?;
+    Ok(path)
+}
+
+fn io_error(name: &str, e: std::io::Error, span: &Span) -> RuntimeError {
+    RuntimeError::Error(format!("{}: {}", name, e), span.clone())
+}
+
+pub fn read_file(interpreter: &mut Interpreter, args: &[Value], span: &Span) -> EvalResult<Value> {
+    let path = checked_path("read_file", interpreter, &args[0], span)?;
+    match fs::read_to_string(path) {
+        Ok(s) => Ok(Value::String(s)),
+        Err(e) => Err(io_error("read_file", e, span)),
+    }
+}
+
+pub fn write_file(interpreter: &mut Interpreter, args: &[Value], span: &Span) -> EvalResult<Value> {
+    let path = checked_path("write_file", interpreter, &args[0], span)?;
+    let content = expect_string("write_file", &args[1], span)?;
+    match fs::write(path, content) {
+        Ok(_) => Ok(Value::Null),
+        Err(e) => Err(io_error("write_file", e, span)),
+    }
+}
+
+pub fn append_file(
+    interpreter: &mut Interpreter,
+    args: &[Value],
+    span: &Span,
+) -> EvalResult<Value> {
+    let path = checked_path("append_file", interpreter, &args[0], span)?;
+    let content = expect_string("append_file", &args[1], span)?;
+    let result = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(content.as_bytes()));
+    match result {
+        Ok(_) => Ok(Value::Null),
+        Err(e) => Err(io_error("append_file", e, span)),
+    }
+}
+
+pub fn exists(interpreter: &mut Interpreter, args: &[Value], span: &Span) -> EvalResult<Value> {
+    let path = checked_path("exists", interpreter, &args[0], span)?;
+    Ok(Value::Boolean(std::path::Path::new(path).exists()))
+}
+
+pub fn list_dir(interpreter: &mut Interpreter, args: &[Value], span: &Span) -> EvalResult<Value> {
+    let path = checked_path("list_dir", interpreter, &args[0], span)?;
+    let entries = fs::read_dir(path).map_err(|e| io_error("list_dir", e, span))?;
+    let mut names = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(|e| io_error("list_dir", e, span))?;
+        names.push(entry.file_name().to_string_lossy().to_string());
+    }
+    names.sort();
+    Ok(Value::array(names.into_iter().map(Value::String).collect()))
+}
+
+pub fn remove_file(
+    interpreter: &mut Interpreter,
+    args: &[Value],
+    span: &Span,
+) -> EvalResult<Value> {
+    let path = checked_path("remove_file", interpreter, &args[0], span)?;
+    match fs::remove_file(path) {
+        Ok(_) => Ok(Value::Null),
+        Err(e) => Err(io_error("remove_file", e, span)),
+    }
+}
@@ -0,0 +1,593 @@
+use std::{
+    collections::{HashMap, HashSet},
+    iter::Peekable,
+    str::Bytes,
+};
+
+use crate::{
+    error::RuntimeError,
+    interpreter::{EvalResult, Interpreter},
+    position::{Pos, Span},
+    value::Value,
+};
+
+use super::{expect_index, expect_string};
+
+pub fn json_parse(_: &mut Interpreter, args: &[Value], span: &Span) -> EvalResult<Value> {
+    let src = expect_string("json_parse", &args[0], span)?;
+    parse(src).map_err(|e| {
+        RuntimeError::Error(
+            format!(
+                "json_parse: {} at line {}, column {}",
+                e.msg, e.pos.ln, e.pos.col
+            ),
+            span.clone(),
+        )
+    })
+}
+
+pub fn json_stringify(_: &mut Interpreter, args: &[Value], span: &Span) -> EvalResult<Value> {
+    let indent = match args.get(1) {
+        Some(Value::Null) | None => None,
+        Some(v) => Some(expect_index("json_stringify", v, span)?),
+    };
+    stringify(&args[0], indent)
+        .map(Value::String)
+        .map_err(|msg| RuntimeError::Error(format!("json_stringify: {}", msg), span.clone()))
+}
+
+/**
+ * arrays and objects nested deeper are rejected by `parse` and `stringify`,
+ * both recurse once per level
+ */
+pub const MAX_DEPTH: usize = 512;
+
+#[derive(Debug)]
+pub struct JsonError {
+    pub msg: String,
+    pub pos: Pos,
+}
+
+/**
+ * JSON text to Value
+ *      object  -> Value::Object
+ *      array   -> Value::Array
+ *      number  -> Value::Number
+ *      string  -> Value::String
+ *      boolean -> Value::Boolean
+ *      null    -> Value::Null
+ */
+pub fn parse(src: &str) -> Result<Value, JsonError> {
+    let mut parser = JsonParser {
+        chars: src.chars().collect(),
+        cursor: 0,
+        ln: 1,
+        col: 1,
+        depth: 0,
+    };
+    let value = parser.parse_value()?;
+    parser.skip_whitespace();
+    if parser.peek().is_some() {
+        return Err(parser.error("unexpected trailing characters"));
+    }
+    Ok(value)
+}
+
+struct JsonParser {
+    chars: Vec<char>,
+    cursor: usize,
+    ln: usize,
+    col: usize,
+    // arrays and objects being parsed
+    depth: usize,
+}
+
+impl JsonParser {
+    fn error(&self, msg: &str) -> JsonError {
+        JsonError {
+            msg: msg.to_string(),
+            pos: Pos::new(self.ln, self.col),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.cursor).copied()
+    }
+
+    fn advance(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.cursor += 1;
+        if c == '\n' {
+            self.ln += 1;
+            self.col = 1;
+        } else {
+            self.col += 1;
+        }
+        Some(c)
+    }
+
+    fn eat(&mut self, expect: char) -> Result<(), JsonError> {
+        match self.peek() {
+            Some(c) if c == expect => {
+                self.advance();
+                Ok(())
+            }
+            Some(c) => Err(self.error(&format!("expected '{}', found '{}'", expect, c))),
+            None => Err(self.error(&format!("expected '{}', found end of input", expect))),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(' ' | '\t' | '\n' | '\r') = self.peek() {
+            self.advance();
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Value, JsonError> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('{') => self.parse_nested(Self::parse_object),
+            Some('[') => self.parse_nested(Self::parse_array),
+            Some('"') => Ok(Value::String(self.parse_string()?)),
+            Some('-' | '0'..='9') => self.parse_number(),
+            Some('t') => self.parse_keyword("true", Value::Boolean(true)),
+            Some('f') => self.parse_keyword("false", Value::Boolean(false)),
+            Some('n') => self.parse_keyword("null", Value::Null),
+            Some(c) => Err(self.error(&format!("unexpected character '{}'", c))),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn parse_nested(
+        &mut self,
+        parse: fn(&mut Self) -> Result<Value, JsonError>,
+    ) -> Result<Value, JsonError> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error(&format!("nesting deeper than {} levels", MAX_DEPTH)));
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn parse_keyword(&mut self, word: &str, value: Value) -> Result<Value, JsonError> {
+        for c in word.chars() {
+            if self.peek() != Some(c) {
+                return Err(self.error(&format!("invalid literal, expected '{}'", word)));
+            }
+            self.advance();
+        }
+        Ok(value)
+    }
+
+    fn parse_object(&mut self) -> Result<Value, JsonError> {
+        self.eat('{')?;
+        let mut map = HashMap::new();
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.advance();
+            return Ok(Value::object(map));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some('"') {
+                return Err(self.error("expected string key"));
+            }
+            let key = self.parse_string()?;
+            self.skip_whitespace();
+            self.eat(':')?;
+            let value = self.parse_value()?;
+            map.insert(key, value);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => {
+                    self.advance();
+                }
+                Some('}') => {
+                    self.advance();
+                    return Ok(Value::object(map));
+                }
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn parse_array(&mut self) -> Result<Value, JsonError> {
+        self.eat('[')?;
+        let mut list = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.advance();
+            return Ok(Value::array(list));
+        }
+        loop {
+            list.push(self.parse_value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => {
+                    self.advance();
+                }
+                Some(']') => {
+                    self.advance();
+                    return Ok(Value::array(list));
+                }
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn parse_string(&mut self) -> Result<String, JsonError> {
+        self.eat('"')?;
+        let mut buf = String::new();
+        loop {
+            let c = match self.peek() {
+                Some(c) => c,
+                None => return Err(self.error("unterminated string")),
+            };
+            match c {
+                '"' => {
+                    self.advance();
+                    return Ok(buf);
+                }
+                '\\' => {
+                    self.advance();
+                    let escaped = match self.peek() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => {
+                            self.advance();
+                            buf.push(self.parse_unicode_escape()?);
+                            continue;
+                        }
+                        _ => return Err(self.error("invalid escape sequence")),
+                    };
+                    self.advance();
+                    buf.push(escaped);
+                }
+                c if (c as u32) < 0x20 => {
+                    return Err(self.error("control character in string"));
+                }
+                c => {
+                    self.advance();
+                    buf.push(c);
+                }
+            }
+        }
+    }
+
+    // the "\u" has been consumed
+    fn parse_unicode_escape(&mut self) -> Result<char, JsonError> {
+        let hi = self.parse_hex4()?;
+        let code = if (0xD800..0xDC00).contains(&hi) {
+            // surrogate pair
+            if self.advance() != Some('\\') || self.advance() != Some('u') {
+                return Err(self.error("invalid unicode surrogate pair"));
+            }
+            let lo = self.parse_hex4()?;
+            if !(0xDC00..0xE000).contains(&lo) {
+                return Err(self.error("invalid unicode surrogate pair"));
+            }
+            0x10000 + ((hi - 0xD800) << 10) + (lo - 0xDC00)
+        } else {
+            hi
+        };
+        char::from_u32(code).ok_or_else(|| self.error("invalid unicode escape"))
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, JsonError> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self
+                .peek()
+                .and_then(|c| c.to_digit(16))
+                .ok_or_else(|| self.error("invalid unicode escape"))?;
+            self.advance();
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+
+    fn parse_number(&mut self) -> Result<Value, JsonError> {
+        let start = self.cursor;
+        let pos = Pos::new(self.ln, self.col);
+        if self.peek() == Some('-') {
+            self.advance();
+        }
+        while let Some('0'..='9' | '.' | 'e' | 'E' | '+' | '-') = self.peek() {
+            self.advance();
+        }
+        let raw: String = self.chars[start..self.cursor].iter().collect();
+        match raw.parse::<f64>() {
+            Ok(n) if is_number(&raw) => Ok(Value::Number(n)),
+            _ => Err(JsonError {
+                msg: format!("invalid number '{}'", raw),
+                pos,
+            }),
+        }
+    }
+}
+
+// -? (0 | [1-9][0-9]*) (. [0-9]+)? ([eE] [+-]? [0-9]+)?
+fn is_number(raw: &str) -> bool {
+    let mut bytes = raw.bytes().peekable();
+    bytes.next_if_eq(&b'-');
+    match bytes.peek() {
+        // no leading zeros
+        Some(b'0') => {
+            bytes.next();
+        }
+        Some(b'1'..=b'9') => {
+            digits(&mut bytes);
+        }
+        _ => return false,
+    }
+    if bytes.next_if_eq(&b'.').is_some() && digits(&mut bytes) == 0 {
+        return false;
+    }
+    if bytes.next_if(|&c| c == b'e' || c == b'E').is_some() {
+        bytes.next_if(|&c| c == b'+' || c == b'-');
+        if digits(&mut bytes) == 0 {
+            return false;
+        }
+    }
+    bytes.next().is_none()
+}
+
+// skip a run of digits, return its length
+fn digits(bytes: &mut Peekable<Bytes>) -> usize {
+    let mut count = 0;
+    while bytes.next_if(u8::is_ascii_digit).is_some() {
+        count += 1;
+    }
+    count
+}
+
+/**
+ * Value to JSON text, `indent` is the number of spaces per level,
+ * object keys are written in sorted order.
+ * instances are written as their fields.
+ */
+pub fn stringify(value: &Value, indent: Option<usize>) -> Result<String, String> {
+    let mut writer = JsonWriter {
+        buf: String::new(),
+        indent,
+        depth: 0,
+        seen: HashSet::new(),
+    };
+    writer.write_value(value)?;
+    Ok(writer.buf)
+}
+
+struct JsonWriter {
+    buf: String,
+    indent: Option<usize>,
+    depth: usize,
+    // containers currently being written, to detect cycles
+    seen: HashSet<usize>,
+}
+
+impl JsonWriter {
+    fn write_value(&mut self, value: &Value) -> Result<(), String> {
+        match value {
+            Value::Null => self.buf.push_str("null"),
+            Value::Boolean(b) => self.buf.push_str(&b.to_string()),
+            Value::Number(n) if n.is_finite() => self.buf.push_str(&n.to_string()),
+            Value::Number(_) => self.buf.push_str("null"),
+            Value::String(s) => self.write_string(s),
+            Value::Array(array) => {
+                let id = array.as_ptr() as usize;
+                self.enter(id)?;
+                let list = array.borrow();
+                self.write_list(list.len(), "[", "]", |w, i| w.write_value(&list[i]))?;
+                self.seen.remove(&id);
+            }
+            Value::Object(obj) => {
+                let id = obj.as_ptr() as usize;
+                self.enter(id)?;
+                self.write_map(&obj.borrow())?;
+                self.seen.remove(&id);
+            }
+            Value::Instance(instance) => {
+                let id = instance.fields.as_ptr() as usize;
+                self.enter(id)?;
+                self.write_map(&instance.fields.borrow())?;
+                self.seen.remove(&id);
+            }
+            Value::Function(_) | Value::NativeFunction(_) => {
+                return Err("cannot serialize a function".to_string())
+            }
+            Value::Class(c) => return Err(format!("cannot serialize class {}", c.id)),
+        }
+        Ok(())
+    }
+
+    fn enter(&mut self, id: usize) -> Result<(), String> {
+        if self.seen.len() == MAX_DEPTH {
+            return Err(format!("nesting deeper than {} levels", MAX_DEPTH));
+        }
+        if !self.seen.insert(id) {
+            return Err("cannot serialize a cyclic structure".to_string());
+        }
+        Ok(())
+    }
+
+    fn write_map(&mut self, map: &HashMap<String, Value>) -> Result<(), String> {
+        let mut keys: Vec<&String> = map.keys().collect();
+        keys.sort();
+        self.write_list(keys.len(), "{", "}", |w, i| {
+            w.write_string(keys[i]);
+            w.buf.push(':');
+            if w.indent.is_some() {
+                w.buf.push(' ');
+            }
+            w.write_value(&map[keys[i]])
+        })
+    }
+
+    fn write_list<F>(&mut self, len: usize, open: &str, close: &str, mut f: F) -> Result<(), String>
+    where
+        F: FnMut(&mut Self, usize) -> Result<(), String>,
+    {
+        self.buf.push_str(open);
+        if len == 0 {
+            self.buf.push_str(close);
+            return Ok(());
+        }
+        self.depth += 1;
+        for i in 0..len {
+            if i > 0 {
+                self.buf.push(',');
+            }
+            self.newline();
+            f(self, i)?;
+        }
+        self.depth -= 1;
+        self.newline();
+        self.buf.push_str(close);
+        Ok(())
+    }
+
+    fn newline(&mut self) {
+        if let Some(indent) = self.indent {
+            self.buf.push('\n');
+            self.buf.push_str(&" ".repeat(indent * self.depth));
+        }
+    }
+
+    fn write_string(&mut self, s: &str) {
+        self.buf.push('"');
+        for c in s.chars() {
+            match c {
+                '"' => self.buf.push_str("\\\""),
+                '\\' => self.buf.push_str("\\\\"),
+                '\n' => self.buf.push_str("\\n"),
+                '\r' => self.buf.push_str("\\r"),
+                '\t' => self.buf.push_str("\\t"),
+                '\u{8}' => self.buf.push_str("\\b"),
+                '\u{c}' => self.buf.push_str("\\f"),
+                c if (c as u32) < 0x20 => self.buf.push_str(&format!("\\u{:04x}", c as u32)),
+                c => self.buf.push(c),
+            }
+        }
+        self.buf.push('"');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_values() {
+        let value =
+            parse(r#" { "a": [1, -2.5, 1e3], "b": { "c": null }, "d": "x\"yé😀", "e": true } "#)
+                .unwrap();
+
+        let mut inner = HashMap::new();
+        inner.insert("c".to_string(), Value::Null);
+        let mut expect = HashMap::new();
+        expect.insert(
+            "a".to_string(),
+            Value::array(vec![
+                Value::Number(1.0),
+                Value::Number(-2.5),
+                Value::Number(1000.0),
+            ]),
+        );
+        expect.insert("b".to_string(), Value::object(inner));
+        expect.insert("d".to_string(), Value::String("x\"yé😀".to_string()));
+        expect.insert("e".to_string(), Value::Boolean(true));
+
+        assert_eq!(value, Value::object(expect));
+    }
+
+    #[test]
+    fn parse_error_position() {
+        let err = parse("{\n  \"a\": 1,\n  \"b\" 2\n}").unwrap_err();
+        assert_eq!((err.pos.ln, err.pos.col), (3, 7));
+
+        assert!(parse("[1, 2").is_err());
+        assert!(parse("01x").is_err());
+        assert!(parse("[1] 2").is_err());
+    }
+
+    #[test]
+    fn parse_numbers() {
+        for (raw, n) in [("0", 0.0), ("-0", -0.0), ("10", 10.0), ("0.5", 0.5)] {
+            assert_eq!(parse(raw).unwrap(), Value::Number(n));
+        }
+        for (raw, n) in [("-1.25e2", -125.0), ("2E+1", 20.0), ("5e-1", 0.5)] {
+            assert_eq!(parse(raw).unwrap(), Value::Number(n));
+        }
+        for raw in [
+            "01", "-01", "00", "1.", "-", "-.5", "1.e3", "1e", "1e+", "1-2", "-01.5e1",
+        ] {
+            let err = parse(raw).unwrap_err();
+            assert_eq!(err.msg, format!("invalid number '{}'", raw), "{}", raw);
+        }
+        // not the start of a number
+        assert!(parse(".5").is_err());
+        assert!(parse("+1").is_err());
+    }
+
+    #[test]
+    fn stringify_values() {
+        let value = parse(r#"{"b": [1, "two", null], "a": {"x": false}, "c": []}"#).unwrap();
+        assert_eq!(
+            stringify(&value, None).unwrap(),
+            r#"{"a":{"x":false},"b":[1,"two",null],"c":[]}"#
+        );
+        assert_eq!(
+            stringify(&value, Some(2)).unwrap(),
+            "{\n  \"a\": {\n    \"x\": false\n  },\n  \"b\": [\n    1,\n    \"two\",\n    null\n  ],\n  \"c\": []\n}"
+        );
+        assert_eq!(
+            stringify(&Value::String("a\n\"b\"".to_string()), None).unwrap(),
+            r#""a\n\"b\"""#
+        );
+    }
+
+    #[test]
+    fn stringify_cycle() {
+        let array = Value::array(vec![Value::Number(1.0)]);
+        if let Value::Array(a) = &array {
+            a.borrow_mut().push(array.clone());
+        }
+        assert!(stringify(&array, None).is_err());
+
+        // the same array twice is not a cycle
+        let shared = Value::array(vec![]);
+        let twice = Value::array(vec![shared.clone(), shared]);
+        assert_eq!(stringify(&twice, None).unwrap(), "[[],[]]");
+    }
+
+    #[test]
+    fn nesting_limit() {
+        let nested = |depth| "[".repeat(depth) + &"]".repeat(depth);
+        assert!(parse(&nested(MAX_DEPTH)).is_ok());
+        let err = parse(&nested(MAX_DEPTH + 1)).unwrap_err();
+        assert_eq!(err.msg, "nesting deeper than 512 levels");
+        assert_eq!((err.pos.ln, err.pos.col), (1, MAX_DEPTH + 1));
+        // fails before recursing into the rest of the input
+        assert!(parse(&"[".repeat(2_000_000)).is_err());
+        assert!(parse(&r#"{"a":"#.repeat(MAX_DEPTH + 1)).is_err());
+
+        let mut value = Value::Null;
+        for _ in 0..MAX_DEPTH {
+            value = Value::array(vec![value]);
+        }
+        assert!(stringify(&value, None).is_ok());
+        let value = Value::array(vec![value]);
+        assert_eq!(
+            stringify(&value, Some(2)).unwrap_err(),
+            "nesting deeper than 512 levels"
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{builtin, error::RuntimeError, position::Span, value::Value};
+
+use super::{native::NativeFunction, EvalResult};
+
+pub mod array;
+pub mod fs;
+pub mod json;
+pub mod process;
+
+/**
+ * global builtin functions, defined when the interpreter is created.
+ * the ones shared with the vm come from `builtin::builtins`
+ */
+pub fn natives() -> Vec<NativeFunction> {
+    vec![
+        NativeFunction::new("json_parse", 1, 1, json::json_parse),
+        NativeFunction::new("json_stringify", 1, 2, json::json_stringify),
+        NativeFunction::new("read_file", 1, 1, fs::read_file),
+        NativeFunction::new("write_file", 2, 2, fs::write_file),
+        NativeFunction::new("append_file", 2, 2, fs::append_file),
+        NativeFunction::new("exists", 1, 1, fs::exists),
+        NativeFunction::new("list_dir", 1, 1, fs::list_dir),
+        NativeFunction::new("remove_file", 1, 1, fs::remove_file),
+        NativeFunction::new("args", 0, 0, process::args),
+        NativeFunction::new("env", 1, 1, process::env),
+        NativeFunction::new("exit", 0, 1, process::exit),
+    ]
+    .into_iter()
+    .chain(builtin::builtins().into_iter().map(NativeFunction::host))
+    .collect()
+}
+
+fn type_error(name: &str, expect: &str, value: &Value, span: &Span) -> RuntimeError {
+    RuntimeError::Error(
+        format!("{}: expected {}, found {}", name, expect, value.type_name()),
+        span.clone(),
+    )
+}
+
+pub(crate) fn expect_array(
+    name: &str,
+    value: &Value,
+    span: &Span,
+) -> EvalResult<Rc<RefCell<Vec<Value>>>> {
+    match value {
+        Value::Array(a) => Ok(Rc::clone(a)),
+        _ => Err(type_error(name, "array", value, span)),
+    }
+}
+
+pub(crate) fn expect_number(name: &str, value: &Value, span: &Span) -> EvalResult<f64> {
+    match value {
+        Value::Number(n) => Ok(*n),
+        _ => Err(type_error(name, "number", value, span)),
+    }
+}
+
+pub(crate) fn expect_string<'a>(name: &str, value: &'a Value, span: &Span) -> EvalResult<&'a str> {
+    match value {
+        Value::String(s) => Ok(s),
+        _ => Err(type_error(name, "string", value, span)),
+    }
+}
+
+// non-negative integer, used as an array index
+pub(crate) fn expect_index(name: &str, value: &Value, span: &Span) -> EvalResult<usize> {
+    let n = expect_number(name, value, span)?;
+    if n < 0.0 || n.fract() != 0.0 {
+        return Err(RuntimeError::Error(
+            format!("{}: invalid index {}", name, n),
+            span.clone(),
+        ));
+    }
+    Ok(n as usize)
+}
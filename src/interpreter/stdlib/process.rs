@@ -0,0 +1,38 @@
+use crate::{
+    error::RuntimeError,
+    interpreter::{EvalResult, Interpreter},
+    position::Span,
+    value::Value,
+};
+
+use super::{expect_number, expect_string};
+
+pub fn args(interpreter: &mut Interpreter, _: &[Value], _: &Span) -> EvalResult<Value> {
+    let args = interpreter.capabilities().args.clone();
+    Ok(Value::array(args.into_iter().map(Value::String).collect()))
+}
+
+pub fn env(interpreter: &mut Interpreter, args: &[Value], span: &Span) -> EvalResult<Value> {
+    let name = expect_string("env", &args[0], span)?;
+    if !interpreter.capabilities().env {
+        return Err(RuntimeError::Error(
+            "env: environment access is disabled".to_string(),
+            span.clone(),
+        ));
+    }
+    match std::env::var(name) {
+        Ok(v) => Ok(Value::String(v)),
+        Err(_) => Ok(Value::Null),
+    }
+}
+
+/**
+ * stop the script, the host decides what to do with the code
+ */
+pub fn exit(_: &mut Interpreter, args: &[Value], span: &Span) -> EvalResult<Value> {
+    let code = match args.first() {
+        Some(v) => expect_number("exit", v, span)? as i32,
+        None => 0,
+    };
+    Err(RuntimeError::Exit(code))
+}
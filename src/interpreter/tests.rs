@@ -1,148 +1,593 @@
-use crate::{lexer::Lexer, parser::parser::Parser};
+use crate::{
+    analizer::resolver::Resolver,
+    error::TracedError,
+    interpreter::config::{Capabilities, FsAccess},
+    lexer::Lexer,
+    parser::parser::Parser,
+    value::Value,
+};
 
 use super::*;
 
-fn interpret(contents: &str) {
-    let lexer = Lexer::new(&contents.as_bytes(), "source.txt");
+fn eval(contents: &str) -> EvalResult<Option<Value>> {
+    eval_with(Interpreter::default(), contents)
+}
+
+fn eval_with(i: Interpreter, contents: &str) -> EvalResult<Option<Value>> {
+    eval_traced(i, contents).map_err(|e| e.error)
+}
+
+// the error with its backtrace
+fn eval_traced(mut i: Interpreter, contents: &str) -> Result<Option<Value>, TracedError> {
+    let lexer = Lexer::new(contents.as_bytes(), "source.txt");
     let mut parser = Parser::new(lexer);
-    let ast = parser.parse();
+    let ast = parser.parse().unwrap();
+
+    if let Err(e) = Resolver::new(&mut i).resolve(&ast) {
+        panic!("{}", e);
+    }
+    i.eval(ast)
+}
 
-    let mut i = Interpreter::default();
-    i.interpret(ast.unwrap()).unwrap();
+fn eval_ok(contents: &str) -> Value {
+    eval(contents).unwrap().unwrap_or(Value::Null)
+}
+
+fn numbers(list: &[f64]) -> Value {
+    Value::array(list.iter().map(|n| Value::Number(*n)).collect())
 }
 
 #[test]
-fn var_decl() {
+fn shadowing() {
     let source = r#"
-        let a = "global a";
-        let b = "global b";
-        let c = "global c";
-        {
-        let a = "outer a";
-        let b = "outer b";
+        let out = []
+        let a = 1
         {
-            let a = "inner a";
-            print a;
-            print b;
-            print c;
-        }
-        print a;
-        print b;
-        print c;
+            let a = 2
+            {
+                let a = 3
+                out.push(a)
+            }
+            out.push(a)
         }
-        print a;
-        print b;
-        print c;   
+        out.push(a)
+        out
     "#;
+    assert_eq!(eval_ok(source), numbers(&[3.0, 2.0, 1.0]));
+}
 
-    interpret(source);
+#[test]
+fn logical_short_circuit() {
+    assert_eq!(
+        eval_ok("let a = false && undefined; a"),
+        Value::Boolean(false)
+    );
+    assert_eq!(eval_ok("1 || undefined"), Value::Number(1.0));
+    assert_eq!(eval_ok("null || \"b\""), Value::String("b".to_string()));
 }
+
 #[test]
-fn while_stmt() {
+fn array_methods() {
     let source = r#"
-        let a = 0;
-        while (a < 10) {
-            print a;
-            a = a + 1;
-        }    
+        let a = [3, 1, 2];
+        a.push(5);
+        a.insert(0, 4);
+        a.remove(1);
+        a.pop();
+        a
     "#;
+    assert_eq!(eval_ok(source), numbers(&[4.0, 1.0, 2.0]));
 
-    interpret(source);
+    assert_eq!(eval_ok("[1, 2, 3].len()"), Value::Number(3.0));
+    assert_eq!(eval_ok("[1, 2, 3, 4].slice(1, 3)"), numbers(&[2.0, 3.0]));
+    assert_eq!(eval_ok("[1, 2].concat([3])"), numbers(&[1.0, 2.0, 3.0]));
+    assert_eq!(eval_ok("[1, 2, 3].reverse()"), numbers(&[3.0, 2.0, 1.0]));
+    assert_eq!(eval_ok("[1, 2, 3].index_of(3)"), Value::Number(2.0));
+    assert_eq!(eval_ok("[1, 2, 3].index_of(4)"), Value::Number(-1.0));
+    assert_eq!(eval_ok("[1, 2, 3].contains(2)"), Value::Boolean(true));
+    assert_eq!(
+        eval_ok(r#"[1, "a", true].join("-")"#),
+        Value::String("1-a-true".to_string())
+    );
+    assert_eq!(eval_ok("[3, 1, 2].sort()"), numbers(&[1.0, 2.0, 3.0]));
 }
 
 #[test]
-fn while_break_stmt() {
-    let source = r#"
-        let a = 0;
-        while (a < 10) {
-            if (a == 5) {
-                return;
-            } else {
-                print a;
-            }
-            a = a + 1;
-        }    
+fn array_reference_semantics() {
+    let source = r#"
+        let a = [1];
+        let b = a;
+        b.push(2);
+        fn add(arr) {
+            arr.push(3);
+        }
+        add(a);
+        a
     "#;
+    assert_eq!(eval_ok(source), numbers(&[1.0, 2.0, 3.0]));
+}
 
-    interpret(source);
+#[test]
+fn array_cycles() {
+    let source = r#"
+        let a = [1];
+        a.push(a);
+        [a.contains(a), a.index_of(a), a.join("-")]
+    "#;
+    assert_eq!(
+        eval_ok(source).to_string(),
+        "[ true, 1, \"1-[ 1, [...] ]\" ]"
+    );
 }
 
 #[test]
-fn if_break_stmt() {
+fn array_higher_order() {
     let source = r#"
-        let a = 0;
-        while (a < 10) {
-            if (a == 5)  return;
-            print a;
-            a = a + 1;
-        }    
+        fn double(x) { return x * 2 }
+        fn odd(x) { return x != 2 }
+        fn sum(acc, x) { return acc + x }
+        fn desc(a, b) { return b - a }
+        let a = [1, 2, 3];
+        [
+            a.map(double),
+            a.filter(odd),
+            a.reduce(sum),
+            a.reduce(sum, 10),
+            a.find(odd),
+            a.any(odd),
+            a.all(odd),
+            a.sort(desc)
+        ]
     "#;
+    let expect = Value::array(vec![
+        numbers(&[2.0, 4.0, 6.0]),
+        numbers(&[1.0, 3.0]),
+        Value::Number(6.0),
+        Value::Number(16.0),
+        Value::Number(1.0),
+        Value::Boolean(true),
+        Value::Boolean(false),
+        numbers(&[3.0, 2.0, 1.0]),
+    ]);
+    assert_eq!(eval_ok(source), expect);
+}
 
-    interpret(source);
+#[test]
+fn array_errors() {
+    assert!(matches!(
+        eval("[1].remove(3)"),
+        Err(RuntimeError::Error(..))
+    ));
+    assert!(matches!(
+        eval("[1, \"a\"].sort()"),
+        Err(RuntimeError::Error(..))
+    ));
+    assert!(matches!(eval("[].reduce(1)"), Err(RuntimeError::Error(..))));
+    assert!(matches!(
+        eval("[1].push()"),
+        Err(RuntimeError::ArgsMismatched(..))
+    ));
+
+    // errors raised by the callback are propagated
+    let source = r#"
+        fn bad(x) { return y }
+        [1].map(bad)
+    "#;
+    assert!(matches!(
+        eval(source),
+        Err(RuntimeError::ReferenceError(..))
+    ));
 }
 
 #[test]
-fn break_stmt() {
+fn json_builtins() {
+    let source = r#"
+        let data = json_parse("{ \u{22}name\u{22}: \u{22}tinyx\u{22}, \u{22}tags\u{22}: [1, 2] }");
+        data.tags.push(3);
+        data.version = 1;
+        json_stringify(data)
+    "#;
+    assert_eq!(
+        eval_ok(source),
+        Value::String(r#"{"name":"tinyx","tags":[1,2,3],"version":1}"#.to_string())
+    );
+
     let source = r#"
-        fn count(n) {
-            while (n < 10) {
-                if (n == 3) return n; 
-                print n;
-                n = n + 1;
+        class Point {
+            init(x, y) {
+                this.x = x;
+                this.y = y;
             }
         }
-      
-        count(1);   
+        json_stringify(Point(1, 2))
+    "#;
+    assert_eq!(
+        eval_ok(source),
+        Value::String(r#"{"x":1,"y":2}"#.to_string())
+    );
+
+    let source = r#"
+        fn f() {}
+        json_stringify([f])
     "#;
+    assert!(matches!(eval(source), Err(RuntimeError::Error(..))));
+    assert!(matches!(
+        eval(r#"json_parse("[1,")"#),
+        Err(RuntimeError::Error(..))
+    ));
 
-    interpret(source);
+    // too deep to parse or to write
+    let source = r#"
+        let a = []
+        let i = 0
+        while (i < 1000) {
+            a = [a]
+            i = i + 1
+        }
+        json_stringify(a)
+    "#;
+    assert!(matches!(eval(source), Err(RuntimeError::Error(msg, _)) if msg.contains("nesting")));
+    let source = format!(r#"json_parse("{}")"#, "[".repeat(100_000));
+    assert!(matches!(eval(&source), Err(RuntimeError::Error(msg, _)) if msg.contains("nesting")));
 }
 
 #[test]
-fn if_stmt() {
+fn fs_builtins() {
+    let dir = std::env::temp_dir().join(format!("tinyx-fs-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let capabilities = Capabilities {
+        fs: FsAccess::Dirs(vec![dir.clone()]),
+        ..Default::default()
+    };
+
+    let source = format!(
+        r#"
+        let file = "{}/a.txt";
+        write_file(file, "hello");
+        append_file(file, " world");
+        [read_file(file), exists(file), list_dir("{}")]
+    "#,
+        dir.display(),
+        dir.display()
+    );
+    let value = eval_with(
+        Interpreter::new().with_capabilities(capabilities.clone()),
+        &source,
+    );
+    assert_eq!(
+        value.unwrap().unwrap(),
+        Value::array(vec![
+            Value::String("hello world".to_string()),
+            Value::Boolean(true),
+            Value::array(vec![Value::String("a.txt".to_string())]),
+        ])
+    );
+
+    let source = format!(r#"remove_file("{}/a.txt")"#, dir.display());
+    eval_with(
+        Interpreter::new().with_capabilities(capabilities.clone()),
+        &source,
+    )
+    .unwrap();
+    assert!(!dir.join("a.txt").exists());
+
+    // outside of the allowed directories
+    let source = format!(r#"read_file("{}/../x.txt")"#, dir.display());
+    let err = eval_with(Interpreter::new().with_capabilities(capabilities), &source);
+    assert!(matches!(err, Err(RuntimeError::Error(msg, _)) if msg.contains("permission denied")));
+
+    // sandboxed by default
+    let err = eval(r#"exists("source.txt")"#);
+    assert!(
+        matches!(err, Err(RuntimeError::Error(msg, span)) if msg.contains("disabled") && span.loc.start.ln == 1)
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn process_builtins() {
+    let i = Interpreter::new().with_capabilities(Capabilities {
+        args: vec!["x".to_string()],
+        ..Default::default()
+    });
+    assert_eq!(
+        eval_with(i, "args()").unwrap().unwrap(),
+        Value::array(vec![Value::String("x".to_string())])
+    );
+    assert!(matches!(
+        eval(r#"env("PATH")"#),
+        Err(RuntimeError::Error(..))
+    ));
+    let i = Interpreter::new().with_capabilities(Capabilities::unrestricted(vec![]));
+    assert!(matches!(
+        eval_with(i, r#"env("TINYX_SURELY_UNSET")"#),
+        Ok(Some(Value::Null))
+    ));
+    assert!(matches!(eval("exit(3)"), Err(RuntimeError::Exit(3))));
+    assert!(matches!(eval("clock()"), Ok(Some(Value::Number(n))) if n > 0.0));
+}
+
+#[test]
+fn backtrace() {
+    let source = r#"
+        class Greeter {
+            greet(name) {
+                return name + missing;
+            }
+        }
+        fn run(g) {
+            return g.greet("a");
+        }
+        run(Greeter());
+    "#;
+    let err = eval_traced(Interpreter::new(), source).unwrap_err();
+    assert!(matches!(&err.error, RuntimeError::ReferenceError(name, _) if name == "missing"));
+
+    let frames: Vec<(String, usize)> = err
+        .backtrace
+        .iter()
+        .map(|f| (f.name.clone(), f.call_site.loc.start.ln))
+        .collect();
+    assert_eq!(
+        frames,
+        vec![("run".to_string(), 10), ("Greeter.greet".to_string(), 8)]
+    );
+
+    let msg = err.to_string();
+    assert!(msg.starts_with("ReferenceError: missing is not defined"));
+    assert!(msg.ends_with("at run (source.txt:10:9)\n    at Greeter.greet (source.txt:8:20)"));
+
+    // errors at the top level have no backtrace
+    let err = eval_traced(Interpreter::new(), "missing").unwrap_err();
+    assert!(err.backtrace.is_empty());
+
+    // a caught exception leaves no backtrace
     let source = r#"
-        // if stmt
-        let a = 11
-        let b = 3
-        if (a-b<0) {
-            print "a < b"
-        } else if(a - b >0) {
-            print "a > b"
+        fn fail() { throw 1 }
+        try { fail() } catch (e) {}
+        missing
+    "#;
+    let err = eval_traced(Interpreter::new(), source).unwrap_err();
+    assert!(matches!(err.error, RuntimeError::ReferenceError(..)));
+    assert!(err.backtrace.is_empty());
+
+    // a finally block between the throw and the top keeps the trace of the throw
+    let source = r#"
+        fn cleanup() {}
+        fn fail() {
+            try { throw 1 } finally { cleanup() }
         }
-        print a
-    
+        fail()
     "#;
+    let err = eval_traced(Interpreter::new(), source).unwrap_err();
+    let names: Vec<&str> = err.backtrace.iter().map(|f| f.name.as_str()).collect();
+    assert_eq!(names, vec!["fail"]);
 
-    interpret(source);
+    // the frames of caught exceptions don't count toward the call depth
+    let i = Interpreter::new().with_limits(config::Limits {
+        max_call_depth: Some(10),
+        ..Default::default()
+    });
+    let source = r#"
+        fn down(n) {
+            if (n == 0) { throw "bottom" }
+            down(n - 1)
+        }
+        let i = 0
+        while (i < 5) {
+            try { down(8) } catch (e) {}
+            i = i + 1
+        }
+        down(8)
+    "#;
+    let err = eval_traced(i, source).unwrap_err();
+    assert!(matches!(err.error, RuntimeError::Thrown(..)));
+    assert_eq!(err.backtrace.len(), 9);
 }
 
 #[test]
-fn closure_fn() {
-    let source = r#"
-        // closure
-        fn makeCounter() {
-            let i = 0;
-            fn count() {
-                i = i + 1;
-                print i;
+fn limits() {
+    use crate::interpreter::config::Limits;
+    use std::time::Duration;
+
+    let source = r#"
+        fn f(n) {
+            return f(n + 1);
+        }
+        f(0);
+    "#;
+    // on a thread with the default 2MB stack, the default limits stop the
+    // recursion before it overflows the host stack, in debug and release builds
+    let nested = r#"
+        fn f(n) {
+            if (n >= 0) {
+                let i = 0;
+                while (i < 1) {
+                    {
+                        i = i + 1;
+                        try { f(n + 1); } finally {}
+                    }
+                }
             }
-            return count;
         }
-        
-        let counter = makeCounter();
-        counter(); // "1".
-        counter(); // "2".
+        f(0);
     "#;
+    let depths = std::thread::spawn(move || {
+        [source, nested].map(|source| match eval_traced(Interpreter::new(), source) {
+            Err(TracedError {
+                error: RuntimeError::StackOverflow(_),
+                backtrace,
+            }) => backtrace.len(),
+            result => panic!("expect stack overflow, found {:?}", result),
+        })
+    })
+    .join()
+    .unwrap();
+    assert!(depths[0] > 50, "{:?}", depths);
+
+    // the default depth on a larger stack
+    let (line, depth) = std::thread::Builder::new()
+        .stack_size(config::HOST_STACK_SIZE)
+        .spawn(move || {
+            let i = Interpreter::new().with_limits(Limits {
+                max_stack_size: Some(config::HOST_STACK_SIZE / 2),
+                ..Default::default()
+            });
+            match eval_traced(i, source) {
+                Err(TracedError {
+                    error: RuntimeError::StackOverflow(span),
+                    backtrace,
+                }) => (span.loc.start.ln, backtrace.len()),
+                result => panic!("expect stack overflow, found {:?}", result),
+            }
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+    assert_eq!((line, depth), (3, config::DEFAULT_MAX_CALL_DEPTH));
+
+    let i = Interpreter::new().with_limits(Limits {
+        max_call_depth: Some(10),
+        ..Default::default()
+    });
+    let err = eval_traced(i, source).unwrap_err();
+    assert!(matches!(err.error, RuntimeError::StackOverflow(_)));
+    assert_eq!(err.backtrace.len(), 10);
+
+    let i = Interpreter::new().with_limits(Limits {
+        max_steps: Some(10_000),
+        ..Default::default()
+    });
+    assert!(matches!(
+        eval_with(i, "while (true) {}"),
+        Err(RuntimeError::StepLimitExceeded(10_000))
+    ));
 
-    interpret(source);
+    let i = Interpreter::new().with_limits(Limits {
+        timeout: Some(Duration::from_millis(20)),
+        ..Default::default()
+    });
+    let source = r#"
+        fn spin() {
+            while (true) {}
+        }
+        spin();
+    "#;
+    assert!(matches!(
+        eval_with(i, source),
+        Err(RuntimeError::Timeout(..))
+    ));
+
+    // the budget is per run, not per interpreter
+    let mut i = Interpreter::new().with_limits(Limits {
+        max_steps: Some(100),
+        ..Default::default()
+    });
+    for _ in 0..10 {
+        let lexer = Lexer::new(b"1 + 2 * 3", "source.txt");
+        let ast = Parser::new(lexer).parse().unwrap();
+        assert!(i.eval(ast).is_ok());
+    }
 }
 
 #[test]
-fn test_rust_scope() {
-    let a = "global";
-    {
-        println!("{}", a);
-        let a = "block";
-        println!("{}", a);
-    }
+fn exceptions() {
+    let source = r#"
+        let out = []
+        fn risky(n) {
+            if (n > 1) throw "too big"
+            return n
+        }
+        try {
+            out.push(risky(1))
+            out.push(risky(2))
+            out.push("unreachable")
+        } catch (e) {
+            out.push(e)
+        } finally {
+            out.push("finally")
+        }
+        fn early() {
+            try {
+                return "try"
+            } finally {
+                out.push("cleanup")
+            }
+        }
+        out.push(early())
+        fn replaced() {
+            try {
+                throw "lost"
+            } finally {
+                return "finally"
+            }
+        }
+        out.push(replaced())
+        out.join(", ")
+    "#;
+    assert_eq!(
+        eval_ok(source),
+        Value::String("1, too big, finally, cleanup, try, finally".to_string())
+    );
+
+    // the error keeps where the value was thrown
+    let source = r#"
+        fn fail() {
+            throw 42;
+        }
+        try {
+            fail();
+        } finally {
+            print "cleanup";
+        }
+    "#;
+    let err = eval_traced(Interpreter::new(), source).unwrap_err();
+    assert!(matches!(&err.error, RuntimeError::Thrown(value, span)
+        if **value == Value::Number(42.0) && span.loc.start.ln == 3));
+    assert_eq!(err.backtrace.len(), 1);
+    assert!(err
+        .error
+        .to_string()
+        .starts_with("RuntimeError: uncaught exception: 42, at: source.txt:3:13"));
+
+    // a return in a finally block doesn't hide the errors of the interpreter
+    let source = r#"
+        fn g() {
+            try { exit(3) } finally { return 2 }
+        }
+        g()
+    "#;
+    assert!(matches!(eval(source), Err(RuntimeError::Exit(3))));
+    let i = Interpreter::new().with_limits(config::Limits {
+        max_steps: Some(1_000),
+        ..Default::default()
+    });
+    let source = r#"
+        fn spin() {
+            try {
+                while (true) {}
+            } finally {
+                return 1
+            }
+        }
+        spin()
+    "#;
+    assert!(matches!(
+        eval_with(i, source),
+        Err(RuntimeError::StepLimitExceeded(1_000))
+    ));
+
+    // errors of the interpreter aren't exceptions
+    let source = r#"
+        try {
+            missing
+        } catch (e) {
+            print e
+        }
+    "#;
+    assert!(matches!(
+        eval(source),
+        Err(RuntimeError::ReferenceError(..))
+    ));
 }
@@ -15,6 +15,8 @@ pub trait StmtVisitor {
             Statement::PrintStmt(expr) => self.visit_print_stmt(expr),
             Statement::While(w) => self.visit_while_stmt(w),
             Statement::ClassDeclaration(class) => self.visit_class_declare(class),
+            Statement::Throw(throw) => self.visit_throw_stmt(throw),
+            Statement::Try(t) => self.visit_try_stmt(t),
         }
     }
 
@@ -28,6 +30,8 @@ pub trait StmtVisitor {
     fn visit_return_stmt(&mut self, stmt: &ReturnStatement) -> Self::Item;
     fn visit_print_stmt(&mut self, expr: &Expr) -> Self::Item;
     fn visit_while_stmt(&mut self, stmt: &WhileStmt) -> Self::Item;
+    fn visit_throw_stmt(&mut self, stmt: &ThrowStatement) -> Self::Item;
+    fn visit_try_stmt(&mut self, stmt: &TryStatement) -> Self::Item;
 }
 
 // expr ===============================
@@ -45,6 +49,7 @@ pub trait ExprVisitor {
             Expr::Assign(a) => self.visit_assign(a),
             Expr::Call(c) => self.visit_call(c),
             Expr::NullLiteral => self.visit_null(),
+            Expr::ArrayLiteral(a) => self.visit_array(a),
             Expr::Logical(l) => self.visit_logical(l),
             Expr::Get(m) => self.visit_get(m),
             Expr::Set(s) => self.visit_set(s),
@@ -69,4 +74,5 @@ pub trait ExprVisitor {
     fn visit_string(&mut self, lit: &StringLiteral) -> Self::Item;
     fn visit_boolean(&mut self, lit: bool) -> Self::Item;
     fn visit_null(&mut self) -> Self::Item;
+    fn visit_array(&mut self, lit: &ArrayLiteral) -> Self::Item;
 }
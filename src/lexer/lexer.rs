@@ -62,6 +62,7 @@ impl<'a> Lexer<'a> {
         Some(self.source[self.cursor])
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> ParseResult<Token> {
         self.skip_whitespace();
         let start = self.pos();
@@ -87,6 +88,8 @@ impl<'a> Lexer<'a> {
                 b'}' => Token::new(TokenKind::BraceClose, "}".to_string(), start, self.pos()),
                 b'(' => Token::new(TokenKind::ParenOpen, "(".to_string(), start, self.pos()),
                 b')' => Token::new(TokenKind::ParenClose, ")".to_string(), start, self.pos()),
+                b'[' => Token::new(TokenKind::BracketOpen, "[".to_string(), start, self.pos()),
+                b']' => Token::new(TokenKind::BracketClose, "]".to_string(), start, self.pos()),
                 b'.' => Token::new(TokenKind::Dot, ".".to_string(), start, self.pos()),
                 _ => {
                     return Err(ParserError::invalid_charactor(
@@ -277,16 +280,13 @@ impl<'a> Lexer<'a> {
         Ok(Token::new(TokenKind::Operator(op), buf, start, self.pos()))
     }
 
+    // the end of line is left to end the statement before the comment
     fn skip_comment(&mut self) -> ParseResult<Token> {
         while let Some(c) = self.peek() {
-            match c {
-                b'\n' | b'\r' => {
-                    self.advance();
-                    self.newline();
-                    break;
-                }
-                _ => self.advance(),
-            };
+            if c == b'\n' || c == b'\r' {
+                break;
+            }
+            self.advance();
         }
         self.next()
     }
@@ -351,7 +351,7 @@ mod tests {
 
     #[test]
     fn test_read_number() {
-        let n = &[b'1', b'2', b'3'];
+        let n = b"123";
         println!("{:?}", n);
         let mut res = 0u32;
         res = (res * 10) + (n[0] - b'0') as u32;
@@ -366,4 +366,19 @@ mod tests {
         let mut lex = Lexer::new(s.as_bytes(), "test");
         lex.log();
     }
+
+    #[test]
+    fn comment_ends_statement() {
+        let mut lex = Lexer::new(b"a // comment\nb", "test");
+        let kinds: Vec<_> = (0..4).map(|_| lex.next().unwrap().kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenKind::Identifier,
+                TokenKind::Eol,
+                TokenKind::Identifier,
+                TokenKind::Eof
+            ]
+        );
+    }
 }
@@ -1,3 +1,4 @@
 // mod helper;
+#[allow(clippy::module_inception)]
 mod lexer;
 pub use lexer::Lexer;
@@ -1,43 +1,216 @@
 use std::{
+    env, fs,
     fs::File,
-    io::{BufReader, Read},
+    io::{self, BufReader, Read},
+    path::Path,
+    process, thread,
 };
 
 use tinyx::{
-    analizer::resolver::Resolver, interpreter::Interpreter, lexer::Lexer, parser::parser::Parser,
+    analizer::resolver::Resolver,
+    ast::Program,
+    bytecode::{
+        assembler, compiler::compile, optimize::optimize, register, serialize, verify::verify,
+        vm::Vm, Chunk,
+    },
+    error::{RuntimeError, TracedError},
+    interpreter::{
+        config::{Capabilities, Limits, HOST_STACK_SIZE},
+        Interpreter,
+    },
+    lexer::Lexer,
+    parser::parser::Parser,
 };
 
 fn main() {
-    let file = File::open("source.txt").unwrap();
-    println!("{:?}", file);
-    let mut buf_reader = BufReader::new(file);
+    // a larger stack than the main thread, for deep recursion in the interpreter
+    let cli = thread::Builder::new()
+        .stack_size(HOST_STACK_SIZE)
+        .spawn(cli)
+        .expect("failed to spawn the interpreter thread");
+    if cli.join().is_err() {
+        process::exit(101);
+    }
+}
 
-    let mut contents = String::new();
-    buf_reader.read_to_string(&mut contents).unwrap();
+fn cli() {
+    // tinyx [--vm [-O] [--trace]] [file] [script args...]
+    // tinyx --register [--trace] [file]
+    // tinyx build [-O] <file.tx> [-o <file.txc>]
+    // tinyx run [-O] [--trace] <file.txc | file.txasm>
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(|s| s.as_str()) {
+        Some("build") => build(&args[1..]),
+        Some("run") => run_bytecode(&args[1..]),
+        _ => run_source(args),
+    }
+}
+
+fn run_source(mut args: Vec<String>) {
+    let use_vm = take_flag(&mut args, "--vm");
+    let use_registers = !use_vm && take_flag(&mut args, "--register");
+    let optimized = use_vm && take_flag(&mut args, "-O");
+    let trace = (use_vm || use_registers) && take_flag(&mut args, "--trace");
+    let filename = args.first().map(|s| s.as_str()).unwrap_or("source.txt");
+    let script_args = args.iter().skip(1).cloned().collect();
+
+    let contents = read_source(filename);
 
     // println!("\n------- TOKEN START -----------\n");
     // let mut lexer_for_log = Lexer::new(&contents.as_bytes(), "source.txt");
     // lexer_for_log.log();
     // println!("\n-------TOKEN END -----------\n\n");
 
-    println!("\n-------- AST START ----------\n");
-    let lexer = Lexer::new(contents.as_bytes(), "source.txt");
+    let lexer = Lexer::new(contents.as_bytes(), filename);
     let mut parser = Parser::new(lexer);
     let ast = parser.parse().unwrap();
     // println!("{:#}", ast);
-    println!("\n-------- AST END -----------\n\n");
 
-    println!("\n------ INTERPRETER START ------------\n");
-    let mut interpreter = Interpreter::default();
+    if use_vm {
+        run_vm(ast, optimized, trace);
+    } else if use_registers {
+        run_register_vm(ast, trace);
+    } else {
+        run_interpreter(ast, script_args);
+    }
+}
+
+// remove a flag given before the file name
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    let flags = args.iter().take_while(|arg| arg.starts_with('-')).count();
+    match args[..flags].iter().position(|arg| arg == flag) {
+        Some(i) => {
+            args.remove(i);
+            true
+        }
+        None => false,
+    }
+}
+
+fn read_source(filename: &str) -> String {
+    let file = File::open(filename).unwrap();
+    let mut buf_reader = BufReader::new(file);
+
+    let mut contents = String::new();
+    buf_reader.read_to_string(&mut contents).unwrap();
+    contents
+}
+
+fn fail(msg: impl std::fmt::Display) -> ! {
+    eprintln!("ERROR: {}", msg);
+    process::exit(1);
+}
+
+fn build(args: &[String]) {
+    let mut args = args.to_vec();
+    let optimized = take_flag(&mut args, "-O");
+    let Some(filename) = args.first() else {
+        fail("usage: tinyx build [-O] <file.tx> [-o <file.txc>]");
+    };
+    let output = match args.iter().position(|a| a == "-o") {
+        Some(i) => match args.get(i + 1) {
+            Some(output) => output.clone(),
+            None => fail("missing output file after -o"),
+        },
+        None => Path::new(filename)
+            .with_extension("txc")
+            .to_string_lossy()
+            .to_string(),
+    };
+
+    let contents = read_source(filename);
+    let lexer = Lexer::new(contents.as_bytes(), filename);
+    let ast = Parser::new(lexer)
+        .parse()
+        .unwrap_or_else(|e| fail(format!("{:?}", e)));
+    let mut chunk = compile(&ast).unwrap_or_else(|e| fail(e));
+    if optimized {
+        chunk = optimize(&chunk);
+    }
+    let bytes = serialize::serialize(&chunk).unwrap_or_else(|e| fail(e));
+    if let Err(e) = fs::write(&output, bytes) {
+        fail(format!("can't write {}: {}", output, e));
+    }
+    println!("compiled {} -> {}", filename, output);
+}
+
+fn run_bytecode(args: &[String]) {
+    let mut args = args.to_vec();
+    let optimized = take_flag(&mut args, "-O");
+    let trace = take_flag(&mut args, "--trace");
+    let Some(filename) = args.first() else {
+        fail("usage: tinyx run [-O] [--trace] <file.txc | file.txasm>");
+    };
+    let bytes =
+        fs::read(filename).unwrap_or_else(|e| fail(format!("can't read {}: {}", filename, e)));
+    // a textual listing, or a compiled file
+    let chunk = if filename.ends_with(".txasm") {
+        let source = String::from_utf8(bytes).unwrap_or_else(|e| fail(e));
+        assembler::assemble(&source).unwrap_or_else(|e| fail(e))
+    } else {
+        serialize::deserialize(&bytes).unwrap_or_else(|e| fail(e))
+    };
+    run_chunk(chunk, optimized, trace);
+}
+
+fn run_interpreter(ast: Program, script_args: Vec<String>) {
+    let mut interpreter = Interpreter::new()
+        .with_capabilities(Capabilities::unrestricted(script_args))
+        .with_limits(Limits {
+            max_stack_size: Some(HOST_STACK_SIZE / 2),
+            ..Default::default()
+        });
     let mut r = Resolver::new(&mut interpreter);
     match r.resolve(&ast) {
-        Ok(_) => interpreter
-            .interpret(ast)
-            .unwrap_or_else(|e| eprintln!("ERROR: {}", e)),
+        Ok(_) => match interpreter.interpret(ast) {
+            Ok(_) => (),
+            Err(TracedError {
+                error: RuntimeError::Exit(code),
+                ..
+            }) => process::exit(code),
+            Err(e) => eprintln!("ERROR: {}", e),
+        },
         Err(e) => eprintln!("ERROR: {}", e),
     }
+}
 
-    println!("\n------- INTERPRETER END -----------\n\n");
+fn run_vm(ast: Program, optimized: bool, trace: bool) {
+    match compile(&ast) {
+        Ok(chunk) => run_chunk(chunk, optimized, trace),
+        Err(e) => eprintln!("ERROR: {}", e),
+    }
+}
+
+fn run_chunk(mut chunk: Chunk, optimized: bool, trace: bool) {
+    // only verified code can be optimized
+    if optimized {
+        verify(&chunk).unwrap_or_else(|e| fail(e));
+        chunk = optimize(&chunk);
+    }
+    let mut vm = Vm::load(chunk).unwrap_or_else(|e| fail(e));
+    if trace {
+        vm = vm.with_trace(io::stdout());
+    }
+    if let Err(e) = vm.run() {
+        eprintln!("ERROR: {}", e);
+    }
+}
+
+fn run_register_vm(ast: Program, trace: bool) {
+    let module = match register::compiler::compile(&ast) {
+        Ok(module) => module,
+        Err(e) => return eprintln!("ERROR: {}", e),
+    };
+    if trace {
+        register::debug::disassemble_module(&module);
+    }
+    let mut vm = register::vm::RegisterVm::new(module);
+    if trace {
+        vm = vm.with_trace(io::stdout());
+    }
+    if let Err(e) = vm.run() {
+        eprintln!("ERROR: {}", e);
+    }
 }
 
 #[cfg(test)]
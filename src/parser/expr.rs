@@ -250,6 +250,7 @@ impl<'a> Parser<'a> {
      *      : Literal
      *      | Identifier
      *      | ParenthesizedExpression
+     *      | ArrayLiteral
      *      | ThisExpression
      *      | SuperExpression
      *      ;
@@ -261,6 +262,7 @@ impl<'a> Parser<'a> {
             }
             TokenKind::Identifier => self.parse_identifier_expr(),
             TokenKind::ParenOpen => self.parse_parenthesized_expr(),
+            TokenKind::BracketOpen => self.parse_array_literal(),
             TokenKind::Keyword(Keyword::This) => self.parse_this_expr(),
             TokenKind::Keyword(Keyword::Super) => self.parse_super_expr(),
             _ => Err(ParserError::invalid_token(
@@ -282,6 +284,29 @@ impl<'a> Parser<'a> {
         Ok(expr)
     }
 
+    /**
+     * ArrayLiteral
+     *   : "[" (Expression ("," Expression)*)? "]"
+     *   ;
+     */
+    fn parse_array_literal(&mut self) -> ParseResult<Expr> {
+        let loc = self.current_token.loc;
+        self.eat(TokenKind::BracketOpen)?;
+        let mut list = Vec::new();
+        if !self.token_is(TokenKind::BracketClose) {
+            list.push(self.parse_expression()?);
+            while self.token_is(TokenKind::Comma) {
+                self.consume();
+                list.push(self.parse_expression()?);
+            }
+        }
+        self.eat(TokenKind::BracketClose)?;
+        Ok(Expr::ArrayLiteral(ArrayLiteral::new(
+            list,
+            Span::new(self.lexer.filename.to_string(), loc),
+        )))
+    }
+
     /**
      * Literal
      *   : NumericLiteral
@@ -16,11 +16,9 @@ impl<'a> Parser<'a> {
 
         match self.current_token.raw.parse::<f64>() {
             Ok(n) => {
+                let span = Span::new(self.lexer.filename.into(), self.current_token.loc);
                 self.consume();
-                Ok(Expr::NumericLiteral(NumericLiteral::new(
-                    n,
-                    Span::new(self.lexer.filename.into(), self.current_token.loc),
-                )))
+                Ok(Expr::NumericLiteral(NumericLiteral::new(n, span)))
             }
             Err(_e) => Err(ParserError::parse_number_error(
                 self.lexer.filename,
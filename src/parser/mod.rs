@@ -2,6 +2,7 @@ use crate::error::ParserError;
 
 pub mod expr;
 pub mod literal;
+#[allow(clippy::module_inception)]
 pub mod parser;
 pub mod stmt;
 
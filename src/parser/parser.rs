@@ -45,15 +45,12 @@ impl<'a> Parser<'a> {
      *  3. expect_stmt_terminator 方法，判断当前是token::semi或者could_be_semi=true
      *  4. 单独处理了return stmt特例情况，目前没有 break 和 continue，也没有 --  ++ 操作
      */
-
     fn asi(&mut self) {
         if self.token_is(TokenKind::Eol) {
             self.consume();
             self.could_be_semi = true;
-        } else if self.expect_one_of(&[TokenKind::BraceClose, TokenKind::Eof]) {
-            self.could_be_semi = true;
         } else {
-            self.could_be_semi = false;
+            self.could_be_semi = self.expect_one_of(&[TokenKind::BraceClose, TokenKind::Eof]);
         }
     }
 
@@ -1,6 +1,7 @@
 use crate::{
     ast::*,
     error::ParserError,
+    position::Span,
     token::{Keyword, Operator, TokenKind},
 };
 
@@ -16,10 +17,7 @@ impl<'a> Parser<'a> {
         let mut list = Vec::new();
 
         while !self.is_stmt_end() {
-            match self.current_token.kind {
-                // TokenKind::Eol => self.consume(),
-                _ => list.push(self.parse_statment()?),
-            }
+            list.push(self.parse_statment()?);
         }
         Ok(list)
     }
@@ -36,6 +34,8 @@ impl<'a> Parser<'a> {
      *      | PrintStatement
      *      | WhileStatement
      *      | ClassDeclaration
+     *      | ThrowStatement
+     *      | TryStatement
      *      ;
      *      ...
      */
@@ -44,6 +44,7 @@ impl<'a> Parser<'a> {
             TokenKind::BraceOpen => self.parse_block_stmt(),
             TokenKind::Semi => self.parse_empty_stmt(),
             TokenKind::ParenOpen => self.parse_expression_stmt(),
+            TokenKind::BracketOpen => self.parse_expression_stmt(),
             TokenKind::Number => self.parse_expression_stmt(),
             TokenKind::String => self.parse_expression_stmt(),
             TokenKind::Null => self.parse_expression_stmt(),
@@ -56,6 +57,8 @@ impl<'a> Parser<'a> {
             TokenKind::Keyword(Keyword::Print) => self.parse_print_stmt(),
             TokenKind::Keyword(Keyword::While) => self.parse_while_stmt(),
             TokenKind::Keyword(Keyword::Class) => self.parse_class_declaration(),
+            TokenKind::Keyword(Keyword::Throw) => self.parse_throw_stmt(),
+            TokenKind::Keyword(Keyword::Try) => self.parse_try_stmt(),
             TokenKind::Keyword(Keyword::This) => self.parse_expression_stmt(),
             TokenKind::Keyword(Keyword::Super) => self.parse_expression_stmt(),
             _ => {
@@ -110,7 +113,6 @@ impl<'a> Parser<'a> {
      *      | ";\n"
      *      ;
      */
-
     fn parse_empty_stmt(&mut self) -> ParseResult<Statement> {
         self.eat(TokenKind::Semi)?;
         Ok(Statement::Empty)
@@ -273,23 +275,14 @@ impl<'a> Parser<'a> {
      */
     fn parse_return_stmt(&mut self) -> ParseResult<Statement> {
         self.eat(TokenKind::Keyword(Keyword::Return))?;
-        if self.could_be_semi {
-            let stmt = ReturnStatement::new(None);
-            self.expect_stmt_terminator()?;
-            return Ok(Statement::Return(stmt));
+        let argument = if self.could_be_semi || self.is_stmt_end() {
+            None
         } else {
-            if !self.is_stmt_end() {
-                // self.log();
-                let argument = Some(self.parse_expression()?);
-                let stmt = ReturnStatement::new(argument);
-                self.expect_stmt_terminator()?;
-                return Ok(Statement::Return(stmt));
-            } else {
-                let stmt = ReturnStatement::new(None);
-                self.expect_stmt_terminator()?;
-                return Ok(Statement::Return(stmt));
-            };
-        }
+            Some(self.parse_expression()?)
+        };
+        let stmt = ReturnStatement::new(argument);
+        self.expect_stmt_terminator()?;
+        Ok(Statement::Return(stmt))
     }
 
     /**
@@ -311,6 +304,57 @@ impl<'a> Parser<'a> {
         Ok(Statement::While(stmt))
     }
 
+    /**
+     *  ThrowStatement
+     *      : "throw" Expression STMT_END
+     *      ;
+     */
+    fn parse_throw_stmt(&mut self) -> ParseResult<Statement> {
+        let span = Span::new(self.lexer.filename.to_string(), self.current_token.loc);
+        self.eat(TokenKind::Keyword(Keyword::Throw))?;
+        let argument = self.parse_expression()?;
+        self.expect_stmt_terminator()?;
+        Ok(Statement::Throw(ThrowStatement::new(argument, span)))
+    }
+
+    /**
+     *  TryStatement
+     *      : "try" BlockStatement Catch
+     *      | "try" BlockStatement Catch? "finally" BlockStatement
+     *      ;
+     *
+     *  Catch
+     *      : "catch" "(" Identifier ")" BlockStatement
+     *      ;
+     */
+    fn parse_try_stmt(&mut self) -> ParseResult<Statement> {
+        self.eat(TokenKind::Keyword(Keyword::Try))?;
+        let block = self.parse_block()?;
+
+        let mut handler = None;
+        if self.token_is(TokenKind::Keyword(Keyword::Catch)) {
+            self.eat(TokenKind::Keyword(Keyword::Catch))?;
+            self.eat(TokenKind::ParenOpen)?;
+            let param = self.parse_identifier()?;
+            self.eat(TokenKind::ParenClose)?;
+            handler = Some(CatchClause::new(param, self.parse_block()?));
+        }
+
+        let mut finalizer = None;
+        if handler.is_none() || self.token_is(TokenKind::Keyword(Keyword::Finally)) {
+            self.eat(TokenKind::Keyword(Keyword::Finally))?;
+            finalizer = Some(self.parse_block()?);
+        }
+        Ok(Statement::Try(TryStatement::new(block, handler, finalizer)))
+    }
+
+    fn parse_block(&mut self) -> ParseResult<Vec<Statement>> {
+        match self.parse_block_stmt()? {
+            Statement::Block(block) => Ok(block),
+            _ => unreachable!(),
+        }
+    }
+
     /**
      * 后面应该挪到builtin中
      * PrintStatement
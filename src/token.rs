@@ -39,6 +39,8 @@ pub enum TokenKind {
     BraceClose,
     ParenOpen,
     ParenClose,
+    BracketOpen,
+    BracketClose,
     Keyword(Keyword),
     Null,
 }
@@ -58,6 +60,8 @@ impl std::fmt::Display for TokenKind {
             TokenKind::BraceClose => write!(f, "BraceClose"),
             TokenKind::ParenOpen => write!(f, "ParenOpen"),
             TokenKind::ParenClose => write!(f, "ParenClose"),
+            TokenKind::BracketOpen => write!(f, "BracketOpen"),
+            TokenKind::BracketClose => write!(f, "BracketClose"),
             TokenKind::Keyword(key) => write!(f, "Keyword::{}", key),
             TokenKind::Boolean => write!(f, "Boolean"),
             TokenKind::Comma => write!(f, "Comma"),
@@ -81,6 +85,10 @@ pub enum Keyword {
     Extends,
     This,
     Super,
+    Throw,
+    Try,
+    Catch,
+    Finally,
 }
 
 impl std::fmt::Display for Keyword {
@@ -98,6 +106,10 @@ impl std::fmt::Display for Keyword {
             Extends => write!(f, "Extends"),
             This => write!(f, "This"),
             Super => write!(f, "Super"),
+            Throw => write!(f, "Throw"),
+            Try => write!(f, "Try"),
+            Catch => write!(f, "Catch"),
+            Finally => write!(f, "Finally"),
             _ => write!(f, ""),
         }
     }
@@ -118,6 +130,10 @@ impl From<&String> for Keyword {
             "extends" => Extends,
             "this" => This,
             "super" => Super,
+            "throw" => Throw,
+            "try" => Try,
+            "catch" => Catch,
+            "finally" => Finally,
             _ => Nil,
         }
     }
@@ -1,7 +1,9 @@
 use core::fmt;
-use std::collections::HashMap;
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
 
-use crate::interpreter::{class::Class, function::Function, instance::Instance};
+use crate::interpreter::{
+    class::Class, function::Function, instance::Instance, native::NativeFunction,
+};
 
 #[derive(Debug, Clone)]
 pub enum Value {
@@ -10,10 +12,11 @@ pub enum Value {
     Boolean(bool),
     Number(f64),
     Function(Function),
+    NativeFunction(NativeFunction),
     Class(Class),
     Instance(Instance),
-    Array(Vec<Value>),
-    Object(HashMap<String, Value>),
+    Array(Rc<RefCell<Vec<Value>>>),
+    Object(Rc<RefCell<HashMap<String, Value>>>),
 }
 
 impl Value {
@@ -28,61 +31,157 @@ impl Value {
             _ => true,
         }
     }
+
+    pub fn array(values: Vec<Value>) -> Value {
+        Value::Array(Rc::new(RefCell::new(values)))
+    }
+
+    pub fn object(map: HashMap<String, Value>) -> Value {
+        Value::Object(Rc::new(RefCell::new(map)))
+    }
+
+    /**
+     * compared by value, unlike functions, classes and objects
+     */
+    pub fn is_primitive(&self) -> bool {
+        matches!(
+            self,
+            Value::Null | Value::String(_) | Value::Boolean(_) | Value::Number(_)
+        )
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::String(_) => "string",
+            Value::Boolean(_) => "boolean",
+            Value::Number(_) => "number",
+            Value::Function(_) | Value::NativeFunction(_) => "function",
+            Value::Class(_) => "class",
+            Value::Instance(_) => "instance",
+            Value::Array(_) => "array",
+            Value::Object(_) => "object",
+        }
+    }
 }
 
 impl PartialEq for Value {
     fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            (Self::String(l0), Self::String(r0)) => l0 == r0,
-            (Self::Boolean(l0), Self::Boolean(r0)) => l0 == r0,
-            (Self::Number(l0), Self::Number(r0)) => l0 == r0,
-            (Self::Function(l0), Self::Function(r0)) => l0.name == r0.name,
-            (Self::Class(l0), Self::Class(r0)) => l0.id == r0.id,
-            (Self::Instance(l0), Self::Instance(r0)) => l0.class.id == r0.class.id,
-            (Self::Array(l0), Self::Array(r0)) => l0 == r0,
-            (Self::Object(l0), Self::Object(r0)) => l0 == r0,
-            _ => core::mem::discriminant(self) == core::mem::discriminant(other),
+        eq_value(self, other, &mut Vec::new())
+    }
+}
+
+// arrays and objects can contain themselves: `seen` holds the pairs being
+// compared, a pair met again is equal unless another element differs
+fn eq_value(left: &Value, right: &Value, seen: &mut Vec<(*const (), *const ())>) -> bool {
+    match (left, right) {
+        (Value::String(l0), Value::String(r0)) => l0 == r0,
+        (Value::Boolean(l0), Value::Boolean(r0)) => l0 == r0,
+        (Value::Number(l0), Value::Number(r0)) => l0 == r0,
+        (Value::Function(l0), Value::Function(r0)) => l0.name == r0.name,
+        (Value::NativeFunction(l0), Value::NativeFunction(r0)) => l0.name == r0.name,
+        (Value::Class(l0), Value::Class(r0)) => l0.id == r0.id,
+        (Value::Instance(l0), Value::Instance(r0)) => l0.class.id == r0.class.id,
+        (Value::Array(l0), Value::Array(r0)) => {
+            let pair = (Rc::as_ptr(l0) as *const (), Rc::as_ptr(r0) as *const ());
+            if Rc::ptr_eq(l0, r0) || seen.contains(&pair) {
+                return true;
+            }
+            let (l0, r0) = (l0.borrow(), r0.borrow());
+            seen.push(pair);
+            let eq =
+                l0.len() == r0.len() && l0.iter().zip(r0.iter()).all(|(l, r)| eq_value(l, r, seen));
+            seen.pop();
+            eq
         }
+        (Value::Object(l0), Value::Object(r0)) => {
+            let pair = (Rc::as_ptr(l0) as *const (), Rc::as_ptr(r0) as *const ());
+            if Rc::ptr_eq(l0, r0) || seen.contains(&pair) {
+                return true;
+            }
+            let (l0, r0) = (l0.borrow(), r0.borrow());
+            seen.push(pair);
+            let eq = l0.len() == r0.len()
+                && l0
+                    .iter()
+                    .all(|(k, l)| r0.get(k).is_some_and(|r| eq_value(l, r, seen)));
+            seen.pop();
+            eq
+        }
+        _ => core::mem::discriminant(left) == core::mem::discriminant(right),
     }
 }
 
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Value::Null => write!(f, "null"),
-            Value::String(s) => write!(f, "\"{}\"", s),
-            Value::Boolean(b) => write!(f, "{}", b),
-            Value::Number(v) => write!(f, "{}", v),
-            Value::Array(a) => fmt_array(a, f),
-            Value::Object(o) => fmt_obj(o, f),
-            Value::Function(fun) => fmt_fn(fun, f),
-            Value::Class(c) => fmt_class(c, f),
-            Value::Instance(i) => fmt_instance(i, f),
+        fmt_value(self, &mut Vec::new(), f)
+    }
+}
+
+// `seen` holds the arrays and objects being printed, one that contains
+// itself is printed as `[...]` or `{...}`
+fn fmt_value(value: &Value, seen: &mut Vec<*const ()>, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match value {
+        Value::Null => write!(f, "null"),
+        Value::String(s) => write!(f, "\"{}\"", s),
+        Value::Boolean(b) => write!(f, "{}", b),
+        Value::Number(v) => write!(f, "{}", v),
+        Value::Array(a) => {
+            let ptr = Rc::as_ptr(a) as *const ();
+            if seen.contains(&ptr) {
+                return write!(f, "[...]");
+            }
+            seen.push(ptr);
+            let result = fmt_array(&a.borrow(), seen, f);
+            seen.pop();
+            result
+        }
+        Value::Object(o) => {
+            let ptr = Rc::as_ptr(o) as *const ();
+            if seen.contains(&ptr) {
+                return write!(f, "{{...}}");
+            }
+            seen.push(ptr);
+            let result = fmt_obj(&o.borrow(), seen, f);
+            seen.pop();
+            result
         }
+        Value::Function(fun) => fmt_fn(fun, f),
+        Value::NativeFunction(fun) => write!(f, "<native fn {}>", fun.name),
+        Value::Class(c) => fmt_class(c, f),
+        Value::Instance(i) => fmt_instance(i, f),
     }
 }
 
-fn fmt_array(array: &[Value], f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    write!(
-        f,
-        "[ {} ]",
-        array
-            .iter()
-            .map(|x| x.to_string())
-            .collect::<Vec<String>>()
-            .join(", ")
-    )
+fn fmt_array(
+    array: &[Value],
+    seen: &mut Vec<*const ()>,
+    f: &mut fmt::Formatter<'_>,
+) -> fmt::Result {
+    write!(f, "[ ")?;
+    for (i, value) in array.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        fmt_value(value, seen, f)?;
+    }
+    write!(f, " ]")
 }
 
-fn fmt_obj(obj: &HashMap<String, Value>, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    write!(
-        f,
-        "{{ {} }}",
-        obj.iter()
-            .map(|(k, v)| format!("{}: {}", k, v))
-            .collect::<Vec<String>>()
-            .join(", ")
-    )
+fn fmt_obj(
+    obj: &HashMap<String, Value>,
+    seen: &mut Vec<*const ()>,
+    f: &mut fmt::Formatter<'_>,
+) -> fmt::Result {
+    write!(f, "{{ ")?;
+    for (i, (key, value)) in obj.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}: ", key)?;
+        fmt_value(value, seen, f)?;
+    }
+    write!(f, " }}")
 }
 
 fn fmt_fn(fun: &Function, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -110,7 +209,7 @@ mod tests {
         let array = vec![
             Value::String(String::from("abcd")),
             Value::Boolean(true),
-            Value::Array(vec![Value::Number(1.0), Value::Number(2.0)]),
+            Value::array(vec![Value::Number(1.0), Value::Number(2.0)]),
         ];
 
         let mut o = HashMap::new();
@@ -120,19 +219,54 @@ mod tests {
         obj.insert("a".into(), Value::Number(1.0));
         obj.insert("b".into(), Value::String("xyz".to_string()));
         obj.insert("c".into(), Value::Null);
-        obj.insert("d".into(), Value::Object(o));
+        obj.insert("d".into(), Value::object(o));
 
-        let v = vec![
+        let v = [
             Value::Null,
             Value::String(String::from("abcd")),
             Value::Boolean(true),
             Value::Number(1.2),
-            Value::Array(array),
-            Value::Object(obj),
+            Value::array(array),
+            Value::object(obj),
         ];
 
         for ele in v.iter() {
             println!("{}", ele);
         }
     }
+
+    #[test]
+    fn cyclic_values() {
+        let a = Value::array(vec![Value::Number(1.0)]);
+        if let Value::Array(inner) = &a {
+            inner.borrow_mut().push(a.clone());
+        }
+        assert_eq!(a.to_string(), "[ 1, [...] ]");
+        assert!(a == a.clone());
+
+        // two arrays containing themselves, with the same elements
+        let b = Value::array(vec![Value::Number(1.0)]);
+        if let Value::Array(inner) = &b {
+            inner.borrow_mut().push(b.clone());
+        }
+        assert!(a == b);
+
+        let c = Value::array(vec![Value::Number(2.0)]);
+        if let Value::Array(inner) = &c {
+            inner.borrow_mut().push(c.clone());
+        }
+        assert!(a != c);
+
+        let o = Value::object(HashMap::new());
+        if let Value::Object(inner) = &o {
+            inner.borrow_mut().insert("self".into(), o.clone());
+        }
+        assert_eq!(o.to_string(), "{ self: {...} }");
+        assert!(o == o.clone());
+
+        // a shared array that isn't a cycle is printed in full
+        let shared = Value::array(vec![Value::Null]);
+        let twice = Value::array(vec![shared.clone(), shared]);
+        assert_eq!(twice.to_string(), "[ [ null ], [ null ] ]");
+    }
 }
@@ -0,0 +1,218 @@
+// runs every program in tests/conformance on each backend and diffs the results
+// against the annotations in the program:
+//
+//     print 1 + 2 // expect: 3
+//     missing     // expect runtime error: missing is not defined
+//
+// printed values must match the `expect` lines in order. a runtime error must
+// happen on the line of the `expect runtime error` annotation, and its message
+// must contain the annotation text
+use std::{cell::RefCell, fs, io::Write, path::Path, rc::Rc, thread};
+
+use tinyx::{
+    analizer::resolver::Resolver,
+    bytecode::{compiler::compile, optimize::optimize, register, vm::Vm},
+    error::{CompileError, RuntimeError},
+    interpreter::{
+        config::{Limits, HOST_STACK_SIZE},
+        Interpreter,
+    },
+    lexer::Lexer,
+    parser::parser::Parser,
+};
+
+const DIR: &str = "tests/conformance";
+
+// what a program printed, and the runtime error that stopped it: (message, line)
+#[derive(Debug, Default)]
+struct Outcome {
+    output: Vec<String>,
+    error: Option<(String, usize)>,
+}
+
+// collects what a backend prints
+#[derive(Clone, Default)]
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Output {
+    fn lines(&self) -> Vec<String> {
+        let output = String::from_utf8(self.0.take()).unwrap();
+        output
+            .lines()
+            .map(|line| line.trim_start_matches(" > print: ").to_string())
+            .collect()
+    }
+}
+
+fn expectations(source: &str) -> Outcome {
+    let mut expected = Outcome::default();
+    for (i, line) in source.lines().enumerate() {
+        if let Some((_, value)) = line.split_once("// expect: ") {
+            expected.output.push(value.trim().to_string());
+        } else if let Some((_, message)) = line.split_once("// expect runtime error: ") {
+            expected.error = Some((message.trim().to_string(), i + 1));
+        }
+    }
+    expected
+}
+
+// on a thread with the stack of the cli, for deep recursion
+fn interpreter(source: &str, filename: &str) -> Result<Outcome, String> {
+    thread::scope(|scope| {
+        thread::Builder::new()
+            .stack_size(HOST_STACK_SIZE)
+            .spawn_scoped(scope, || run_interpreter(source, filename))
+            .expect("failed to spawn the interpreter thread")
+            .join()
+            .expect("the interpreter panicked")
+    })
+}
+
+fn run_interpreter(source: &str, filename: &str) -> Result<Outcome, String> {
+    let lexer = Lexer::new(source.as_bytes(), filename);
+    let ast = Parser::new(lexer).parse().map_err(|e| format!("{:?}", e))?;
+    let output = Output::default();
+    let mut interpreter = Interpreter::new()
+        .with_output(output.clone())
+        .with_limits(Limits {
+            max_stack_size: Some(HOST_STACK_SIZE / 2),
+            ..Default::default()
+        });
+    Resolver::new(&mut interpreter)
+        .resolve(&ast)
+        .map_err(|e| e.to_string())?;
+    let error = interpreter.eval(ast).err().map(|e| match &e.error {
+        RuntimeError::ReferenceError(name, span) => {
+            (format!("{} is not defined", name), span.loc.start.ln)
+        }
+        RuntimeError::SyntaxError(message, span) | RuntimeError::Error(message, span) => {
+            (message.clone(), span.loc.start.ln)
+        }
+        RuntimeError::Thrown(value, span) => {
+            (format!("uncaught exception: {}", value), span.loc.start.ln)
+        }
+        e => (e.to_string(), 0),
+    });
+    Ok(Outcome {
+        output: output.lines(),
+        error,
+    })
+}
+
+fn vm(source: &str, filename: &str, optimized: bool) -> Result<Outcome, String> {
+    let lexer = Lexer::new(source.as_bytes(), filename);
+    let ast = Parser::new(lexer).parse().map_err(|e| format!("{:?}", e))?;
+    let mut chunk = compile(&ast).map_err(|e| e.to_string())?;
+    if optimized {
+        chunk = optimize(&chunk);
+    }
+    let output = Output::default();
+    let mut vm = Vm::load(chunk)
+        .map_err(|e| e.to_string())?
+        .with_output(output.clone());
+    let error = vm.run().err().map(|e| (e.message, e.pos.0));
+    Ok(Outcome {
+        output: output.lines(),
+        error,
+    })
+}
+
+// None when the program uses what the register machine doesn't cover
+fn register_vm(source: &str, filename: &str) -> Option<Result<Outcome, String>> {
+    let lexer = Lexer::new(source.as_bytes(), filename);
+    let ast = match Parser::new(lexer).parse() {
+        Ok(ast) => ast,
+        Err(e) => return Some(Err(format!("{:?}", e))),
+    };
+    let module = match register::compiler::compile(&ast) {
+        Ok(module) => module,
+        Err(CompileError::Unsupported(..)) => return None,
+        Err(e) => return Some(Err(e.to_string())),
+    };
+    let output = Output::default();
+    let mut vm = register::vm::RegisterVm::new(module).with_output(output.clone());
+    let error = vm.run().err().map(|e| (e.message, e.pos.0));
+    Some(Ok(Outcome {
+        output: output.lines(),
+        error,
+    }))
+}
+
+// what differs from the expectations
+fn diff(expected: &Outcome, actual: &Outcome) -> Option<String> {
+    if actual.output != expected.output {
+        return Some(format!(
+            "printed {:?}, expected {:?}",
+            actual.output, expected.output
+        ));
+    }
+    let matches = match (&expected.error, &actual.error) {
+        (None, None) => true,
+        (Some((message, line)), Some((actual_message, actual_line))) => {
+            actual_message.contains(message.as_str()) && line == actual_line
+        }
+        _ => false,
+    };
+    match matches {
+        true => None,
+        false => Some(format!(
+            "runtime error {:?}, expected {:?}",
+            actual.error, expected.error
+        )),
+    }
+}
+
+#[test]
+fn conformance() {
+    let mut paths: Vec<_> = fs::read_dir(Path::new(env!("CARGO_MANIFEST_DIR")).join(DIR))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "tx"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "no programs in {}", DIR);
+
+    let mut failures = vec![];
+    let mut register_runs = 0;
+    for path in paths.iter() {
+        let source = fs::read_to_string(path).unwrap();
+        let filename = path.file_name().unwrap().to_string_lossy();
+        let expected = expectations(&source);
+        let mut outcomes = vec![
+            ("interpreter", interpreter(&source, &filename)),
+            ("vm", vm(&source, &filename, false)),
+            ("vm -O", vm(&source, &filename, true)),
+        ];
+        if let Some(outcome) = register_vm(&source, &filename) {
+            outcomes.push(("register vm", outcome));
+            register_runs += 1;
+        }
+        for (backend, outcome) in outcomes {
+            let problem = match outcome {
+                Ok(outcome) => diff(&expected, &outcome),
+                Err(e) => Some(e),
+            };
+            if let Some(problem) = problem {
+                failures.push(format!("{} on {}: {}", filename, backend, problem));
+            }
+        }
+    }
+    assert!(register_runs > 0, "no program runs on the register vm");
+    assert!(
+        failures.is_empty(),
+        "{} failures in {} programs:\n{}",
+        failures.len(),
+        paths.len(),
+        failures.join("\n")
+    );
+}